@@ -1,7 +1,9 @@
 use token::{Token};
 use lox_value::LoxValue;
+use lox_callable::Arity;
 use std::hash::{Hash, Hasher};
 use std::cmp::{Eq, PartialEq};
+use std::rc::Rc;
 #[derive(Debug, Clone)]
 pub enum Expr {
     Assign {
@@ -18,6 +20,21 @@ pub enum Expr {
         paren: Token,
         arguments: Vec<Expr>,
     },
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    IndexSet {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
     Grouping {
         expression: Box<Expr>,
         token: Token,
@@ -26,11 +43,37 @@ pub enum Expr {
         value: LoxValue,
         token: Token,
     },
+    List {
+        bracket: Token,
+        elements: Vec<Expr>,
+    },
+    /// An anonymous `fun (...) { ... }` expression. The declaration's name is
+    /// synthesized from where the `fun` keyword appears.
+    Lambda {
+        keyword: Token,
+        declaration: Rc<FunctionDeclaration>,
+    },
+    Map {
+        brace: Token,
+        entries: Vec<(Expr, Expr)>,
+    },
     Logical {
         left: Box<Expr>,
         right: Box<Expr>,
         operator: Token,
     },
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    },
+    Super {
+        keyword: Token,
+        method: Token,
+    },
+    This {
+        keyword: Token,
+    },
     Unary {
         right: Box<Expr>,
         operator: Token,
@@ -41,10 +84,20 @@ pub enum Expr {
 }
 
 impl Expr {
-    fn token(&self) -> &Token {
+    pub fn token(&self) -> &Token {
         match *self {
-            Expr::Assign { ref name, .. } | Expr::Variable { ref name, .. } => name,
+            Expr::Assign { ref name, .. }
+            | Expr::Variable { ref name, .. }
+            | Expr::Get { ref name, .. }
+            | Expr::Set { ref name, .. } => name,
+            Expr::This { ref keyword }
+            | Expr::Super { ref keyword, .. }
+            | Expr::Lambda { ref keyword, .. } => keyword,
             Expr::Call { ref paren, .. } => paren,
+            Expr::Index { ref bracket, .. }
+            | Expr::IndexSet { ref bracket, .. }
+            | Expr::List { ref bracket, .. } => bracket,
+            Expr::Map { ref brace, .. } => brace,
             Expr::Grouping { ref token, .. } | Expr::Literal { ref token, .. } => token,
             Expr::Binary { ref operator, .. }
             | Expr::Logical { ref operator, .. }
@@ -72,150 +125,435 @@ impl Hash for Expr {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: Token,
+    /// Evaluated when a call leaves the parameter out, in the callee's
+    /// scope after the parameters before it are bound.
+    pub default: Option<Expr>,
+}
+
 #[derive(Debug, Clone)]
 pub struct FunctionDeclaration {
     pub name: Token,
-    pub parameters: Vec<Token>,
+    pub parameters: Vec<Parameter>,
+    /// `...name` at the end of the parameters, which collects the arguments
+    /// past them into a list.
+    pub rest: Option<Token>,
+    pub body: Vec<Statement>,
+}
+
+impl FunctionDeclaration {
+    pub fn arity(&self) -> Arity {
+        Arity {
+            min: self.parameters.iter().filter(|p| p.default.is_none()).count(),
+            max: self.parameters.len(),
+            variadic: self.rest.is_some(),
+        }
+    }
+}
+
+/// `catch (name) { body }`. The name and the body's own declarations share
+/// a scope.
+#[derive(Debug, Clone)]
+pub struct Catch {
+    pub name: Token,
     pub body: Vec<Statement>,
 }
 
+/// The first and last tokens of a statement, counting the keywords, `;`
+/// and braces that the tree doesn't otherwise keep.
+#[derive(Debug, Clone)]
+pub struct Bounds {
+    pub first: Token,
+    pub last: Token,
+}
+
 #[derive(Debug, Clone)]
 pub enum Statement {
     Block {
         statements: Vec<Statement>,
+        bounds: Bounds,
+    },
+    Break {
+        keyword: Token,
+        bounds: Bounds,
+    },
+    Class {
+        name: Token,
+        superclass: Option<Box<Expr>>,
+        methods: Vec<FunctionDeclaration>,
+        bounds: Bounds,
+    },
+    Continue {
+        keyword: Token,
+        bounds: Bounds,
     },
     Expression {
         expression: Box<Expr>,
+        bounds: Bounds,
+    },
+    /// Kept as written rather than desugared into a `While` so that tools can
+    /// print it back. The initializer is scoped to the loop.
+    For {
+        keyword: Token,
+        initializer: Option<Box<Statement>>,
+        condition: Option<Box<Expr>>,
+        increment: Option<Box<Expr>>,
+        body: Box<Statement>,
+        bounds: Bounds,
+    },
+    Function {
+        declaration: FunctionDeclaration,
+        bounds: Bounds,
     },
-    Function(FunctionDeclaration),
     If {
         condition: Box<Expr>,
         then_branch: Box<Statement>,
         else_branch: Option<Box<Statement>>,
+        bounds: Bounds,
     },
     Print {
         expression: Box<Expr>,
+        bounds: Bounds,
     },
     Return {
         keyword: Token,
         value: Option<Box<Expr>>,
+        bounds: Bounds,
+    },
+    Throw {
+        keyword: Token,
+        value: Box<Expr>,
+        bounds: Bounds,
+    },
+    /// Has a `catch`, a `finally`, or both.
+    Try {
+        keyword: Token,
+        body: Vec<Statement>,
+        catch: Option<Catch>,
+        finally: Option<Vec<Statement>>,
+        bounds: Bounds,
     },
     While {
         condition: Box<Expr>,
         body: Box<Statement>,
+        bounds: Bounds,
     },
     Var {
         name: Token,
         initializer: Option<Box<Expr>>,
+        bounds: Bounds,
     },
 }
 
+impl Statement {
+    /// A token to point at when reporting on the statement, or `None` for an
+    /// empty block.
+    pub fn token(&self) -> Option<&Token> {
+        match *self {
+            Statement::Expression { ref expression, .. } | Statement::Print { ref expression, .. } => {
+                Some(expression.token())
+            }
+            Statement::If { ref condition, .. } | Statement::While { ref condition, .. } => {
+                Some(condition.token())
+            }
+            Statement::Class { ref name, .. } | Statement::Var { ref name, .. } => Some(name),
+            Statement::Function { ref declaration, .. } => Some(&declaration.name),
+            Statement::For { ref keyword, .. }
+            | Statement::Return { ref keyword, .. }
+            | Statement::Throw { ref keyword, .. }
+            | Statement::Try { ref keyword, .. }
+            | Statement::Break { ref keyword, .. }
+            | Statement::Continue { ref keyword, .. } => Some(keyword),
+            Statement::Block { ref statements, .. } => statements.first().and_then(|s| s.token()),
+        }
+    }
+
+    pub fn bounds(&self) -> &Bounds {
+        match *self {
+            Statement::Block { ref bounds, .. }
+            | Statement::Break { ref bounds, .. }
+            | Statement::Class { ref bounds, .. }
+            | Statement::Continue { ref bounds, .. }
+            | Statement::Expression { ref bounds, .. }
+            | Statement::For { ref bounds, .. }
+            | Statement::Function { ref bounds, .. }
+            | Statement::If { ref bounds, .. }
+            | Statement::Print { ref bounds, .. }
+            | Statement::Return { ref bounds, .. }
+            | Statement::Throw { ref bounds, .. }
+            | Statement::Try { ref bounds, .. }
+            | Statement::While { ref bounds, .. }
+            | Statement::Var { ref bounds, .. } => bounds,
+        }
+    }
+}
+
 pub struct Program {
     pub statements: Vec<Statement>,
 }
 
-// pub trait AstPrint {
-//     fn pretty_print(&self) -> String;
-// }
-
-// impl AstPrint for Program {
-//     fn pretty_print(&self) -> String {
-//         let inner_str: Vec<String> = self.statements.iter().map(|s| s.pretty_print()).collect();
-//         format!("({} {})", "program", inner_str.join(" "))
-//     }
-// }
-
-// impl AstPrint for Statement {
-//     fn pretty_print(&self) -> String {
-//         match *self {
-//             Statement::Expression { ref expression } => {
-//                 format!("({} {})", "expr", expression.pretty_print())
-//             }
-
-//             Statement::Print { ref expression } => {
-//                 format!("({} {})", "print", expression.pretty_print())
-//             }
-
-//             Statement::Block { ref statements } => {
-//                 let inner_str: Vec<String> = statements.iter().map(|s| s.pretty_print()).collect();
-//                 format!("({} {})", "block", inner_str.join(" "))
-//             }
-
-//             Statement::If {
-//                 ref condition,
-//                 ref then_branch,
-//                 ref else_branch,
-//             } => {
-//                 let else_str = match *else_branch {
-//                     Some(ref e) => e.pretty_print(),
-//                     None => "".to_string(),
-//                 };
-
-//                 format!(
-//                     "({} {} {} {})",
-//                     "if",
-//                     condition.pretty_print(),
-//                     then_branch.pretty_print(),
-//                     else_str
-//                 )
-//             }
-
-//             Statement::While {
-//                 ref condition,
-//                 ref body,
-//             } => format!(
-//                 "({} {} {})",
-//                 "while",
-//                 condition.pretty_print(),
-//                 body.pretty_print()
-//             ),
-
-//             Statement::Var {
-//                 ref name,
-//                 ref initializer,
-//             } => {
-//                 let var = match *initializer {
-//                     Some(ref v) => format!("{}", v.pretty_print()),
-//                     None => "nil".to_string(),
-//                 };
-//                 format!("({} {} {})", "def_var", name.lexeme, var)
-//             }
-//         }
-//     }
-// }
-
-// impl AstPrint for Expr {
-//     fn pretty_print(&self) -> String {
-//         match *self {
-//             Expr::Assign {
-//                 ref name,
-//                 ref value,
-//             } => format!("({} {} {})", "set_var", name.lexeme, value.pretty_print()),
-//             Expr::Binary {
-//                 operator: ref o,
-//                 left: ref l,
-//                 right: ref r,
-//             }
-//             | Expr::Logical {
-//                 operator: ref o,
-//                 left: ref l,
-//                 right: ref r,
-//             } => format!("({} {} {})", o.lexeme, l.pretty_print(), r.pretty_print()),
-//             Expr::Unary {
-//                 operator: ref o,
-//                 right: ref r,
-//             } => format!("({} {})", o.lexeme, r.pretty_print()),
-//             Expr::Call {
-//                 ref callee,
-//                 ref paren,
-//                 ref arguments,
-//             }=> {
-//                 let args: Vec<String> = arguments.iter().map(|s| s.pretty_print()).collect();
-//                 format!("({} {} {})", "call", callee.pretty_print(), args.join(" "))
-//             }
-//             Expr::Grouping { expression: ref r } => format!("({} {})", "group", r.pretty_print()),
-//             Expr::Literal { value: ref v } => format!("{:?}", v),
-//             Expr::Variable { name: ref n } => format!("{}", n.lexeme),
-//         }
-//     }
-// }
+/// Renders the tree as S-expressions, one top-level statement per line. The
+/// output is stable enough to compare against in tests.
+pub trait AstPrint {
+    fn pretty_print(&self) -> String;
+}
+
+fn list<T: AstPrint>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|i| format!(" {}", i.pretty_print()))
+        .collect()
+}
+
+/// A `for` clause that may be left empty.
+fn optional(expr: &Option<Box<Expr>>) -> String {
+    match *expr {
+        Some(ref e) => e.pretty_print(),
+        None => "()".to_string(),
+    }
+}
+
+fn parameters(declaration: &FunctionDeclaration) -> String {
+    let mut names: Vec<String> = declaration
+        .parameters
+        .iter()
+        .map(|p| match p.default {
+            Some(ref default) => format!("(= {} {})", p.name.lexeme, default.pretty_print()),
+            None => p.name.lexeme.clone(),
+        })
+        .collect();
+    if let Some(ref rest) = declaration.rest {
+        names.push(format!("...{}", rest.lexeme));
+    }
+    format!("({})", names.join(" "))
+}
+
+impl AstPrint for Program {
+    fn pretty_print(&self) -> String {
+        let inner_str: Vec<String> = self.statements
+            .iter()
+            .map(|s| format!("\n  {}", s.pretty_print()))
+            .collect();
+        format!("({}{})", "program", inner_str.concat())
+    }
+}
+
+impl AstPrint for FunctionDeclaration {
+    fn pretty_print(&self) -> String {
+        format!(
+            "({} {} {}{})",
+            "fun",
+            self.name.lexeme,
+            parameters(self),
+            list(&self.body)
+        )
+    }
+}
+
+impl AstPrint for Statement {
+    fn pretty_print(&self) -> String {
+        match *self {
+            Statement::Expression { ref expression, .. } => {
+                format!("({} {})", "expr", expression.pretty_print())
+            }
+
+            Statement::Print { ref expression, .. } => {
+                format!("({} {})", "print", expression.pretty_print())
+            }
+
+            Statement::Block { ref statements, .. } => format!("({}{})", "block", list(statements)),
+
+            Statement::Break { .. } => "(break)".to_string(),
+
+            Statement::Continue { .. } => "(continue)".to_string(),
+
+            Statement::Class {
+                ref name,
+                ref superclass,
+                ref methods,
+                ..
+            } => {
+                let superclass = match *superclass {
+                    Some(ref s) => format!(" (< {})", s.pretty_print()),
+                    None => "".to_string(),
+                };
+                format!("({} {}{}{})", "class", name.lexeme, superclass, list(methods))
+            }
+
+            Statement::Function { ref declaration, .. } => declaration.pretty_print(),
+
+            Statement::If {
+                ref condition,
+                ref then_branch,
+                ref else_branch,
+                ..
+            } => {
+                let else_str = match *else_branch {
+                    Some(ref e) => format!(" {}", e.pretty_print()),
+                    None => "".to_string(),
+                };
+
+                format!(
+                    "({} {} {}{})",
+                    "if",
+                    condition.pretty_print(),
+                    then_branch.pretty_print(),
+                    else_str
+                )
+            }
+
+            Statement::Return { ref value, .. } => match *value {
+                Some(ref v) => format!("({} {})", "return", v.pretty_print()),
+                None => "(return)".to_string(),
+            },
+
+            Statement::For {
+                ref initializer,
+                ref condition,
+                ref increment,
+                ref body,
+                ..
+            } => {
+                let initializer = match *initializer {
+                    Some(ref i) => i.pretty_print(),
+                    None => "()".to_string(),
+                };
+                format!(
+                    "({} {} {} {} {})",
+                    "for",
+                    initializer,
+                    optional(condition),
+                    optional(increment),
+                    body.pretty_print()
+                )
+            }
+
+            Statement::Throw { ref value, .. } => format!("(throw {})", value.pretty_print()),
+
+            Statement::Try {
+                ref body,
+                ref catch,
+                ref finally,
+                ..
+            } => {
+                let catch = match *catch {
+                    Some(ref c) => format!(" (catch {}{})", c.name.lexeme, list(&c.body)),
+                    None => "".to_string(),
+                };
+                let finally = match *finally {
+                    Some(ref f) => format!(" (finally{})", list(f)),
+                    None => "".to_string(),
+                };
+                format!("(try (block{}){}{})", list(body), catch, finally)
+            }
+
+            Statement::While {
+                ref condition,
+                ref body,
+                ..
+            } => format!(
+                "({} {} {})",
+                "while",
+                condition.pretty_print(),
+                body.pretty_print()
+            ),
+
+            Statement::Var {
+                ref name,
+                ref initializer,
+                ..
+            } => {
+                let var = match *initializer {
+                    Some(ref v) => v.pretty_print(),
+                    None => "nil".to_string(),
+                };
+                format!("({} {} {})", "def_var", name.lexeme, var)
+            }
+        }
+    }
+}
+
+impl AstPrint for Expr {
+    fn pretty_print(&self) -> String {
+        match *self {
+            Expr::Assign {
+                ref name,
+                ref value,
+            } => format!("({} {} {})", "set_var", name.lexeme, value.pretty_print()),
+            Expr::Binary {
+                operator: ref o,
+                left: ref l,
+                right: ref r,
+            }
+            | Expr::Logical {
+                operator: ref o,
+                left: ref l,
+                right: ref r,
+            } => format!("({} {} {})", o.lexeme, l.pretty_print(), r.pretty_print()),
+            Expr::Unary {
+                operator: ref o,
+                right: ref r,
+            } => format!("({} {})", o.lexeme, r.pretty_print()),
+            Expr::Call {
+                ref callee,
+                ref arguments,
+                ..
+            } => format!("({} {}{})", "call", callee.pretty_print(), list(arguments)),
+            Expr::Get {
+                ref object,
+                ref name,
+            } => format!("({} {} {})", "get", object.pretty_print(), name.lexeme),
+            Expr::Set {
+                ref object,
+                ref name,
+                ref value,
+            } => format!(
+                "({} {} {} {})",
+                "set",
+                object.pretty_print(),
+                name.lexeme,
+                value.pretty_print()
+            ),
+            Expr::Index {
+                ref object,
+                ref index,
+                ..
+            } => format!("({} {} {})", "index", object.pretty_print(), index.pretty_print()),
+            Expr::IndexSet {
+                ref object,
+                ref index,
+                ref value,
+                ..
+            } => format!(
+                "({} {} {} {})",
+                "set_index",
+                object.pretty_print(),
+                index.pretty_print(),
+                value.pretty_print()
+            ),
+            Expr::Grouping { ref expression, .. } => {
+                format!("({} {})", "group", expression.pretty_print())
+            }
+            Expr::Literal { ref value, .. } => match *value {
+                LoxValue::String(ref s) => format!("{:?}", s),
+                ref v => v.to_string(),
+            },
+            Expr::List { ref elements, .. } => format!("({}{})", "list", list(elements)),
+            Expr::Map { ref entries, .. } => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(k, v)| format!(" ({} {})", k.pretty_print(), v.pretty_print()))
+                    .collect();
+                format!("({}{})", "map", entries.concat())
+            }
+            Expr::Lambda {
+                ref declaration, ..
+            } => declaration.pretty_print(),
+            Expr::Super { ref method, .. } => format!("({} {})", "super", method.lexeme),
+            Expr::This { .. } => "this".to_string(),
+            Expr::Variable { ref name } => name.lexeme.clone(),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use lox_callable::Arity;
+use std::rc::Rc;
+
+/// A single VM instruction. Operands are indexes into the chunk's constant
+/// pool, stack slots, upvalue slots, or relative jump distances, and are kept
+/// small so that an instruction fits in four bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpCode {
+    Constant(u16),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u8),
+    SetLocal(u8),
+    GetGlobal(u16),
+    DefineGlobal(u16),
+    SetGlobal(u16),
+    GetUpvalue(u8),
+    SetUpvalue(u8),
+    GetProperty(u16),
+    SetProperty(u16),
+    GetIndex,
+    SetIndex,
+    GetSuper(u16),
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump(u16),
+    JumpIfFalse(u16),
+    /// Jumps if the call passed an argument for the parameter at that
+    /// index, skipping the code that computes its default.
+    JumpIfPassed(u8, u16),
+    Loop(u16),
+    Call(u8),
+    Closure(u16),
+    CloseUpvalue,
+    Return,
+    Class(u16),
+    Inherit,
+    Method(u16),
+    /// Installs a handler that a runtime error or `throw` unwinds to, at
+    /// that distance ahead, with the exception pushed for a `catch`.
+    Try(u16),
+    /// Like `Try`, but for a `finally` block, which gets no value and ends
+    /// in `Rethrow`.
+    TryFinally(u16),
+    /// Removes the innermost handler.
+    EndTry,
+    /// Pops a value and raises it as an exception.
+    Throw,
+    /// Raises again the exception a `finally` block was unwinding for.
+    Rethrow,
+    /// Forgets that exception instead, when the block leaves by `return`,
+    /// `break` or `continue`.
+    DropException,
+    /// Pops that many values and pushes a list holding them in order.
+    BuildList(u16),
+    /// Pops that many key/value pairs and pushes a map holding them.
+    BuildMap(u16),
+}
+
+#[derive(Debug, Clone)]
+pub enum Constant {
+    Number(f64),
+    String(Rc<str>),
+    Function(Rc<FunctionProto>),
+}
+
+/// The source location an instruction was compiled from.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub position: usize,
+    pub length: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub constants: Vec<Constant>,
+    /// Run-length encoded: each entry is the offset of the first instruction
+    /// compiled from a span, so consecutive instructions share one entry.
+    lines: Vec<(usize, Span)>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, op: OpCode, span: Span) -> usize {
+        let offset = self.code.len();
+        self.code.push(op);
+        match self.lines.last() {
+            Some(&(_, last)) if last == span => (),
+            _ => self.lines.push((offset, span)),
+        }
+        offset
+    }
+
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        self.constants.push(constant);
+        self.constants.len() - 1
+    }
+
+    pub fn span_at(&self, offset: usize) -> Span {
+        let idx = match self.lines.binary_search_by_key(&offset, |&(start, _)| start) {
+            Ok(idx) => idx,
+            Err(idx) => idx.saturating_sub(1),
+        };
+        self.lines.get(idx).map(|&(_, span)| span).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpvalueDescriptor {
+    /// Whether the upvalue captures a local of the directly enclosing
+    /// function, or one of that function's own upvalues.
+    pub is_local: bool,
+    pub index: u8,
+}
+
+/// The compiled, immutable half of a function. Closures pair one of these with
+/// the upvalues they captured.
+#[derive(Debug, Default)]
+pub struct FunctionProto {
+    pub name: String,
+    pub arity: Arity,
+    pub upvalues: Vec<UpvalueDescriptor>,
+    pub chunk: Chunk,
+}
+
+#[cfg(test)]
+mod tests {
+    use chunk::{Chunk, OpCode, Span};
+
+    #[test]
+    fn line_table_shares_runs_between_instructions() {
+        let first = Span {
+            line: 1,
+            position: 0,
+            length: 5,
+        };
+        let second = Span {
+            line: 2,
+            position: 8,
+            length: 1,
+        };
+        let mut chunk = Chunk::default();
+        chunk.write(OpCode::Nil, first);
+        chunk.write(OpCode::Print, first);
+        chunk.write(OpCode::True, second);
+        chunk.write(OpCode::Pop, second);
+
+        assert_eq!(chunk.lines.len(), 2);
+        assert_eq!(chunk.span_at(1), first);
+        assert_eq!(chunk.span_at(3), second);
+    }
+}
@@ -0,0 +1,977 @@
+use ast::{Catch, Expr, FunctionDeclaration, Program, Statement};
+use chunk::{Constant, FunctionProto, OpCode, Span, UpvalueDescriptor};
+use diagnostic::Diagnostic;
+use lox_value::LoxValue;
+use token::{Token, TokenType};
+use std::fmt;
+use std::rc::Rc;
+
+const MAX_LOCALS: usize = 256;
+const MAX_UPVALUES: usize = 256;
+
+#[derive(Debug)]
+pub struct Error {
+    token: Token,
+    message: String,
+}
+
+impl Error {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new("compile error", &self.token, &self.message)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Compile error at '{}': {}",
+            self.token.line, self.token.lexeme, self.message
+        )
+    }
+}
+
+type CompileResult<T> = Result<T, Error>;
+
+struct Local {
+    name: String,
+    /// `None` while the local's initializer is being compiled.
+    depth: Option<usize>,
+    is_captured: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+/// The jumps out of a loop body that can't be patched until the rest of the
+/// loop has been compiled.
+struct LoopState {
+    scope_depth: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// Part of a `try` statement, which a `break`, `continue` or `return` has
+/// to clean up after on its way out.
+struct TryState {
+    /// How many loops enclose the statement.
+    loops: usize,
+    /// Set in the `finally` block run for an exception, whose exception is
+    /// dropped. Everywhere else a handler is installed, to be removed before
+    /// running the `finally` block, if there is one.
+    unwinding: bool,
+    finally: Option<Vec<Statement>>,
+}
+
+struct FunctionState {
+    proto: FunctionProto,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    loops: Vec<LoopState>,
+    tries: Vec<TryState>,
+}
+
+/// Lowers a resolved program into bytecode. Like the resolver, top-level
+/// declarations become globals and everything else becomes a stack slot,
+/// with locals that outlive their frame captured as upvalues.
+pub struct Compiler {
+    functions: Vec<FunctionState>,
+}
+
+fn span(token: &Token) -> Span {
+    Span {
+        line: token.line,
+        position: token.position,
+        length: token.lexeme.chars().count(),
+    }
+}
+
+impl Compiler {
+    pub fn compile(program: &Program) -> CompileResult<Rc<FunctionProto>> {
+        let mut compiler = Compiler { functions: vec![] };
+        compiler.begin_function("script", FunctionKind::Script);
+        for s in &program.statements {
+            compiler.statement(s)?;
+        }
+        let end = program
+            .statements
+            .last()
+            .map(|s| compiler.statement_span(s))
+            .unwrap_or_default();
+        Ok(Rc::new(compiler.end_function(end)))
+    }
+
+    fn statement(&mut self, s: &Statement) -> CompileResult<()> {
+        match *s {
+            Statement::Block { ref statements, .. } => {
+                self.begin_scope();
+                for s in statements {
+                    self.statement(s)?;
+                }
+                self.end_scope(self.statement_span(s));
+            }
+
+            Statement::Class {
+                ref name,
+                ref superclass,
+                ref methods,
+                ..
+            } => self.class_declaration(name, superclass, methods)?,
+
+            Statement::Expression { ref expression, .. } => {
+                self.expression(expression)?;
+                self.emit(OpCode::Pop, expression.token());
+            }
+
+            Statement::Function { ref declaration, .. } => {
+                let name = &declaration.name;
+                self.declare_variable(name)?;
+                self.mark_initialized();
+                self.function(declaration, FunctionKind::Function)?;
+                self.define_variable(name)?;
+            }
+
+            Statement::If {
+                ref condition,
+                ref then_branch,
+                ref else_branch,
+                ..
+            } => {
+                self.expression(condition)?;
+                let token = condition.token();
+                let then_jump = self.emit(OpCode::JumpIfFalse(0), token);
+                self.emit(OpCode::Pop, token);
+                self.statement(then_branch)?;
+                let else_jump = self.emit(OpCode::Jump(0), token);
+                self.patch_jump(then_jump, token)?;
+                self.emit(OpCode::Pop, token);
+                if let Some(ref else_branch) = *else_branch {
+                    self.statement(else_branch)?;
+                }
+                self.patch_jump(else_jump, token)?;
+            }
+
+            Statement::Print { ref expression, .. } => {
+                self.expression(expression)?;
+                self.emit(OpCode::Print, expression.token());
+            }
+
+            Statement::Return {
+                ref keyword,
+                ref value,
+                ..
+            } => {
+                if self.current().kind == FunctionKind::Initializer {
+                    self.emit(OpCode::GetLocal(0), keyword);
+                } else if let Some(ref value) = *value {
+                    self.expression(value)?;
+                } else {
+                    self.emit(OpCode::Nil, keyword);
+                }
+                if !self.current().tries.is_empty() {
+                    // the value keeps a slot of its own while finally blocks run
+                    self.begin_scope();
+                    self.add_local("", keyword)?;
+                    self.mark_initialized();
+                    self.leave_tries(true, keyword)?;
+                    self.current_mut().locals.pop();
+                    self.current_mut().scope_depth -= 1;
+                }
+                self.emit(OpCode::Return, keyword);
+            }
+
+            Statement::For {
+                ref keyword,
+                ref initializer,
+                ref condition,
+                ref increment,
+                ref body,
+                ..
+            } => {
+                self.begin_scope();
+                if let Some(ref i) = *initializer {
+                    self.statement(i)?;
+                }
+                self.loop_statement(condition.as_deref(), body, increment.as_deref(), keyword)?;
+                self.end_scope(span(keyword));
+            }
+
+            Statement::While {
+                ref condition,
+                ref body,
+                ..
+            } => self.loop_statement(Some(condition), body, None, condition.token())?,
+
+            Statement::Break { ref keyword, .. } => {
+                self.leave_tries(false, keyword)?;
+                self.discard_loop_locals(keyword);
+                let jump = self.emit(OpCode::Jump(0), keyword);
+                self.current_mut().loops.last_mut().unwrap().breaks.push(jump);
+            }
+
+            Statement::Continue { ref keyword, .. } => {
+                self.leave_tries(false, keyword)?;
+                self.discard_loop_locals(keyword);
+                let jump = self.emit(OpCode::Jump(0), keyword);
+                self.current_mut().loops.last_mut().unwrap().continues.push(jump);
+            }
+
+            Statement::Throw {
+                ref keyword,
+                ref value,
+                ..
+            } => {
+                self.expression(value)?;
+                self.emit(OpCode::Throw, keyword);
+            }
+
+            Statement::Try {
+                ref keyword,
+                ref body,
+                ref catch,
+                ref finally,
+                ..
+            } => self.try_statement(keyword, body, catch.as_ref(), finally.as_ref())?,
+
+            Statement::Var {
+                ref name,
+                ref initializer,
+                ..
+            } => {
+                self.declare_variable(name)?;
+                match *initializer {
+                    Some(ref e) => self.expression(e)?,
+                    None => {
+                        self.emit(OpCode::Nil, name);
+                    }
+                }
+                self.mark_initialized();
+                self.define_variable(name)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn class_declaration(
+        &mut self,
+        name: &Token,
+        superclass: &Option<Box<Expr>>,
+        methods: &[FunctionDeclaration],
+    ) -> CompileResult<()> {
+        let name_constant = self.identifier_constant(name)?;
+        self.declare_variable(name)?;
+        self.emit(OpCode::Class(name_constant), name);
+        self.mark_initialized();
+        self.define_variable(name)?;
+
+        // the superclass stays on the stack as a local named `super` for as
+        // long as the methods are being compiled, so they can capture it
+        if let Some(ref superclass) = *superclass {
+            self.expression(superclass)?;
+            self.begin_scope();
+            self.add_local("super", superclass.token())?;
+            self.mark_initialized();
+            self.named_variable(name, false)?;
+            self.emit(OpCode::Inherit, superclass.token());
+        }
+
+        self.named_variable(name, false)?;
+        for method in methods {
+            let kind = if method.name.lexeme == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.function(method, kind)?;
+            let method_constant = self.identifier_constant(&method.name)?;
+            self.emit(OpCode::Method(method_constant), &method.name);
+        }
+        self.emit(OpCode::Pop, name);
+
+        if superclass.is_some() {
+            self.end_scope(span(name));
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, declaration: &FunctionDeclaration, kind: FunctionKind) -> CompileResult<()> {
+        let name = &declaration.name;
+        self.begin_function(&name.lexeme, kind);
+        self.begin_scope();
+        self.current_mut().proto.arity = declaration.arity();
+        // every parameter has a slot by the time the body runs, so a default
+        // is computed into the slot of the one it belongs to
+        for (i, p) in declaration.parameters.iter().enumerate() {
+            if let Some(ref default) = p.default {
+                let jump = self.emit(OpCode::JumpIfPassed(i as u8, 0), &p.name);
+                self.expression(default)?;
+                self.emit(OpCode::SetLocal(i as u8 + 1), &p.name);
+                self.emit(OpCode::Pop, &p.name);
+                self.patch_jump(jump, &p.name)?;
+            }
+            self.declare_variable(&p.name)?;
+            self.mark_initialized();
+        }
+        if let Some(ref rest) = declaration.rest {
+            self.declare_variable(rest)?;
+            self.mark_initialized();
+        }
+        for s in &declaration.body {
+            self.statement(s)?;
+        }
+
+        let proto = self.end_function(span(name));
+        let constant = self.make_constant(Constant::Function(Rc::new(proto)), name)?;
+        self.emit(OpCode::Closure(constant), name);
+        Ok(())
+    }
+
+    fn expression(&mut self, e: &Expr) -> CompileResult<()> {
+        match *e {
+            Expr::Assign {
+                ref name,
+                ref value,
+            } => {
+                self.expression(value)?;
+                self.named_variable(name, true)?;
+            }
+
+            Expr::Binary {
+                ref left,
+                ref right,
+                ref operator,
+            } => {
+                self.expression(left)?;
+                self.expression(right)?;
+                match operator.token_type {
+                    TokenType::Plus => self.emit(OpCode::Add, operator),
+                    TokenType::Minus => self.emit(OpCode::Subtract, operator),
+                    TokenType::Star => self.emit(OpCode::Multiply, operator),
+                    TokenType::Slash => self.emit(OpCode::Divide, operator),
+                    TokenType::EqualEqual => self.emit(OpCode::Equal, operator),
+                    TokenType::BangEqual => {
+                        self.emit(OpCode::Equal, operator);
+                        self.emit(OpCode::Not, operator)
+                    }
+                    TokenType::Greater => self.emit(OpCode::Greater, operator),
+                    // `a >= b` can't be compiled as `!(a < b)` because
+                    // comparisons with NaN are false both ways
+                    TokenType::GreaterEqual => self.emit(OpCode::GreaterEqual, operator),
+                    TokenType::Less => self.emit(OpCode::Less, operator),
+                    TokenType::LessEqual => self.emit(OpCode::LessEqual, operator),
+                    _ => return Err(self.error(operator, "Unknown binary operator.")),
+                };
+            }
+
+            Expr::Call {
+                ref callee,
+                ref paren,
+                ref arguments,
+            } => {
+                self.expression(callee)?;
+                for a in arguments {
+                    self.expression(a)?;
+                }
+                if arguments.len() > u8::MAX as usize {
+                    return Err(self.error(paren, "Cannot have more than 255 arguments."));
+                }
+                self.emit(OpCode::Call(arguments.len() as u8), paren);
+            }
+
+            Expr::Get {
+                ref object,
+                ref name,
+            } => {
+                self.expression(object)?;
+                let constant = self.identifier_constant(name)?;
+                self.emit(OpCode::GetProperty(constant), name);
+            }
+
+            Expr::Grouping { ref expression, .. } => self.expression(expression)?,
+
+            Expr::Index {
+                ref object,
+                ref bracket,
+                ref index,
+            } => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.emit(OpCode::GetIndex, bracket);
+            }
+
+            Expr::IndexSet {
+                ref object,
+                ref bracket,
+                ref index,
+                ref value,
+            } => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.expression(value)?;
+                self.emit(OpCode::SetIndex, bracket);
+            }
+
+            Expr::List {
+                ref bracket,
+                ref elements,
+            } => {
+                for e in elements {
+                    self.expression(e)?;
+                }
+                if elements.len() > u16::MAX as usize {
+                    return Err(self.error(bracket, "Too many elements in list literal."));
+                }
+                self.emit(OpCode::BuildList(elements.len() as u16), bracket);
+            }
+
+            Expr::Lambda {
+                ref declaration,
+                ..
+            } => self.function(declaration, FunctionKind::Function)?,
+
+            Expr::Map {
+                ref brace,
+                ref entries,
+            } => {
+                for (key, value) in entries {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+                if entries.len() > u16::MAX as usize {
+                    return Err(self.error(brace, "Too many entries in map literal."));
+                }
+                self.emit(OpCode::BuildMap(entries.len() as u16), brace);
+            }
+
+            Expr::Literal {
+                ref value,
+                ref token,
+            } => {
+                match *value {
+                    LoxValue::Nil => self.emit(OpCode::Nil, token),
+                    LoxValue::Bool(true) => self.emit(OpCode::True, token),
+                    LoxValue::Bool(false) => self.emit(OpCode::False, token),
+                    LoxValue::Number(n) => {
+                        let constant = self.make_constant(Constant::Number(n), token)?;
+                        self.emit(OpCode::Constant(constant), token)
+                    }
+                    LoxValue::String(ref s) => {
+                        let constant = self.make_constant(Constant::String(Rc::from(s.as_str())), token)?;
+                        self.emit(OpCode::Constant(constant), token)
+                    }
+                    _ => return Err(self.error(token, "Unsupported literal.")),
+                };
+            }
+
+            Expr::Logical {
+                ref left,
+                ref right,
+                ref operator,
+            } => {
+                self.expression(left)?;
+                if operator.token_type == TokenType::Or {
+                    let else_jump = self.emit(OpCode::JumpIfFalse(0), operator);
+                    let end_jump = self.emit(OpCode::Jump(0), operator);
+                    self.patch_jump(else_jump, operator)?;
+                    self.emit(OpCode::Pop, operator);
+                    self.expression(right)?;
+                    self.patch_jump(end_jump, operator)?;
+                } else {
+                    let end_jump = self.emit(OpCode::JumpIfFalse(0), operator);
+                    self.emit(OpCode::Pop, operator);
+                    self.expression(right)?;
+                    self.patch_jump(end_jump, operator)?;
+                }
+            }
+
+            Expr::Set {
+                ref object,
+                ref name,
+                ref value,
+            } => {
+                self.expression(object)?;
+                self.expression(value)?;
+                let constant = self.identifier_constant(name)?;
+                self.emit(OpCode::SetProperty(constant), name);
+            }
+
+            Expr::Super {
+                ref keyword,
+                ref method,
+            } => {
+                let this = Token {
+                    lexeme: "this".to_string(),
+                    ..keyword.clone()
+                };
+                self.named_variable(&this, false)?;
+                self.named_variable(keyword, false)?;
+                let constant = self.identifier_constant(method)?;
+                self.emit(OpCode::GetSuper(constant), method);
+            }
+
+            Expr::This { ref keyword } => self.named_variable(keyword, false)?,
+
+            Expr::Unary {
+                ref right,
+                ref operator,
+            } => {
+                self.expression(right)?;
+                match operator.token_type {
+                    TokenType::Bang => self.emit(OpCode::Not, operator),
+                    TokenType::Minus => self.emit(OpCode::Negate, operator),
+                    _ => return Err(self.error(operator, "Unknown unary operator.")),
+                };
+            }
+
+            Expr::Variable { ref name } => self.named_variable(name, false)?,
+        }
+        Ok(())
+    }
+
+    /// Emits a read of `name`, or a write of the value on top of the stack
+    /// when `assign` is set.
+    fn named_variable(&mut self, name: &Token, assign: bool) -> CompileResult<()> {
+        let top = self.functions.len() - 1;
+        let op = if let Some(slot) = self.resolve_local(top, name) {
+            if assign {
+                OpCode::SetLocal(slot)
+            } else {
+                OpCode::GetLocal(slot)
+            }
+        } else if let Some(idx) = self.resolve_upvalue(top, name)? {
+            if assign {
+                OpCode::SetUpvalue(idx)
+            } else {
+                OpCode::GetUpvalue(idx)
+            }
+        } else {
+            let constant = self.identifier_constant(name)?;
+            if assign {
+                OpCode::SetGlobal(constant)
+            } else {
+                OpCode::GetGlobal(constant)
+            }
+        };
+        self.emit(op, name);
+        Ok(())
+    }
+
+    fn resolve_local(&self, level: usize, name: &Token) -> Option<u8> {
+        self.functions[level]
+            .locals
+            .iter()
+            .rposition(|l| l.name == name.lexeme)
+            .map(|slot| slot as u8)
+    }
+
+    fn resolve_upvalue(&mut self, level: usize, name: &Token) -> CompileResult<Option<u8>> {
+        if level == 0 {
+            return Ok(None);
+        }
+
+        if let Some(local) = self.resolve_local(level - 1, name) {
+            self.functions[level - 1].locals[local as usize].is_captured = true;
+            return self.add_upvalue(level, local, true, name).map(Some);
+        }
+
+        match self.resolve_upvalue(level - 1, name)? {
+            Some(upvalue) => self.add_upvalue(level, upvalue, false, name).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(&mut self, level: usize, index: u8, is_local: bool, name: &Token) -> CompileResult<u8> {
+        let descriptor = UpvalueDescriptor { is_local, index };
+        let upvalues = &mut self.functions[level].proto.upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == descriptor) {
+            return Ok(existing as u8);
+        }
+        if upvalues.len() == MAX_UPVALUES {
+            return Err(self.error(name, "Too many closure variables in function."));
+        }
+        upvalues.push(descriptor);
+        Ok((upvalues.len() - 1) as u8)
+    }
+
+    fn declare_variable(&mut self, name: &Token) -> CompileResult<()> {
+        if self.current().scope_depth == 0 {
+            return Ok(());
+        }
+        self.add_local(&name.lexeme, name)
+    }
+
+    fn add_local(&mut self, name: &str, token: &Token) -> CompileResult<()> {
+        if self.current().locals.len() == MAX_LOCALS {
+            return Err(self.error(token, "Too many local variables in function."));
+        }
+        self.current_mut().locals.push(Local {
+            name: name.to_string(),
+            depth: None,
+            is_captured: false,
+        });
+        Ok(())
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.current_mut();
+        if state.scope_depth == 0 {
+            return;
+        }
+        let depth = state.scope_depth;
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(depth);
+        }
+    }
+
+    /// Globals are bound by name; a local is already sitting in its slot.
+    fn define_variable(&mut self, name: &Token) -> CompileResult<()> {
+        if self.current().scope_depth > 0 {
+            return Ok(());
+        }
+        let constant = self.identifier_constant(name)?;
+        self.emit(OpCode::DefineGlobal(constant), name);
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.current_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self, span: Span) {
+        self.current_mut().scope_depth -= 1;
+        let depth = self.current().scope_depth;
+        while let Some(captured) = self.current()
+            .locals
+            .last()
+            .filter(|l| l.depth.is_some_and(|d| d > depth))
+            .map(|l| l.is_captured)
+        {
+            let op = if captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            };
+            self.write(op, span);
+            self.current_mut().locals.pop();
+        }
+    }
+
+    /// Pops the locals declared inside the innermost loop body before a jump
+    /// out of it. Unlike `end_scope`, the compiler keeps tracking them, since
+    /// the code after the jump is still inside their scope.
+    fn discard_loop_locals(&mut self, keyword: &Token) {
+        let state = self.current();
+        let depth = state.loops.last().unwrap().scope_depth;
+        let ops: Vec<OpCode> = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|l| l.depth.is_none_or(|d| d > depth))
+            .map(|l| if l.is_captured { OpCode::CloseUpvalue } else { OpCode::Pop })
+            .collect();
+        for op in ops {
+            self.emit(op, keyword);
+        }
+    }
+
+    fn begin_function(&mut self, name: &str, kind: FunctionKind) {
+        // slot zero holds the callee, which methods know as `this`
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+        self.functions.push(FunctionState {
+            proto: FunctionProto {
+                name: name.to_string(),
+                ..FunctionProto::default()
+            },
+            kind,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: Some(0),
+                is_captured: false,
+            }],
+            scope_depth: 0,
+            loops: vec![],
+            tries: vec![],
+        });
+    }
+
+    fn end_function(&mut self, span: Span) -> FunctionProto {
+        if self.current().kind == FunctionKind::Initializer {
+            self.write(OpCode::GetLocal(0), span);
+        } else {
+            self.write(OpCode::Nil, span);
+        }
+        self.write(OpCode::Return, span);
+        self.functions.pop().unwrap().proto
+    }
+
+    fn emit_loop(&mut self, loop_start: usize, token: &Token) -> CompileResult<()> {
+        let distance = self.current().proto.chunk.code.len() + 1 - loop_start;
+        if distance > u16::MAX as usize {
+            return Err(self.error(token, "Loop body too large."));
+        }
+        self.emit(OpCode::Loop(distance as u16), token);
+        Ok(())
+    }
+
+    fn patch_jump(&mut self, offset: usize, token: &Token) -> CompileResult<()> {
+        let distance = self.current().proto.chunk.code.len() - offset - 1;
+        if distance > u16::MAX as usize {
+            return Err(self.error(token, "Too much code to jump over."));
+        }
+        let distance = distance as u16;
+        let code = &mut self.current_mut().proto.chunk.code;
+        code[offset] = match code[offset] {
+            OpCode::Jump(_) => OpCode::Jump(distance),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(distance),
+            OpCode::JumpIfPassed(parameter, _) => OpCode::JumpIfPassed(parameter, distance),
+            OpCode::Try(_) => OpCode::Try(distance),
+            OpCode::TryFinally(_) => OpCode::TryFinally(distance),
+            op => op,
+        };
+        Ok(())
+    }
+
+    fn identifier_constant(&mut self, name: &Token) -> CompileResult<u16> {
+        let constant = Constant::String(Rc::from(name.lexeme.as_str()));
+        self.make_constant(constant, name)
+    }
+
+    fn make_constant(&mut self, constant: Constant, token: &Token) -> CompileResult<u16> {
+        let idx = self.current_mut().proto.chunk.add_constant(constant);
+        if idx > u16::MAX as usize {
+            return Err(self.error(token, "Too many constants in one chunk."));
+        }
+        Ok(idx as u16)
+    }
+
+    fn emit(&mut self, op: OpCode, token: &Token) -> usize {
+        self.write(op, span(token))
+    }
+
+    fn write(&mut self, op: OpCode, span: Span) -> usize {
+        self.current_mut().proto.chunk.write(op, span)
+    }
+
+    /// Compiles a loop that runs until `condition` is falsey, or forever
+    /// without one. `continue` jumps to the increment.
+    fn loop_statement(
+        &mut self,
+        condition: Option<&Expr>,
+        body: &Statement,
+        increment: Option<&Expr>,
+        token: &Token,
+    ) -> CompileResult<()> {
+        let loop_start = self.current().proto.chunk.code.len();
+        let exit_jump = match condition {
+            Some(condition) => {
+                self.expression(condition)?;
+                let jump = self.emit(OpCode::JumpIfFalse(0), token);
+                self.emit(OpCode::Pop, token);
+                Some(jump)
+            }
+            None => None,
+        };
+
+        let scope_depth = self.current().scope_depth;
+        self.current_mut().loops.push(LoopState {
+            scope_depth,
+            breaks: vec![],
+            continues: vec![],
+        });
+        let compiled = self.statement(body);
+        let state = self.current_mut().loops.pop().unwrap();
+        compiled?;
+
+        for jump in state.continues {
+            self.patch_jump(jump, token)?;
+        }
+        if let Some(increment) = increment {
+            self.expression(increment)?;
+            self.emit(OpCode::Pop, increment.token());
+        }
+        self.emit_loop(loop_start, token)?;
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump, token)?;
+            self.emit(OpCode::Pop, token);
+        }
+        for jump in state.breaks {
+            self.patch_jump(jump, token)?;
+        }
+        Ok(())
+    }
+
+    /// Installs a handler around the body. An exception unwinds to the
+    /// `catch`, if there is one, and from there or the body to a copy of the
+    /// `finally` block that raises it again. Otherwise the `finally` block
+    /// runs after the rest of the statement.
+    ///
+    /// The copy for an exception from the `catch` runs with the catch
+    /// variable still on the stack, in a slot the compiler no longer names.
+    fn try_statement(
+        &mut self,
+        keyword: &Token,
+        body: &[Statement],
+        catch: Option<&Catch>,
+        finally: Option<&Vec<Statement>>,
+    ) -> CompileResult<()> {
+        let loops = self.current().loops.len();
+        let handler = match catch {
+            Some(_) => OpCode::Try(0),
+            None => OpCode::TryFinally(0),
+        };
+        let handler = self.emit(handler, keyword);
+        self.current_mut().tries.push(TryState {
+            loops,
+            unwinding: false,
+            finally: finally.cloned(),
+        });
+        let compiled = self.block(body, keyword);
+        self.current_mut().tries.pop();
+        compiled?;
+        self.emit(OpCode::EndTry, keyword);
+        let mut exits = vec![self.emit(OpCode::Jump(0), keyword)];
+        self.patch_jump(handler, keyword)?;
+
+        let mut from_catch = false;
+        if let Some(catch) = catch {
+            // the exception was pushed where the catch variable's slot is
+            self.begin_scope();
+            self.add_local(&catch.name.lexeme, &catch.name)?;
+            self.mark_initialized();
+            let rethrow = match finally {
+                Some(finally) => {
+                    let rethrow = self.emit(OpCode::TryFinally(0), keyword);
+                    self.current_mut().tries.push(TryState {
+                        loops,
+                        unwinding: false,
+                        finally: Some(finally.clone()),
+                    });
+                    Some(rethrow)
+                }
+                None => None,
+            };
+            for s in &catch.body {
+                self.statement(s)?;
+            }
+            if rethrow.is_some() {
+                self.current_mut().tries.pop();
+                self.emit(OpCode::EndTry, keyword);
+            }
+            self.end_scope(span(keyword));
+            if let Some(rethrow) = rethrow {
+                exits.push(self.emit(OpCode::Jump(0), keyword));
+                self.patch_jump(rethrow, keyword)?;
+                from_catch = true;
+            }
+        }
+
+        if let Some(finally) = finally {
+            self.begin_scope();
+            if from_catch {
+                self.add_local("", keyword)?;
+                self.mark_initialized();
+                // a closure in the catch may have captured the variable
+                self.current_mut().locals.last_mut().unwrap().is_captured = true;
+            }
+            self.current_mut().tries.push(TryState {
+                loops,
+                unwinding: true,
+                finally: None,
+            });
+            let compiled = self.block(finally, keyword);
+            self.current_mut().tries.pop();
+            compiled?;
+            self.emit(OpCode::Rethrow, keyword);
+            if from_catch {
+                self.current_mut().locals.pop();
+            }
+            self.current_mut().scope_depth -= 1;
+        }
+        for exit in exits {
+            self.patch_jump(exit, keyword)?;
+        }
+        if let Some(finally) = finally {
+            self.block(finally, keyword)?;
+        }
+        Ok(())
+    }
+
+    /// Cleans up after the `try` statements a jump leaves: every one in the
+    /// function for a `return`, or those inside the innermost loop.
+    fn leave_tries(&mut self, all: bool, token: &Token) -> CompileResult<()> {
+        let loops = self.current().loops.len();
+        let mut i = self.current().tries.len();
+        while i > 0 && (all || self.current().tries[i - 1].loops == loops) {
+            i -= 1;
+            if self.current().tries[i].unwinding {
+                self.emit(OpCode::DropException, token);
+                continue;
+            }
+            self.emit(OpCode::EndTry, token);
+            if let Some(finally) = self.current().tries[i].finally.clone() {
+                // the block runs outside the statement it belongs to
+                let inner = self.current_mut().tries.split_off(i);
+                let compiled = self.block(&finally, token);
+                self.current_mut().tries.extend(inner);
+                compiled?;
+            }
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, statements: &[Statement], token: &Token) -> CompileResult<()> {
+        self.begin_scope();
+        for s in statements {
+            self.statement(s)?;
+        }
+        self.end_scope(span(token));
+        Ok(())
+    }
+
+    fn statement_span(&self, s: &Statement) -> Span {
+        match *s {
+            Statement::Expression { ref expression, .. } | Statement::Print { ref expression, .. } => {
+                span(expression.token())
+            }
+            Statement::If { ref condition, .. } | Statement::While { ref condition, .. } => {
+                span(condition.token())
+            }
+            Statement::Class { ref name, .. } | Statement::Var { ref name, .. } => span(name),
+            Statement::Function { ref declaration, .. } => span(&declaration.name),
+            Statement::For { ref keyword, .. }
+            | Statement::Return { ref keyword, .. }
+            | Statement::Throw { ref keyword, .. }
+            | Statement::Try { ref keyword, .. }
+            | Statement::Break { ref keyword, .. }
+            | Statement::Continue { ref keyword, .. } => span(keyword),
+            Statement::Block { ref statements, .. } => statements
+                .last()
+                .map(|s| self.statement_span(s))
+                .unwrap_or_default(),
+        }
+    }
+
+    fn current(&self) -> &FunctionState {
+        self.functions.last().unwrap()
+    }
+
+    fn current_mut(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn error(&self, token: &Token, message: &str) -> Error {
+        Error {
+            token: token.clone(),
+            message: message.to_string(),
+        }
+    }
+}
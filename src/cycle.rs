@@ -0,0 +1,26 @@
+//! Printing containers that may hold themselves.
+
+use std::cell::RefCell;
+use std::fmt;
+
+thread_local! {
+    /// Addresses of the containers being printed, outermost first.
+    static PRINTING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Prints a container with `print`, or as `placeholder` if it is already
+/// being printed further out, so a container that holds itself doesn't
+/// recurse forever.
+pub fn print_once<T, F>(container: *const T, f: &mut fmt::Formatter, placeholder: &str, print: F) -> fmt::Result
+where
+    F: FnOnce(&mut fmt::Formatter) -> fmt::Result,
+{
+    let address = container as usize;
+    if PRINTING.with(|p| p.borrow().contains(&address)) {
+        return write!(f, "{}", placeholder);
+    }
+    PRINTING.with(|p| p.borrow_mut().push(address));
+    let printed = print(f);
+    PRINTING.with(|p| p.borrow_mut().pop());
+    printed
+}
@@ -0,0 +1,120 @@
+//! The line-oriented frontend behind `lox debug`.
+
+use debugger::{Frontend, Paused, Reason, Resume};
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+b, break LINE     pause before statements on LINE
+delete LINE       remove the breakpoint on LINE
+c, continue       run to the next breakpoint
+s, step           step to the next statement, into calls
+n, next           step to the next statement, over calls
+o, out            run until the current function returns
+bt, backtrace     print the call stack
+p, print EXPR     evaluate EXPR in the current frame
+vars              print the locals and globals in scope
+q, quit           stop the program";
+
+/// Reads commands from `input` at every pause. At the end of the input the
+/// program runs to completion.
+pub struct Console<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Console<R, W> {
+    pub fn new(input: R, output: W) -> Console<R, W> {
+        Console { input, output }
+    }
+
+    /// Handles one command, returning how to resume if it was one that
+    /// resumes.
+    fn command(&mut self, state: &mut Paused, line: &str) -> Option<Resume> {
+        let line = line.trim();
+        let (command, argument) = match line.find(' ') {
+            Some(i) => (&line[..i], line[i + 1..].trim()),
+            None => (line, ""),
+        };
+        match command {
+            "" => (),
+            "c" | "continue" => return Some(Resume::Continue),
+            "s" | "step" => return Some(Resume::StepIn),
+            "n" | "next" => return Some(Resume::StepOver),
+            "o" | "out" | "finish" => return Some(Resume::StepOut),
+            "b" | "break" | "delete" => match argument.parse::<usize>() {
+                Ok(n) if command == "delete" => {
+                    if !state.breakpoints.remove(&n) {
+                        self.say(&format!("No breakpoint on line {}.", n));
+                    }
+                }
+                Ok(n) => {
+                    state.breakpoints.insert(n);
+                    self.say(&format!("Breakpoint on line {}.", n));
+                }
+                Err(_) => self.say("Expected a line number."),
+            },
+            "bt" | "backtrace" => {
+                let frames: Vec<String> = state
+                    .stack()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &(function, line))| format!("#{} {} (line {})", i, function, line))
+                    .collect();
+                self.say(&frames.join("\n"));
+            }
+            "p" | "print" => match state.evaluate(0, argument) {
+                Ok(value) => self.say(&value),
+                Err(message) => self.say(&format!("error: {}", message)),
+            },
+            "vars" | "locals" => {
+                let mut lines = vec![];
+                for scope in state.locals(0) {
+                    for (name, value) in scope {
+                        lines.push(format!("{} = {}", name, value));
+                    }
+                }
+                for (name, value) in state.globals() {
+                    lines.push(format!("{} = {} (global)", name, value));
+                }
+                self.say(&lines.join("\n"));
+            }
+            "help" => self.say(HELP),
+            "q" | "quit" => ::std::process::exit(0),
+            other => self.say(&format!("Unknown command '{}'. Try 'help'.", other)),
+        }
+        None
+    }
+
+    fn say(&mut self, text: &str) {
+        if !text.is_empty() {
+            let _ = writeln!(self.output, "{}", text);
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Frontend for Console<R, W> {
+    fn paused(&mut self, state: &mut Paused, reason: Reason) -> Resume {
+        let (function, line) = state.stack()[0];
+        let why = match reason {
+            Reason::Entry => "Paused on entry",
+            Reason::Breakpoint => "Breakpoint",
+            Reason::Step => "Stepped",
+        };
+        self.say(&format!("{} in {} at line {}.", why, function, line));
+        loop {
+            let _ = write!(self.output, "(debug) ");
+            let _ = self.output.flush();
+            let mut command = String::new();
+            match self.input.read_line(&mut command) {
+                Ok(0) | Err(_) => {
+                    state.breakpoints.clear();
+                    return Resume::Continue;
+                }
+                Ok(_) => (),
+            }
+            if let Some(resume) = self.command(state, &command) {
+                return resume;
+            }
+        }
+    }
+}
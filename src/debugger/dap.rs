@@ -0,0 +1,358 @@
+//! A Debug Adapter Protocol server, spoken over a pair of streams (stdin
+//! and stdout for `lox dap`).
+//!
+//! The adapter launches one program and reports it as a single thread.
+//! Requests are only read before the program starts and while it is
+//! paused, so `pause` isn't offered, and breakpoints changed while it runs
+//! take effect from the next stop. Lines start at 1. Whatever the program
+//! prints is sent to the client as `output` events.
+
+use debugger::{self, Frontend, Paused, Reason, Resume};
+use framing::{read_message, write_message};
+use interpreter::Interpreter;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
+
+const THREAD: u64 = 1;
+
+type RequestResult = Result<Value, String>;
+
+struct Connection<R, W> {
+    input: R,
+    output: W,
+    seq: u64,
+}
+
+/// The connection is shared between the adapter and the program's output.
+type Shared<R, W> = Rc<RefCell<Connection<R, W>>>;
+
+impl<R: BufRead, W: Write> Connection<R, W> {
+    /// The next request, skipping anything that isn't JSON. `None` at the
+    /// end of the input.
+    fn receive(&mut self) -> io::Result<Option<Value>> {
+        while let Some(body) = read_message(&mut self.input)? {
+            if let Ok(message) = serde_json::from_str(&body) {
+                return Ok(Some(message));
+            }
+        }
+        Ok(None)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = Value::from(self.seq);
+        write_message(&mut self.output, &message)
+    }
+
+    fn respond(&mut self, request: &Value, result: RequestResult) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = Value::from(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({"type": "event", "event": event, "body": body}))
+    }
+}
+
+/// Runs one debugging session: configuration, the program itself, then
+/// whatever the client asks until it disconnects or closes `input`.
+pub fn serve<R: BufRead + 'static, W: Write + 'static>(input: R, output: W) -> io::Result<i32> {
+    let connection = Rc::new(RefCell::new(Connection {
+        input,
+        output,
+        seq: 0,
+    }));
+    let mut breakpoints = BTreeSet::new();
+    let mut launch = None;
+    let mut configured = false;
+    while launch.is_none() || !configured {
+        let request = match connection.borrow_mut().receive()? {
+            Some(request) => request,
+            None => return Ok(0),
+        };
+        let arguments = &request["arguments"];
+        let result = match request["command"].as_str().unwrap_or("") {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsEvaluateForHovers": true,
+            })),
+            "launch" => {
+                let path = arguments["program"].as_str().unwrap_or("").to_string();
+                match fs::read_to_string(&path) {
+                    Ok(source) => {
+                        let stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+                        launch = Some((path, source, stop_on_entry));
+                        Ok(Value::Null)
+                    }
+                    Err(e) => Err(format!("Could not read '{}': {}", path, e)),
+                }
+            }
+            "setBreakpoints" => Ok(set_breakpoints(&mut breakpoints, arguments)),
+            "configurationDone" => {
+                configured = true;
+                Ok(Value::Null)
+            }
+            "threads" => Ok(threads()),
+            "disconnect" | "terminate" => {
+                connection.borrow_mut().respond(&request, Ok(Value::Null))?;
+                return Ok(0);
+            }
+            command => Err(format!("Unsupported request '{}'.", command)),
+        };
+        let initialized = request["command"] == "initialize" && result.is_ok();
+        connection.borrow_mut().respond(&request, result)?;
+        if initialized {
+            connection.borrow_mut().event("initialized", json!({}))?;
+        }
+    }
+
+    let (path, source, stop_on_entry) = launch.expect("launched");
+    let name = Path::new(&path)
+        .file_name()
+        .map_or(path.clone(), |n| n.to_string_lossy().into_owned());
+    let adapter = Adapter {
+        connection: Rc::clone(&connection),
+        source: json!({"name": name, "path": path}),
+        references: vec![],
+    };
+    let mut interpreter = Interpreter::new();
+    interpreter.print_to(Box::new(ProgramOutput {
+        connection: Rc::clone(&connection),
+        line: vec![],
+    }));
+    let exit_code = match debugger::run(interpreter, &source, adapter, breakpoints, stop_on_entry) {
+        Ok(()) => 0,
+        Err(diagnostics) => {
+            for d in &diagnostics {
+                let text = format!("{}\n", d.render(&path, &source));
+                let body = json!({"category": "stderr", "output": text});
+                connection.borrow_mut().event("output", body)?;
+            }
+            if diagnostics.iter().any(|d| d.kind == "runtime error") {
+                70
+            } else {
+                65
+            }
+        }
+    };
+    let mut connection = connection.borrow_mut();
+    connection.event("exited", json!({"exitCode": exit_code}))?;
+    connection.event("terminated", json!({}))?;
+
+    while let Some(request) = connection.receive()? {
+        match request["command"].as_str().unwrap_or("") {
+            "disconnect" | "terminate" => {
+                connection.respond(&request, Ok(Value::Null))?;
+                break;
+            }
+            "threads" => connection.respond(&request, Ok(json!({"threads": []})))?,
+            _ => connection.respond(&request, Err("The program has ended.".to_string()))?,
+        }
+    }
+    Ok(0)
+}
+
+/// Only lines where a statement begins are kept. The source is read from
+/// the request, since clients may set breakpoints before they launch.
+fn set_breakpoints(breakpoints: &mut BTreeSet<usize>, arguments: &Value) -> Value {
+    breakpoints.clear();
+    let path = arguments["source"]["path"].as_str().unwrap_or("");
+    let lines = fs::read_to_string(path)
+        .ok()
+        .and_then(|source| debugger::statement_lines(&source))
+        .unwrap_or_default();
+    let requested = arguments["breakpoints"].as_array().map_or(&[][..], |b| &b[..]);
+    let set: Vec<Value> = requested
+        .iter()
+        .filter_map(|b| b["line"].as_u64())
+        .map(|line| {
+            if lines.contains(&(line as usize)) {
+                breakpoints.insert(line as usize);
+                json!({"verified": true, "line": line})
+            } else {
+                json!({"verified": false, "line": line, "message": "No statement begins on this line."})
+            }
+        })
+        .collect();
+    json!({ "breakpoints": set })
+}
+
+fn threads() -> Value {
+    json!({"threads": [{"id": THREAD, "name": "main"}]})
+}
+
+/// Answers requests while the program is paused.
+struct Adapter<R, W> {
+    connection: Shared<R, W>,
+    /// The program, as a DAP `Source`.
+    source: Value,
+    /// What each `variablesReference` handed out at this stop stands for:
+    /// a frame and one of its local scopes, or the globals.
+    references: Vec<(usize, Option<usize>)>,
+}
+
+impl<R: BufRead, W: Write> Adapter<R, W> {
+    /// Handles one request, returning how to resume if it was one that
+    /// resumes.
+    fn request(
+        &mut self,
+        state: &mut Paused,
+        command: &str,
+        arguments: &Value,
+    ) -> (RequestResult, Option<Resume>) {
+        let resume = match command {
+            "continue" => Resume::Continue,
+            "next" => Resume::StepOver,
+            "stepIn" => Resume::StepIn,
+            "stepOut" => Resume::StepOut,
+            _ => return (self.inspect(state, command, arguments), None),
+        };
+        (Ok(json!({"allThreadsContinued": true})), Some(resume))
+    }
+
+    fn inspect(&mut self, state: &mut Paused, command: &str, arguments: &Value) -> RequestResult {
+        // frame ids start at 1 so that none of them looks unset
+        let frame = arguments["frameId"].as_u64().map_or(0, |id| id.saturating_sub(1) as usize);
+        match command {
+            "threads" => Ok(threads()),
+            "setBreakpoints" => Ok(set_breakpoints(state.breakpoints, arguments)),
+            "stackTrace" => {
+                let frames: Vec<Value> = state
+                    .stack()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &(function, line))| {
+                        json!({
+                            "id": i + 1,
+                            "name": function,
+                            "source": self.source,
+                            "line": line,
+                            "column": 1,
+                        })
+                    })
+                    .collect();
+                Ok(json!({"stackFrames": frames, "totalFrames": frames.len()}))
+            }
+            "scopes" => {
+                let mut scopes = vec![];
+                for (i, scope) in state.locals(frame).iter().enumerate() {
+                    if scope.is_empty() {
+                        continue;
+                    }
+                    let name = match i {
+                        0 => "Locals".to_string(),
+                        i => format!("Enclosing scope {}", i),
+                    };
+                    scopes.push(self.scope(&name, frame, Some(i)));
+                }
+                scopes.push(self.scope("Globals", frame, None));
+                Ok(json!({ "scopes": scopes }))
+            }
+            "variables" => {
+                let reference = arguments["variablesReference"].as_u64().unwrap_or(0) as usize;
+                let variables = match reference.checked_sub(1).and_then(|r| self.references.get(r)) {
+                    Some(&(frame, Some(scope))) => state.locals(frame).swap_remove(scope),
+                    Some(&(_, None)) => state.globals(),
+                    None => return Err("Unknown variables reference.".to_string()),
+                };
+                let variables: Vec<Value> = variables
+                    .into_iter()
+                    .map(|(name, value)| json!({"name": name, "value": value, "variablesReference": 0}))
+                    .collect();
+                Ok(json!({ "variables": variables }))
+            }
+            "evaluate" => {
+                let expression = arguments["expression"].as_str().unwrap_or("");
+                let result = state.evaluate(frame, expression)?;
+                Ok(json!({"result": result, "variablesReference": 0}))
+            }
+            command => Err(format!("Unsupported request '{}'.", command)),
+        }
+    }
+
+    fn scope(&mut self, name: &str, frame: usize, scope: Option<usize>) -> Value {
+        self.references.push((frame, scope));
+        json!({
+            "name": name,
+            "variablesReference": self.references.len(),
+            "expensive": false,
+        })
+    }
+}
+
+impl<R: BufRead, W: Write> Frontend for Adapter<R, W> {
+    fn paused(&mut self, state: &mut Paused, reason: Reason) -> Resume {
+        self.references.clear();
+        let reason = match reason {
+            Reason::Entry => "entry",
+            Reason::Breakpoint => "breakpoint",
+            Reason::Step => "step",
+        };
+        let stopped = json!({"reason": reason, "threadId": THREAD, "allThreadsStopped": true});
+        if self.connection.borrow_mut().event("stopped", stopped).is_err() {
+            process::exit(0);
+        }
+        loop {
+            // the connection isn't borrowed while handling a request, since
+            // evaluating an expression may print
+            let request = match self.connection.borrow_mut().receive() {
+                Ok(Some(request)) => request,
+                // the client has gone, and the program goes with it
+                Ok(None) | Err(_) => process::exit(0),
+            };
+            let command = request["command"].as_str().unwrap_or("");
+            if command == "disconnect" || command == "terminate" {
+                let _ = self.connection.borrow_mut().respond(&request, Ok(Value::Null));
+                process::exit(0);
+            }
+            let (result, resume) = self.request(state, command, &request["arguments"]);
+            if self.connection.borrow_mut().respond(&request, result).is_err() {
+                process::exit(0);
+            }
+            if let Some(resume) = resume {
+                return resume;
+            }
+        }
+    }
+}
+
+/// Sends what the program prints to the client a line at a time.
+struct ProgramOutput<R, W> {
+    connection: Shared<R, W>,
+    line: Vec<u8>,
+}
+
+impl<R: BufRead, W: Write> Write for ProgramOutput<R, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        if self.line.ends_with(b"\n") {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(&self.line).into_owned();
+        self.line.clear();
+        let body = json!({"category": "stdout", "output": text});
+        self.connection.borrow_mut().event("output", body)
+    }
+}
@@ -0,0 +1,546 @@
+//! Source-level debugging for the tree-walking interpreter.
+//!
+//! A `Debugger` attaches to the interpreter as a `Hook`, follows the Lox call
+//! stack, and decides before each statement whether to pause. While paused,
+//! a `Frontend` (the console, or a DAP client) inspects the program through
+//! `Paused` and says how to go on.
+
+pub mod console;
+pub mod dap;
+
+use ast::{Expr, Statement};
+use diagnostic::Diagnostic;
+use environment::Environment;
+use interpreter::{Hook, Interpreter};
+use lox_value::LoxValue;
+use parser::Parser;
+use resolver::Resolver;
+use scanner::Scanner;
+use std::collections::{BTreeSet, HashMap};
+use std::mem::{replace, take};
+use token::Token;
+
+/// How to go on after a pause.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume {
+    Continue,
+    /// Stop at the next statement.
+    StepIn,
+    /// Stop at the next statement that isn't inside a call made from here.
+    StepOver,
+    /// Stop at the next statement after the current function returns.
+    StepOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    Entry,
+    Breakpoint,
+    Step,
+}
+
+pub trait Frontend {
+    fn paused(&mut self, state: &mut Paused, reason: Reason) -> Resume;
+}
+
+/// An active call. The script itself is the outermost one.
+struct Frame {
+    function: String,
+    line: usize,
+    /// Position of the frame's current statement, which keys the names of
+    /// its locals.
+    statement: usize,
+    /// Kept up to date whenever the frame stops being the innermost one.
+    environment: Environment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Run,
+    StepIn,
+    /// Carry the call depth the step started at.
+    StepOver(usize),
+    StepOut(usize),
+}
+
+pub struct Debugger<F: Frontend> {
+    frontend: F,
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    entry: bool,
+    frames: Vec<Frame>,
+    scopes: HashMap<usize, Vec<Vec<String>>>,
+    /// The line, call depth and statement position last paused at. A
+    /// breakpoint there doesn't fire again for the statements after it on
+    /// the same line, but does once a loop comes back round to it.
+    paused_at: Option<(usize, usize, usize)>,
+    /// Where the next expression typed while paused is scanned from, past
+    /// every position in the program.
+    next_position: usize,
+    natives: Vec<String>,
+}
+
+/// Runs `source` on `interpreter` under a debugger that pauses at
+/// `breakpoints` (line numbers), and before the first statement if
+/// `stop_on_entry`. Returns the compile or runtime errors that stopped the
+/// program.
+pub fn run<F: Frontend + 'static>(
+    interpreter: Interpreter,
+    source: &str,
+    frontend: F,
+    breakpoints: BTreeSet<usize>,
+    stop_on_entry: bool,
+) -> Result<(), Vec<Diagnostic>> {
+    let mut scanner = Scanner::new(source.to_string());
+    scanner.scan_tokens();
+    let program = Parser::new(scanner.tokens)
+        .parse()
+        .map_err(|errors| errors.iter().map(|e| e.diagnostic()).collect::<Vec<_>>())?;
+    let mut resolver = Resolver::new(interpreter);
+    resolver.collect_scopes();
+    resolver.resolve(&program).map_err(|e| vec![e.diagnostic()])?;
+
+    let natives = resolver
+        .interpreter
+        .environment
+        .globals()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let debugger = Debugger {
+        frontend,
+        breakpoints,
+        mode: if stop_on_entry { Mode::StepIn } else { Mode::Run },
+        entry: stop_on_entry,
+        frames: vec![Frame {
+            function: "<script>".to_string(),
+            line: 1,
+            statement: 0,
+            environment: resolver.interpreter.environment.clone(),
+        }],
+        scopes: resolver.scopes(),
+        paused_at: None,
+        next_position: source.chars().count() + 1,
+        natives,
+    };
+    let mut interpreter = resolver.interpreter;
+    interpreter.attach(Box::new(debugger));
+    interpreter
+        .interpret(program)
+        .map_err(|e| e.diagnostic().into_iter().collect())
+}
+
+impl<F: Frontend> Hook for Debugger<F> {
+    fn statement(&mut self, interpreter: &mut Interpreter, statement: &Statement) {
+        if let Statement::Block { .. } = *statement {
+            return;
+        }
+        let token = match statement.token() {
+            Some(token) => token,
+            None => return,
+        };
+        let first = &statement.bounds().first;
+        let depth = self.frames.len();
+        if let Some(frame) = self.frames.last_mut() {
+            frame.line = first.line;
+            frame.statement = token.position;
+        }
+        if let Some((line, at_depth, position)) = self.paused_at {
+            if line != first.line || at_depth != depth || first.position <= position {
+                self.paused_at = None;
+            }
+        }
+
+        let stepped = match self.mode {
+            Mode::Run => false,
+            Mode::StepIn => true,
+            Mode::StepOver(from) => depth <= from,
+            Mode::StepOut(from) => depth < from,
+        };
+        let reason = if self.entry {
+            Reason::Entry
+        } else if stepped {
+            Reason::Step
+        } else if self.paused_at.is_none() && self.breakpoints.contains(&first.line) {
+            Reason::Breakpoint
+        } else {
+            return;
+        };
+        self.entry = false;
+
+        if let Some(frame) = self.frames.last_mut() {
+            frame.environment = interpreter.environment.clone();
+        }
+        let mut state = Paused {
+            interpreter,
+            frames: &self.frames,
+            scopes: &self.scopes,
+            breakpoints: &mut self.breakpoints,
+            next_position: &mut self.next_position,
+            natives: &self.natives,
+        };
+        self.mode = match self.frontend.paused(&mut state, reason) {
+            Resume::Continue => Mode::Run,
+            Resume::StepIn => Mode::StepIn,
+            Resume::StepOver => Mode::StepOver(depth),
+            Resume::StepOut => Mode::StepOut(depth),
+        };
+        self.paused_at = Some((first.line, depth, first.position));
+    }
+
+    fn call(&mut self, interpreter: &mut Interpreter, function: &str, paren: &Token) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.environment = interpreter.environment.clone();
+        }
+        self.frames.push(Frame {
+            function: function.to_string(),
+            line: paren.line,
+            statement: 0,
+            environment: interpreter.environment.clone(),
+        });
+    }
+
+    fn returned(&mut self, _interpreter: &mut Interpreter) {
+        self.frames.pop();
+    }
+}
+
+/// The program as a frontend sees it while paused. Frames are numbered from
+/// the innermost, 0.
+pub struct Paused<'a> {
+    interpreter: &'a mut Interpreter,
+    frames: &'a [Frame],
+    scopes: &'a HashMap<usize, Vec<Vec<String>>>,
+    pub breakpoints: &'a mut BTreeSet<usize>,
+    next_position: &'a mut usize,
+    natives: &'a [String],
+}
+
+impl<'a> Paused<'a> {
+    fn frame(&self, index: usize) -> Option<&'a Frame> {
+        let frames: &'a [Frame] = self.frames;
+        frames.iter().rev().nth(index)
+    }
+
+    /// The function each frame is in and its current line, innermost first.
+    pub fn stack(&self) -> Vec<(&'a str, usize)> {
+        let frames: &'a [Frame] = self.frames;
+        frames
+            .iter()
+            .rev()
+            .map(|f| (f.function.as_str(), f.line))
+            .collect()
+    }
+
+    /// The locals visible in a frame, innermost scope first.
+    pub fn locals(&self, frame: usize) -> Vec<Vec<(String, String)>> {
+        let frame = match self.frame(frame) {
+            Some(frame) => frame,
+            None => return vec![],
+        };
+        let names = self.scopes.get(&frame.statement).map_or(&[][..], |s| &s[..]);
+        names
+            .iter()
+            .rev()
+            .zip(frame.environment.locals())
+            .map(|(names, values)| {
+                names
+                    .iter()
+                    .zip(values)
+                    .map(|(name, value)| (name.clone(), inspect(&value)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Every global the program has defined, leaving out native functions.
+    pub fn globals(&self) -> Vec<(String, String)> {
+        self.interpreter
+            .environment
+            .globals()
+            .into_iter()
+            .filter(|(name, _)| !self.natives.contains(name))
+            .map(|(name, value)| (name, inspect(&value)))
+            .collect()
+    }
+
+    /// Evaluates an expression in a frame. It can assign to variables.
+    pub fn evaluate(&mut self, frame: usize, expression: &str) -> Result<String, String> {
+        let frame = self.frame(frame).ok_or("No such frame.")?;
+
+        // parsed as an expression statement, so the ';' is optional
+        let statement = format!("{};", expression.trim().trim_end_matches(';'));
+        let mut scanner = Scanner::with_offset(statement.clone(), *self.next_position, 1);
+        *self.next_position += statement.chars().count() + 1;
+        scanner.scan_tokens();
+        let mut program = Parser::new(scanner.tokens)
+            .parse()
+            .map_err(|errors| errors[0].diagnostic().message)?;
+        let expr = match program.statements.pop() {
+            Some(Statement::Expression { expression, .. }) if program.statements.is_empty() => {
+                expression
+            }
+            _ => return Err("Expected an expression.".to_string()),
+        };
+
+        // the resolver records locals in the interpreter it owns
+        let names = self.scopes.get(&frame.statement).map_or(&[][..], |s| &s[..]);
+        let mut resolver = Resolver::new(take(self.interpreter));
+        let resolved = resolver.resolve_in(&expr, names);
+        *self.interpreter = resolver.interpreter;
+        resolved.map_err(|e| e.diagnostic().message)?;
+
+        let environment = replace(&mut self.interpreter.environment, frame.environment.clone());
+        let value = self.interpreter.evaluate(&expr);
+        self.interpreter.environment = environment;
+        match value {
+            Ok(value) => Ok(inspect(&value)),
+            Err(e) => Err(e.diagnostic().map_or_else(|| e.to_string(), |d| d.message)),
+        }
+    }
+}
+
+/// The lines a breakpoint can stop at: those where a statement other than a
+/// block begins. `None` if `source` doesn't parse.
+pub fn statement_lines(source: &str) -> Option<BTreeSet<usize>> {
+    let mut scanner = Scanner::new(source.to_string());
+    scanner.scan_tokens();
+    let program = Parser::new(scanner.tokens).parse().ok()?;
+    let mut lines = BTreeSet::new();
+    for s in &program.statements {
+        statement_lines_in(s, &mut lines);
+    }
+    Some(lines)
+}
+
+fn body_lines_in(statements: &[Statement], lines: &mut BTreeSet<usize>) {
+    for s in statements {
+        statement_lines_in(s, lines);
+    }
+}
+
+fn statement_lines_in(statement: &Statement, lines: &mut BTreeSet<usize>) {
+    if let Statement::Block { ref statements, .. } = *statement {
+        return body_lines_in(statements, lines);
+    }
+    lines.insert(statement.bounds().first.line);
+    match *statement {
+        Statement::Class {
+            ref superclass,
+            ref methods,
+            ..
+        } => {
+            superclass.iter().for_each(|e| expression_lines_in(e, lines));
+            methods.iter().for_each(|m| body_lines_in(&m.body, lines));
+        }
+        Statement::Expression { ref expression, .. } | Statement::Print { ref expression, .. } => {
+            expression_lines_in(expression, lines)
+        }
+        Statement::For {
+            ref initializer,
+            ref condition,
+            ref increment,
+            body: ref loop_body,
+            ..
+        } => {
+            initializer.iter().for_each(|s| statement_lines_in(s, lines));
+            condition.iter().chain(increment).for_each(|e| expression_lines_in(e, lines));
+            statement_lines_in(loop_body, lines);
+        }
+        Statement::Function { ref declaration, .. } => {
+            let defaults = declaration.parameters.iter().filter_map(|p| p.default.as_ref());
+            defaults.for_each(|e| expression_lines_in(e, lines));
+            body_lines_in(&declaration.body, lines);
+        }
+        Statement::If {
+            ref condition,
+            ref then_branch,
+            ref else_branch,
+            ..
+        } => {
+            expression_lines_in(condition, lines);
+            statement_lines_in(then_branch, lines);
+            else_branch.iter().for_each(|s| statement_lines_in(s, lines));
+        }
+        Statement::Return { ref value, .. } => value.iter().for_each(|e| expression_lines_in(e, lines)),
+        Statement::Throw { ref value, .. } => expression_lines_in(value, lines),
+        Statement::Try {
+            body: ref try_body,
+            ref catch,
+            ref finally,
+            ..
+        } => {
+            body_lines_in(try_body, lines);
+            catch.iter().for_each(|c| body_lines_in(&c.body, lines));
+            finally.iter().for_each(|f| body_lines_in(f, lines));
+        }
+        Statement::While {
+            ref condition,
+            body: ref loop_body,
+            ..
+        } => {
+            expression_lines_in(condition, lines);
+            statement_lines_in(loop_body, lines);
+        }
+        Statement::Var { ref initializer, .. } => {
+            initializer.iter().for_each(|e| expression_lines_in(e, lines))
+        }
+        Statement::Block { .. } | Statement::Break { .. } | Statement::Continue { .. } => {}
+    }
+}
+
+/// Only a lambda's body holds statements, but one can be nested anywhere.
+fn expression_lines_in(expression: &Expr, lines: &mut BTreeSet<usize>) {
+    let mut walk = |e: &Expr| expression_lines_in(e, lines);
+    match *expression {
+        Expr::Lambda { ref declaration, .. } => body_lines_in(&declaration.body, lines),
+        Expr::Assign { ref value, .. } | Expr::Unary { right: ref value, .. } => walk(value),
+        Expr::Binary {
+            ref left, ref right, ..
+        }
+        | Expr::Logical {
+            ref left, ref right, ..
+        } => {
+            walk(left);
+            walk(right);
+        }
+        Expr::Call {
+            ref callee,
+            ref arguments,
+            ..
+        } => {
+            walk(callee);
+            arguments.iter().for_each(walk);
+        }
+        Expr::Get { ref object, .. } => walk(object),
+        Expr::Set {
+            ref object,
+            ref value,
+            ..
+        } => {
+            walk(object);
+            walk(value);
+        }
+        Expr::Index {
+            ref object,
+            ref index,
+            ..
+        } => {
+            walk(object);
+            walk(index);
+        }
+        Expr::IndexSet {
+            ref object,
+            ref index,
+            ref value,
+            ..
+        } => {
+            walk(object);
+            walk(index);
+            walk(value);
+        }
+        Expr::Grouping { ref expression, .. } => walk(expression),
+        Expr::List { ref elements, .. } => elements.iter().for_each(walk),
+        Expr::Map { ref entries, .. } => entries.iter().for_each(|(k, v)| {
+            walk(k);
+            walk(v);
+        }),
+        Expr::Literal { .. } | Expr::Super { .. } | Expr::This { .. } | Expr::Variable { .. } => {}
+    }
+}
+
+/// Like printing a value, but with strings quoted so they stand out from
+/// numbers and names.
+fn inspect(value: &LoxValue) -> String {
+    match *value {
+        LoxValue::String(ref s) => format!("{:?}", s),
+        ref value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use debugger::{run, statement_lines, Frontend, Paused, Reason, Resume};
+    use interpreter::Interpreter;
+    use std::cell::RefCell;
+    use std::collections::BTreeSet;
+    use std::rc::Rc;
+
+    /// Steps through the program, recording what it sees at every pause.
+    struct Script {
+        steps: Vec<Resume>,
+        seen: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Frontend for Script {
+        fn paused(&mut self, state: &mut Paused, reason: Reason) -> Resume {
+            let (function, line) = state.stack()[0];
+            let x = state.evaluate(0, "x").unwrap_or_default();
+            let depth = state.stack().len();
+            let seen = format!("{:?} {}:{} x={} depth={}", reason, function, line, x, depth);
+            self.seen.borrow_mut().push(seen);
+            if self.steps.is_empty() {
+                Resume::Continue
+            } else {
+                self.steps.remove(0)
+            }
+        }
+    }
+
+    #[test]
+    fn steps_in_over_and_out_of_calls() {
+        let source = "fun f(x) {\n  var y = x + 1;\n  return y;\n}\nvar x = f(1);\nprint f(x);\n";
+        let seen = Rc::new(RefCell::new(vec![]));
+        let script = Script {
+            steps: vec![
+                Resume::StepIn,
+                Resume::StepIn,
+                Resume::StepIn,
+                Resume::StepOut,
+                Resume::StepOver,
+            ],
+            seen: Rc::clone(&seen),
+        };
+        let breakpoints: BTreeSet<usize> = vec![3].into_iter().collect();
+        run(Interpreter::new(), source, script, breakpoints, true).unwrap();
+        assert_eq!(
+            *seen.borrow(),
+            vec![
+                "Entry <script>:1 x= depth=1",
+                "Step <script>:5 x= depth=1",
+                "Step f:2 x=1 depth=2",
+                "Step f:3 x=1 depth=2",
+                "Step <script>:6 x=2 depth=1",
+                "Breakpoint f:3 x=2 depth=2",
+            ]
+        );
+    }
+
+    #[test]
+    fn breaks_on_every_pass_through_a_loop() {
+        let source = "for (var x = 0; x < 3; x = x + 1) {\n  print x; print x;\n}\n";
+        let seen = Rc::new(RefCell::new(vec![]));
+        let script = Script {
+            steps: vec![],
+            seen: Rc::clone(&seen),
+        };
+        let breakpoints: BTreeSet<usize> = vec![2].into_iter().collect();
+        run(Interpreter::new(), source, script, breakpoints, false).unwrap();
+        assert_eq!(
+            *seen.borrow(),
+            vec![
+                "Breakpoint <script>:2 x=0 depth=1",
+                "Breakpoint <script>:2 x=1 depth=1",
+                "Breakpoint <script>:2 x=2 depth=1",
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_the_lines_statements_begin_on() {
+        let source = "var f = fun (x) {\n  return x;\n};\n{\n\n  print f(\n    1);\n}\n";
+        let lines: Vec<usize> = statement_lines(source).unwrap().into_iter().collect();
+        assert_eq!(lines, vec![1, 2, 6]);
+        assert_eq!(statement_lines("print ;"), None);
+    }
+}
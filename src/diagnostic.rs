@@ -0,0 +1,136 @@
+use std::fmt;
+use token::Token;
+
+/// The message for an exception nobody caught: the thrown map's `message`,
+/// when it has one, or else the thrown value itself. A caught runtime error
+/// is a map with a `message`, so rethrowing it reports the original error.
+pub fn uncaught_message<T: fmt::Display>(thrown: &T, message: Option<String>) -> String {
+    message.unwrap_or_else(|| format!("Uncaught exception: {}.", thrown))
+}
+
+/// A located error message that can be rendered against the source it came
+/// from. Parse, resolution, and runtime errors all convert into this.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub kind: &'static str,
+    pub message: String,
+    pub position: usize,
+    pub length: usize,
+    /// For a runtime error raised inside a function, the calls that were
+    /// active: each function's name and the line it had reached, innermost
+    /// first.
+    pub trace: Vec<(String, usize)>,
+}
+
+impl Diagnostic {
+    pub fn new(kind: &'static str, token: &Token, message: &str) -> Diagnostic {
+        Diagnostic {
+            kind,
+            message: message.to_string(),
+            position: token.position,
+            length: token.lexeme.chars().count(),
+            trace: vec![],
+        }
+    }
+
+    /// Returns the 1-based (line, column) of the start of the diagnostic.
+    pub fn location(&self, source: &str) -> (usize, usize) {
+        let mut line = 1;
+        let mut column = 1;
+        for c in source.chars().take(self.position) {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        (line, column)
+    }
+
+    /// Renders the message, `file:line:col`, the offending source line, and a
+    /// caret underline beneath the token, followed by the stack trace if
+    /// there is one.
+    ///
+    /// ```text
+    /// parse error: Expect ';' after statement.
+    ///  --> example.lox:2:7
+    ///   |
+    /// 2 | print a
+    ///   |       ^
+    /// ```
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let (line, column) = self.location(source);
+        let source_line = source.lines().nth(line - 1).unwrap_or("");
+        let gutter = " ".repeat(line.to_string().len());
+
+        // a token that spans lines (a string literal) only gets underlined up
+        // to the end of its first line
+        let remaining = source_line.chars().count().saturating_sub(column - 1);
+        let underline = "^".repeat(self.length.min(remaining).max(1));
+
+        let mut rendered = format!(
+            "{}: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self.kind,
+            self.message,
+            gutter,
+            file_name,
+            line,
+            column,
+            gutter,
+            line,
+            source_line,
+            gutter,
+            " ".repeat(column - 1),
+            underline
+        );
+        // recursion that ran away repeats the same call; only the first
+        // few are worth printing
+        let mut repeated = 0;
+        for (i, (function, line)) in self.trace.iter().enumerate() {
+            if i > 0 && self.trace[i - 1] == self.trace[i] {
+                repeated += 1;
+            } else {
+                rendered.push_str(&repeats(repeated));
+                repeated = 0;
+            }
+            if repeated < 3 {
+                rendered.push_str(&format!("\n  at {} ({}:{})", function, file_name, line));
+            }
+        }
+        rendered.push_str(&repeats(repeated));
+        rendered
+    }
+}
+
+/// The line that stands for the repeats of a call beyond the first three.
+fn repeats(repeated: usize) -> String {
+    match repeated.saturating_sub(2) {
+        0 => String::new(),
+        1 => "\n  ... repeated 1 more time".to_string(),
+        n => format!("\n  ... repeated {} more times", n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diagnostic::Diagnostic;
+
+    #[test]
+    fn renders_line_column_and_caret() {
+        let source = "var a = 1;\nprint a +;\n";
+        let d = Diagnostic {
+            kind: "parse error",
+            message: "Expect expression.".to_string(),
+            position: 20,
+            length: 1,
+            trace: vec![],
+        };
+
+        assert_eq!(d.location(source), (2, 10));
+        assert_eq!(
+            d.render("test.lox", source),
+            "parse error: Expect expression.\n --> test.lox:2:10\n  |\n2 | print a +;\n  |          ^"
+        );
+    }
+}
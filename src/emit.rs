@@ -0,0 +1,625 @@
+//! Machine-readable dumps of the scanner's and parser's output for external
+//! tools.
+//!
+//! Both documents are JSON objects with a top-level `"version"` field set to
+//! [`SCHEMA_VERSION`], which is bumped whenever a field is removed, renamed or
+//! changes meaning. Adding fields does not bump it.
+//!
+//! A token is `{"type", "lexeme", "literal", "line", "column", "position",
+//! "length"}` where `type` is the `TokenType` variant name, `literal` is the
+//! number or string value of literal tokens and `null` otherwise, and
+//! `position` and `length` count characters.
+//!
+//! Every AST node is an object with a `"type"` naming its kind and a
+//! `"span"`, followed by its children and names. A span is `{"start",
+//! "end"}`, each a `{"position", "line", "column"}` location, with `end` just
+//! past the node's last character. A statement's span runs from its first
+//! token to its last, so it takes in keywords like `var`, the `;` and the
+//! braces of a block. Other nodes only cover the tokens the tree records for
+//! them and their children.
+
+use ast::{Expr, FunctionDeclaration, Program, Statement};
+use lox_value::LoxValue;
+use serde_json::{Map, Value};
+use token::Token;
+
+pub const SCHEMA_VERSION: u64 = 4;
+
+pub fn tokens_json(tokens: &[Token]) -> String {
+    let tokens: Vec<Value> = tokens.iter().map(token).collect();
+    document("tokens", Value::Array(tokens))
+}
+
+/// `source` is the text the program was parsed from, used to turn positions
+/// into lines and columns.
+pub fn ast_json(program: &Program, source: &str) -> String {
+    let emitter = Emitter::new(source);
+    let statements = emitter.statements(&program.statements);
+    let node = emitter.node("Program", statements.span, vec![("statements", statements.value)]);
+    document("program", node.value)
+}
+
+fn document(key: &str, value: Value) -> String {
+    let mut object = Map::new();
+    object.insert("version".to_string(), Value::from(SCHEMA_VERSION));
+    object.insert(key.to_string(), value);
+    serde_json::to_string_pretty(&Value::Object(object)).unwrap()
+}
+
+fn token(token: &Token) -> Value {
+    let mut object = Map::new();
+    object.insert("type".to_string(), Value::from(format!("{:?}", token.token_type)));
+    object.insert("lexeme".to_string(), Value::from(token.lexeme.as_str()));
+    object.insert("literal".to_string(), literal(&token.literal));
+    object.insert("line".to_string(), Value::from(token.line));
+    object.insert("column".to_string(), Value::from(token.column));
+    object.insert("position".to_string(), Value::from(token.position));
+    object.insert("length".to_string(), Value::from(token.lexeme.chars().count()));
+    Value::Object(object)
+}
+
+fn literal(value: &LoxValue) -> Value {
+    match *value {
+        LoxValue::Number(n) => Value::from(n),
+        LoxValue::String(ref s) => Value::from(s.as_str()),
+        LoxValue::Bool(b) => Value::from(b),
+        _ => Value::Null,
+    }
+}
+
+/// Start and end positions, end exclusive.
+type Span = Option<(usize, usize)>;
+
+fn token_span(token: &Token) -> Span {
+    Some((token.position, token.position + token.lexeme.chars().count()))
+}
+
+fn join(a: Span, b: Span) -> Span {
+    match (a, b) {
+        (Some((s1, e1)), Some((s2, e2))) => Some((s1.min(s2), e1.max(e2))),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// A serialized node together with its span, which parents fold into their
+/// own.
+struct Node {
+    value: Value,
+    span: Span,
+}
+
+struct Emitter {
+    /// Position of the first character of each line.
+    line_starts: Vec<usize>,
+}
+
+impl Emitter {
+    fn new(source: &str) -> Emitter {
+        let mut line_starts = vec![0];
+        for (i, c) in source.chars().enumerate() {
+            if c == '\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        Emitter { line_starts }
+    }
+
+    fn location(&self, position: usize) -> Value {
+        let line = match self.line_starts.binary_search(&position) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let mut object = Map::new();
+        object.insert("position".to_string(), Value::from(position));
+        object.insert("line".to_string(), Value::from(line + 1));
+        object.insert("column".to_string(), Value::from(position - self.line_starts[line] + 1));
+        Value::Object(object)
+    }
+
+    fn node(&self, kind: &str, span: Span, fields: Vec<(&str, Value)>) -> Node {
+        let mut object = Map::new();
+        object.insert("type".to_string(), Value::from(kind));
+        let span_value = match span {
+            Some((start, end)) => {
+                let mut range = Map::new();
+                range.insert("start".to_string(), self.location(start));
+                range.insert("end".to_string(), self.location(end));
+                Value::Object(range)
+            }
+            None => Value::Null,
+        };
+        object.insert("span".to_string(), span_value);
+        for (name, value) in fields {
+            object.insert(name.to_string(), value);
+        }
+        Node {
+            value: Value::Object(object),
+            span,
+        }
+    }
+
+    fn statements(&self, statements: &[Statement]) -> Node {
+        let nodes: Vec<Node> = statements.iter().map(|s| self.statement(s)).collect();
+        self.array(nodes)
+    }
+
+    fn exprs(&self, exprs: &[Expr]) -> Node {
+        let nodes: Vec<Node> = exprs.iter().map(|e| self.expr(e)).collect();
+        self.array(nodes)
+    }
+
+    fn array(&self, nodes: Vec<Node>) -> Node {
+        let span = nodes.iter().fold(None, |span, n| join(span, n.span));
+        Node {
+            value: Value::Array(nodes.into_iter().map(|n| n.value).collect()),
+            span,
+        }
+    }
+
+    fn optional_expr(&self, expr: &Option<Box<Expr>>) -> Node {
+        match *expr {
+            Some(ref e) => self.expr(e),
+            None => Node {
+                value: Value::Null,
+                span: None,
+            },
+        }
+    }
+
+    fn function(&self, kind: &str, declaration: &FunctionDeclaration, span: Span) -> Node {
+        let parameters: Vec<Node> = declaration
+            .parameters
+            .iter()
+            .map(|p| {
+                let default = match p.default {
+                    Some(ref default) => self.expr(default),
+                    None => Node {
+                        value: Value::Null,
+                        span: None,
+                    },
+                };
+                self.node(
+                    "Parameter",
+                    join(token_span(&p.name), default.span),
+                    vec![
+                        ("name", Value::from(p.name.lexeme.as_str())),
+                        ("default", default.value),
+                    ],
+                )
+            })
+            .collect();
+        let parameters = self.array(parameters);
+        let rest = declaration.rest.as_ref();
+        let span = join(span, parameters.span);
+        let span = rest.map_or(span, |r| join(span, token_span(r)));
+        let rest = rest.map_or(Value::Null, |r| Value::from(r.lexeme.as_str()));
+        let body = self.statements(&declaration.body);
+        self.node(
+            kind,
+            join(span, body.span),
+            vec![
+                ("name", Value::from(declaration.name.lexeme.as_str())),
+                ("parameters", parameters.value),
+                ("rest", rest),
+                ("body", body.value),
+            ],
+        )
+    }
+
+    fn statement(&self, statement: &Statement) -> Node {
+        let bounds = statement.bounds();
+        let span = join(token_span(&bounds.first), token_span(&bounds.last));
+        match *statement {
+            Statement::Block { ref statements, .. } => {
+                let statements = self.statements(statements);
+                self.node("Block", span, vec![("statements", statements.value)])
+            }
+            Statement::Break { .. } => self.node("Break", span, vec![]),
+            Statement::Continue { .. } => self.node("Continue", span, vec![]),
+            Statement::Class {
+                ref name,
+                ref superclass,
+                ref methods,
+                ..
+            } => {
+                let superclass = self.optional_expr(superclass);
+                let methods: Vec<Node> = methods
+                    .iter()
+                    .map(|m| self.function("Function", m, token_span(&m.name)))
+                    .collect();
+                let methods = self.array(methods);
+                self.node(
+                    "Class",
+                    span,
+                    vec![
+                        ("name", Value::from(name.lexeme.as_str())),
+                        ("superclass", superclass.value),
+                        ("methods", methods.value),
+                    ],
+                )
+            }
+            Statement::Expression { ref expression, .. } => {
+                let expression = self.expr(expression);
+                self.node("Expression", span, vec![("expression", expression.value)])
+            }
+            Statement::Function { ref declaration, .. } => {
+                self.function("Function", declaration, span)
+            }
+            Statement::If {
+                ref condition,
+                ref then_branch,
+                ref else_branch,
+                ..
+            } => {
+                let condition = self.expr(condition);
+                let then_branch = self.statement(then_branch);
+                let else_branch = match *else_branch {
+                    Some(ref s) => self.statement(s),
+                    None => Node {
+                        value: Value::Null,
+                        span: None,
+                    },
+                };
+                self.node(
+                    "If",
+                    span,
+                    vec![
+                        ("condition", condition.value),
+                        ("then", then_branch.value),
+                        ("else", else_branch.value),
+                    ],
+                )
+            }
+            Statement::Print { ref expression, .. } => {
+                let expression = self.expr(expression);
+                self.node("Print", span, vec![("expression", expression.value)])
+            }
+            Statement::Return { ref value, .. } => {
+                let value = self.optional_expr(value);
+                self.node("Return", span, vec![("value", value.value)])
+            }
+            Statement::Throw { ref value, .. } => {
+                let value = self.expr(value);
+                self.node("Throw", span, vec![("value", value.value)])
+            }
+            Statement::Try {
+                ref body,
+                ref catch,
+                ref finally,
+                ..
+            } => {
+                let body = self.statements(body);
+                let catch = match *catch {
+                    Some(ref catch) => {
+                        let body = self.statements(&catch.body);
+                        self.node(
+                            "Catch",
+                            join(token_span(&catch.name), body.span),
+                            vec![
+                                ("name", Value::from(catch.name.lexeme.as_str())),
+                                ("body", body.value),
+                            ],
+                        )
+                    }
+                    None => Node {
+                        value: Value::Null,
+                        span: None,
+                    },
+                };
+                let finally = match *finally {
+                    Some(ref finally) => self.statements(finally),
+                    None => Node {
+                        value: Value::Null,
+                        span: None,
+                    },
+                };
+                self.node(
+                    "Try",
+                    span,
+                    vec![
+                        ("body", body.value),
+                        ("catch", catch.value),
+                        ("finally", finally.value),
+                    ],
+                )
+            }
+            Statement::For {
+                ref initializer,
+                ref condition,
+                ref increment,
+                ref body,
+                ..
+            } => {
+                let initializer = match *initializer {
+                    Some(ref s) => self.statement(s),
+                    None => Node {
+                        value: Value::Null,
+                        span: None,
+                    },
+                };
+                let condition = self.optional_expr(condition);
+                let increment = self.optional_expr(increment);
+                let body = self.statement(body);
+                self.node(
+                    "For",
+                    span,
+                    vec![
+                        ("initializer", initializer.value),
+                        ("condition", condition.value),
+                        ("increment", increment.value),
+                        ("body", body.value),
+                    ],
+                )
+            }
+            Statement::While {
+                ref condition,
+                ref body,
+                ..
+            } => {
+                let condition = self.expr(condition);
+                let body = self.statement(body);
+                self.node(
+                    "While",
+                    span,
+                    vec![("condition", condition.value), ("body", body.value)],
+                )
+            }
+            Statement::Var {
+                ref name,
+                ref initializer,
+                ..
+            } => {
+                let initializer = self.optional_expr(initializer);
+                self.node(
+                    "Var",
+                    span,
+                    vec![
+                        ("name", Value::from(name.lexeme.as_str())),
+                        ("initializer", initializer.value),
+                    ],
+                )
+            }
+        }
+    }
+
+    fn expr(&self, expr: &Expr) -> Node {
+        match *expr {
+            Expr::Assign {
+                ref name,
+                ref value,
+            } => {
+                let value = self.expr(value);
+                self.node(
+                    "Assign",
+                    join(token_span(name), value.span),
+                    vec![
+                        ("name", Value::from(name.lexeme.as_str())),
+                        ("value", value.value),
+                    ],
+                )
+            }
+            Expr::Binary {
+                ref left,
+                ref right,
+                ref operator,
+            } => self.binary("Binary", left, operator, right),
+            Expr::Logical {
+                ref left,
+                ref right,
+                ref operator,
+            } => self.binary("Logical", left, operator, right),
+            Expr::Call {
+                ref callee,
+                ref paren,
+                ref arguments,
+            } => {
+                let callee = self.expr(callee);
+                let arguments = self.exprs(arguments);
+                self.node(
+                    "Call",
+                    join(join(callee.span, arguments.span), token_span(paren)),
+                    vec![("callee", callee.value), ("arguments", arguments.value)],
+                )
+            }
+            Expr::Get {
+                ref object,
+                ref name,
+            } => {
+                let object = self.expr(object);
+                self.node(
+                    "Get",
+                    join(object.span, token_span(name)),
+                    vec![
+                        ("object", object.value),
+                        ("name", Value::from(name.lexeme.as_str())),
+                    ],
+                )
+            }
+            Expr::Set {
+                ref object,
+                ref name,
+                ref value,
+            } => {
+                let object = self.expr(object);
+                let value = self.expr(value);
+                self.node(
+                    "Set",
+                    join(join(object.span, token_span(name)), value.span),
+                    vec![
+                        ("object", object.value),
+                        ("name", Value::from(name.lexeme.as_str())),
+                        ("value", value.value),
+                    ],
+                )
+            }
+            Expr::Index {
+                ref object,
+                ref bracket,
+                ref index,
+            } => {
+                let object = self.expr(object);
+                let index = self.expr(index);
+                self.node(
+                    "Index",
+                    join(join(object.span, token_span(bracket)), index.span),
+                    vec![("object", object.value), ("index", index.value)],
+                )
+            }
+            Expr::IndexSet {
+                ref object,
+                ref bracket,
+                ref index,
+                ref value,
+            } => {
+                let object = self.expr(object);
+                let index = self.expr(index);
+                let value = self.expr(value);
+                self.node(
+                    "IndexSet",
+                    join(
+                        join(join(object.span, token_span(bracket)), index.span),
+                        value.span,
+                    ),
+                    vec![
+                        ("object", object.value),
+                        ("index", index.value),
+                        ("value", value.value),
+                    ],
+                )
+            }
+            Expr::Grouping {
+                ref expression,
+                ref token,
+            } => {
+                let expression = self.expr(expression);
+                self.node(
+                    "Grouping",
+                    join(expression.span, token_span(token)),
+                    vec![("expression", expression.value)],
+                )
+            }
+            Expr::Literal {
+                ref value,
+                ref token,
+            } => self.node("Literal", token_span(token), vec![("value", literal(value))]),
+            Expr::List {
+                ref bracket,
+                ref elements,
+            } => {
+                let elements = self.exprs(elements);
+                self.node(
+                    "List",
+                    join(token_span(bracket), elements.span),
+                    vec![("elements", elements.value)],
+                )
+            }
+            Expr::Lambda {
+                ref keyword,
+                ref declaration,
+            } => self.function("Lambda", declaration, token_span(keyword)),
+            Expr::Map {
+                ref brace,
+                ref entries,
+            } => {
+                let mut span = token_span(brace);
+                let entries: Vec<Value> = entries
+                    .iter()
+                    .map(|(k, v)| {
+                        let key = self.expr(k);
+                        let value = self.expr(v);
+                        span = join(join(span, key.span), value.span);
+                        let mut entry = Map::new();
+                        entry.insert("key".to_string(), key.value);
+                        entry.insert("value".to_string(), value.value);
+                        Value::Object(entry)
+                    })
+                    .collect();
+                self.node("Map", span, vec![("entries", Value::from(entries))])
+            }
+            Expr::Super {
+                ref keyword,
+                ref method,
+            } => self.node(
+                "Super",
+                join(token_span(keyword), token_span(method)),
+                vec![("method", Value::from(method.lexeme.as_str()))],
+            ),
+            Expr::This { ref keyword } => self.node("This", token_span(keyword), vec![]),
+            Expr::Unary {
+                ref right,
+                ref operator,
+            } => {
+                let right = self.expr(right);
+                self.node(
+                    "Unary",
+                    join(token_span(operator), right.span),
+                    vec![
+                        ("operator", Value::from(operator.lexeme.as_str())),
+                        ("right", right.value),
+                    ],
+                )
+            }
+            Expr::Variable { ref name } => self.node(
+                "Variable",
+                token_span(name),
+                vec![("name", Value::from(name.lexeme.as_str()))],
+            ),
+        }
+    }
+
+    fn binary(&self, kind: &str, left: &Expr, operator: &Token, right: &Expr) -> Node {
+        let left = self.expr(left);
+        let right = self.expr(right);
+        self.node(
+            kind,
+            join(left.span, right.span),
+            vec![
+                ("left", left.value),
+                ("operator", Value::from(operator.lexeme.as_str())),
+                ("right", right.value),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use emit;
+    use parser::Parser;
+    use scanner::Scanner;
+    use serde_json::{self, Value};
+
+    fn ast(source: &str) -> Value {
+        let mut scanner = Scanner::new(source.to_string());
+        scanner.scan_tokens();
+        let program = Parser::new(scanner.tokens).parse().unwrap();
+        serde_json::from_str(&emit::ast_json(&program, source)).unwrap()
+    }
+
+    #[test]
+    fn spans_cover_children_across_lines() {
+        let json = ast("print 1 +\n  foo;");
+        assert_eq!(json["version"], emit::SCHEMA_VERSION);
+        let binary = &json["program"]["statements"][0]["expression"];
+        assert_eq!(binary["type"], "Binary");
+        assert_eq!(binary["span"]["start"]["position"], 6);
+        assert_eq!(binary["span"]["end"]["line"], 2);
+        assert_eq!(binary["span"]["end"]["column"], 6);
+        assert_eq!(binary["right"]["name"], "foo");
+    }
+
+    #[test]
+    fn statement_spans_take_in_keywords_semicolons_and_braces() {
+        let json = ast("var a = 1;\nif (a) {}\n");
+        // (start line, start column, end line, end column)
+        let span = |statement: &Value| {
+            let at = |end: &str, field: &str| statement["span"][end][field].as_u64().unwrap();
+            (at("start", "line"), at("start", "column"), at("end", "line"), at("end", "column"))
+        };
+        let statements = &json["program"]["statements"];
+        assert_eq!(span(&statements[0]), (1, 1, 1, 11));
+        assert_eq!(span(&statements[1]), (2, 1, 2, 10));
+        assert_eq!(span(&statements[1]["then"]), (2, 8, 2, 10));
+    }
+}
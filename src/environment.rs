@@ -1,100 +1,169 @@
 use std::collections::HashMap;
-use token::{Token};
+use token::Token;
 use lox_value::LoxValue;
 use interpreter::Error;
 use std::cell::RefCell;
 use std::rc::Rc;
+use gc::{self, Trace, Tracer};
+
+/// One local scope. The resolver hands out slot indices in declaration order,
+/// so defining a local is a push and reading one is an index.
+#[derive(Debug)]
+struct Frame {
+    slots: RefCell<Vec<LoxValue>>,
+    enclosing: Option<Rc<Frame>>,
+}
 
-type Bindings = Rc<RefCell<HashMap<String, LoxValue>>>;
-
+/// Globals are looked up by name; everything else lives in a chain of frames
+/// addressed by the (depth, slot) pairs the resolver computes. Cloning an
+/// environment only clones two `Rc`s, which is what a closure captures.
 #[derive(Debug, Clone)]
 pub struct Environment {
-    stack: Vec<Bindings>,
+    globals: Rc<RefCell<HashMap<String, LoxValue>>>,
+    frame: Option<Rc<Frame>>,
 }
 
-fn new_binding() -> Bindings {
-    Rc::new(RefCell::new(HashMap::new()))
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Environment {
     pub fn from(source_env: &Environment) -> Environment {
-        Environment {
-            stack: source_env.stack.iter().map(|e| Rc::clone(e)).collect(),
-        }
+        source_env.clone()
     }
 
     pub fn new() -> Environment {
+        let globals = Rc::new(RefCell::new(HashMap::new()));
+        gc::track(&globals);
         Environment {
-            stack: vec![new_binding()],
+            globals,
+            frame: None,
         }
     }
 
     pub fn push(&mut self) {
-        self.stack.push(new_binding())
+        let enclosing = self.frame.take();
+        let frame = Rc::new(Frame {
+            slots: RefCell::new(Vec::new()),
+            enclosing,
+        });
+        gc::track(&frame);
+        self.frame = Some(frame);
     }
 
     pub fn pop(&mut self) {
-        self.stack.pop();
+        self.frame = self.frame.take().and_then(|f| f.enclosing.clone());
     }
 
-    fn find_frame_for_var(&mut self, name: &str) -> Option<Bindings> {
-        for frame in self.stack.iter().rev() {
-            if frame.borrow_mut().contains_key(name) {
-                return Some(Rc::clone(frame));
-            }
+    fn ancestor(&self, depth: usize) -> &Rc<Frame> {
+        let mut frame = self.frame.as_ref().expect("resolved local outside of any scope");
+        for _ in 0..depth {
+            frame = frame.enclosing.as_ref().expect("resolved local deeper than scope chain");
         }
-        None
+        frame
     }
 
-    pub fn define(&mut self, name: String, value: LoxValue) -> () {
-        let values = self.stack.last().unwrap();
-
-        values.borrow_mut().insert(name, value);
+    /// Defines a new variable in the innermost scope. At the top level that
+    /// is the global table; anywhere else the value takes the next slot.
+    pub fn define(&mut self, name: &str, value: LoxValue) {
+        match self.frame {
+            Some(ref frame) => frame.slots.borrow_mut().push(value),
+            None => {
+                self.globals.borrow_mut().insert(name.to_string(), value);
+            }
+        }
     }
 
-    pub fn get_at(&mut self, idx: usize, name: &Token) -> Result<LoxValue, Error> {
-        let lexeme = name.lexeme.clone();
-        let stack_frame = &self.stack[idx];
-
-        Ok(stack_frame.borrow_mut().get(&lexeme).unwrap().to_owned())
+    pub fn get_at(&self, depth: usize, slot: usize) -> LoxValue {
+        self.ancestor(depth).slots.borrow()[slot].clone()
     }
 
-    pub fn get(&mut self, name: &Token) -> Result<LoxValue, Error> {
-        let lexeme = name.lexeme.clone();
+    pub fn assign_at(&mut self, depth: usize, slot: usize, value: LoxValue) -> LoxValue {
+        self.ancestor(depth).slots.borrow_mut()[slot] = value.clone();
+        value
+    }
 
-        match self.find_frame_for_var(&lexeme) {
-            Some(stack_frame) => Ok(stack_frame.borrow_mut().get(&lexeme).unwrap().to_owned()),
+    pub fn get_global(&self, name: &Token) -> Result<LoxValue, Error> {
+        match self.globals.borrow().get(&name.lexeme) {
+            Some(value) => Ok(value.clone()),
             None => Err(Error::RuntimeError {
                 token: name.clone(),
-                message: format!("Undefined variable {}.", lexeme),
+                message: format!("Undefined variable '{}'.", name.lexeme),
+                trace: Box::new([]),
             }),
         }
     }
 
-    pub fn assign(&mut self, name: &Token, value: LoxValue) -> Result<LoxValue, Error> {
-        let lexeme = name.lexeme.clone();
-        match self.find_frame_for_var(&lexeme) {
-            Some(stack_frame) => {
-                stack_frame.borrow_mut().insert(lexeme, value.to_owned());
+    pub fn assign_global(&mut self, name: &Token, value: LoxValue) -> Result<LoxValue, Error> {
+        match self.globals.borrow_mut().get_mut(&name.lexeme) {
+            Some(slot) => {
+                *slot = value.clone();
                 Ok(value)
             }
             None => Err(Error::RuntimeError {
                 token: name.clone(),
-                message: format!("Undefined variable {}.", lexeme),
+                message: format!("Undefined variable '{}'.", name.lexeme),
+                trace: Box::new([]),
             }),
         }
     }
 
-    pub fn assign_at(
-        &mut self,
-        idx: usize,
-        name: &Token,
-        value: LoxValue,
-    ) -> Result<LoxValue, Error> {
-        let lexeme = name.lexeme.clone();
-        let stack_frame = &self.stack[idx];
+    /// The values in each local scope, innermost first.
+    pub fn locals(&self) -> Vec<Vec<LoxValue>> {
+        let mut locals = vec![];
+        let mut frame = self.frame.as_ref();
+        while let Some(f) = frame {
+            locals.push(f.slots.borrow().clone());
+            frame = f.enclosing.as_ref();
+        }
+        locals
+    }
+
+    /// Every global binding, sorted by name.
+    pub fn globals(&self) -> Vec<(String, LoxValue)> {
+        let mut globals: Vec<(String, LoxValue)> = self.globals
+            .borrow()
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    /// Reports the references a closure holding this environment keeps.
+    pub fn trace(&self, tracer: &mut Tracer) {
+        tracer.edge(&self.globals);
+        if let Some(ref frame) = self.frame {
+            tracer.edge(frame);
+        }
+    }
+}
+
+impl Trace for Frame {
+    fn trace(&self, tracer: &mut Tracer) {
+        for value in self.slots.borrow().iter() {
+            value.trace(tracer);
+        }
+        if let Some(ref enclosing) = self.enclosing {
+            tracer.edge(enclosing);
+        }
+    }
+
+    fn clear(&self) {
+        self.slots.borrow_mut().clear();
+    }
+}
+
+impl Trace for RefCell<HashMap<String, LoxValue>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for value in self.borrow().values() {
+            value.trace(tracer);
+        }
+    }
 
-        stack_frame.borrow_mut().insert(lexeme, value.to_owned());
-        Ok(value)
+    fn clear(&self) {
+        self.borrow_mut().clear();
     }
 }
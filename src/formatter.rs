@@ -0,0 +1,578 @@
+use ast::{Expr, FunctionDeclaration, Statement};
+use diagnostic::Diagnostic;
+use parser::Parser;
+use scanner::Scanner;
+use std::mem::take;
+use token::Token;
+
+const INDENT: &str = "  ";
+
+/// Reprints `source` in the canonical layout: two-space indentation, opening
+/// braces on the same line, single spaces around binary operators and after
+/// commas, and at most one blank line between statements. Comments are kept
+/// where they were relative to the surrounding code.
+pub fn format(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let mut scanner = Scanner::new(source.to_string());
+    scanner.scan_tokens();
+    let comments = scanner.comments.clone();
+    let mut parser = Parser::new(scanner.tokens);
+    let program = parser
+        .parse()
+        .map_err(|errors| errors.iter().map(|e| e.diagnostic()).collect::<Vec<_>>())?;
+
+    let mut printer = Printer::new(&parser.tokens, &comments);
+    for s in &program.statements {
+        printer.statement(s);
+    }
+    if let Some(token) = printer.out_of_step {
+        let message = "Could not reprint the code here, so the file was left as it is.";
+        return Err(vec![Diagnostic::new("format error", &token, message)]);
+    }
+    printer.comments_before(usize::MAX);
+    printer.newline();
+    Ok(printer.out)
+}
+
+/// Writes the tree back out while walking the source tokens in step with
+/// it. Every token the printer writes is checked against the next source
+/// token, which is also how it knows when to emit the comments that came
+/// before it. If they ever disagree the printer stops writing, and
+/// `format` reports where.
+struct Printer<'a> {
+    tokens: &'a [Token],
+    next: usize,
+    comments: &'a [Token],
+    next_comment: usize,
+    out: String,
+    indent: usize,
+    /// Set when a comment broke a statement across lines, which indents the
+    /// rest of it one more level.
+    continued: bool,
+    at_line_start: bool,
+    /// A space to write before the next text on the same line, so that none
+    /// is left at the end of a line.
+    space_pending: bool,
+    /// Source line of the last token or comment written.
+    last_line: usize,
+    /// False right after an opening brace, where blank lines are dropped.
+    blank_allowed: bool,
+    /// The source token that didn't match what the tree says comes next.
+    out_of_step: Option<Token>,
+}
+
+impl<'a> Printer<'a> {
+    fn new(tokens: &'a [Token], comments: &'a [Token]) -> Printer<'a> {
+        Printer {
+            tokens,
+            next: 0,
+            comments,
+            next_comment: 0,
+            out: String::new(),
+            indent: 0,
+            continued: false,
+            at_line_start: true,
+            space_pending: false,
+            last_line: 1,
+            blank_allowed: false,
+            out_of_step: None,
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        if self.at_line_start {
+            for _ in 0..self.indent + self.continued as usize {
+                self.out.push_str(INDENT);
+            }
+            self.at_line_start = false;
+        } else if self.space_pending {
+            self.out.push(' ');
+        }
+        self.space_pending = false;
+        self.out.push_str(text);
+    }
+
+    fn space(&mut self) {
+        self.space_pending = true;
+    }
+
+    fn newline(&mut self) {
+        if !self.at_line_start {
+            self.out.push('\n');
+            self.at_line_start = true;
+        }
+        self.space_pending = false;
+    }
+
+    /// Keeps a blank line the source had before `line`.
+    fn blank_line(&mut self, line: usize) {
+        if self.blank_allowed && line > self.last_line + 1 {
+            self.out.push('\n');
+        }
+    }
+
+    /// Writes every comment that starts before `position`. One on the same
+    /// line as the code before it stays at the end of that line; the rest
+    /// get lines of their own.
+    fn comments_before(&mut self, position: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.position >= position {
+                break;
+            }
+            if self.at_line_start || comment.line != self.last_line {
+                self.newline();
+                self.blank_line(comment.line);
+            } else {
+                self.space();
+            }
+            self.write(&comment.lexeme);
+            self.newline();
+            self.last_line = comment.line;
+            self.blank_allowed = true;
+            self.next_comment += 1;
+        }
+    }
+
+    fn token(&mut self, text: &str) {
+        let token = &self.tokens[self.next];
+        if self.out_of_step.is_some() || token.lexeme != text {
+            self.out_of_step.get_or_insert_with(|| token.clone());
+            return;
+        }
+        let comments = self.next_comment;
+        self.comments_before(token.position);
+        if self.next_comment > comments && self.at_line_start {
+            self.continued = true;
+        }
+        self.write(text);
+        self.last_line = token.line;
+        self.blank_allowed = true;
+        self.next += 1;
+    }
+
+    fn name(&mut self, token: &Token) {
+        self.token(&token.lexeme);
+    }
+
+    fn peek(&self) -> &'a Token {
+        &self.tokens[self.next]
+    }
+
+    /// Writes `{`, the items on their own lines one level deeper, and `}`.
+    fn braced<T, F: FnMut(&mut Self, &T)>(&mut self, items: &[T], mut item: F) {
+        self.token("{");
+        self.blank_allowed = false;
+        // a block that opens on a continuation line is indented from there
+        let continued = take(&mut self.continued);
+        self.indent += 1 + continued as usize;
+        for i in items {
+            self.start_line();
+            item(self, i);
+        }
+        self.continued = false;
+        let close = self.peek().position;
+        let has_comments = self
+            .comments
+            .get(self.next_comment)
+            .is_some_and(|c| c.position < close);
+        self.comments_before(close);
+        self.indent -= 1;
+        if !items.is_empty() || has_comments {
+            self.newline();
+        }
+        self.token("}");
+        self.indent -= continued as usize;
+        self.continued = continued;
+    }
+
+    /// Moves to a new line for the next statement.
+    fn start_line(&mut self) {
+        self.continued = false;
+        let position = self.peek().position;
+        self.comments_before(position);
+        self.newline();
+        let line = self.peek().line;
+        self.blank_line(line);
+    }
+
+    fn statement(&mut self, s: &Statement) {
+        self.start_line();
+        self.inline_statement(s);
+    }
+
+    /// The body of an `if`, `else` or loop: a block, or a single statement
+    /// on the same line.
+    fn branch(&mut self, s: &Statement) {
+        self.space();
+        self.inline_statement(s);
+    }
+
+    fn inline_statement(&mut self, s: &Statement) {
+        match *s {
+            Statement::Block { ref statements, .. } => {
+                self.braced(statements, |p, s| p.inline_statement(s))
+            }
+            Statement::Break { ref keyword, .. } | Statement::Continue { ref keyword, .. } => {
+                self.name(keyword);
+                self.token(";");
+            }
+            Statement::Class {
+                ref name,
+                ref superclass,
+                ref methods,
+                ..
+            } => {
+                self.token("class");
+                self.space();
+                self.name(name);
+                if let Some(ref superclass) = *superclass {
+                    self.space();
+                    self.token("<");
+                    self.space();
+                    self.expr(superclass);
+                }
+                self.space();
+                self.braced(methods, |p, m| p.function(m));
+            }
+            Statement::Expression { ref expression, .. } => {
+                self.expr(expression);
+                self.token(";");
+            }
+            Statement::For {
+                ref initializer,
+                ref condition,
+                ref increment,
+                ref body,
+                ..
+            } => {
+                self.token("for");
+                self.space();
+                self.token("(");
+                match *initializer {
+                    Some(ref i) => self.inline_statement(i),
+                    None => self.token(";"),
+                }
+                if let Some(ref c) = *condition {
+                    self.space();
+                    self.expr(c);
+                }
+                self.token(";");
+                if let Some(ref i) = *increment {
+                    self.space();
+                    self.expr(i);
+                }
+                self.token(")");
+                self.branch(body);
+            }
+            Statement::Function { ref declaration, .. } => {
+                self.token("fun");
+                self.space();
+                self.function(declaration);
+            }
+            Statement::If {
+                ref condition,
+                ref then_branch,
+                ref else_branch,
+                ..
+            } => {
+                self.token("if");
+                self.space();
+                self.token("(");
+                self.expr(condition);
+                self.token(")");
+                self.branch(then_branch);
+                if let Some(ref else_branch) = *else_branch {
+                    // a comment before `else` doesn't make it a continuation
+                    let position = self.peek().position;
+                    self.comments_before(position);
+                    self.space();
+                    self.token("else");
+                    self.branch(else_branch);
+                }
+            }
+            Statement::Print { ref expression, .. } => {
+                self.token("print");
+                self.space();
+                self.expr(expression);
+                self.token(";");
+            }
+            Statement::Return {
+                ref keyword,
+                ref value,
+                ..
+            } => {
+                self.name(keyword);
+                if let Some(ref v) = *value {
+                    self.space();
+                    self.expr(v);
+                }
+                self.token(";");
+            }
+            Statement::Throw {
+                ref keyword,
+                ref value,
+                ..
+            } => {
+                self.name(keyword);
+                self.space();
+                self.expr(value);
+                self.token(";");
+            }
+            Statement::Try {
+                ref body,
+                ref catch,
+                ref finally,
+                ..
+            } => {
+                self.token("try");
+                self.space();
+                self.braced(body, |p, s| p.inline_statement(s));
+                if let Some(ref catch) = *catch {
+                    self.space();
+                    self.token("catch");
+                    self.space();
+                    self.token("(");
+                    self.name(&catch.name);
+                    self.token(")");
+                    self.space();
+                    self.braced(&catch.body, |p, s| p.inline_statement(s));
+                }
+                if let Some(ref finally) = *finally {
+                    self.space();
+                    self.token("finally");
+                    self.space();
+                    self.braced(finally, |p, s| p.inline_statement(s));
+                }
+            }
+            Statement::While {
+                ref condition,
+                ref body,
+                ..
+            } => {
+                self.token("while");
+                self.space();
+                self.token("(");
+                self.expr(condition);
+                self.token(")");
+                self.branch(body);
+            }
+            Statement::Var {
+                ref name,
+                ref initializer,
+                ..
+            } => {
+                self.token("var");
+                self.space();
+                self.name(name);
+                if let Some(ref i) = *initializer {
+                    self.space();
+                    self.token("=");
+                    self.space();
+                    self.expr(i);
+                }
+                self.token(";");
+            }
+        }
+    }
+
+    /// Everything after `fun`, or a whole method.
+    fn function(&mut self, declaration: &FunctionDeclaration) {
+        self.name(&declaration.name);
+        self.parameters(declaration);
+    }
+
+    fn parameters(&mut self, declaration: &FunctionDeclaration) {
+        self.token("(");
+        self.separated(&declaration.parameters, |p, parameter| {
+            p.name(&parameter.name);
+            if let Some(ref default) = parameter.default {
+                p.assigned(default);
+            }
+        });
+        if let Some(ref rest) = declaration.rest {
+            if !declaration.parameters.is_empty() {
+                self.token(",");
+                self.space();
+            }
+            self.token("...");
+            self.name(rest);
+        }
+        self.token(")");
+        self.space();
+        self.braced(&declaration.body, |p, s| p.inline_statement(s));
+    }
+
+    fn separated<T, F: FnMut(&mut Self, &T)>(&mut self, items: &[T], mut item: F) {
+        for (i, t) in items.iter().enumerate() {
+            if i > 0 {
+                self.token(",");
+                self.space();
+            }
+            item(self, t);
+        }
+    }
+
+    fn expr(&mut self, e: &Expr) {
+        match *e {
+            Expr::Assign {
+                ref name,
+                ref value,
+            } => {
+                self.name(name);
+                self.assigned(value);
+            }
+            Expr::Binary {
+                ref left,
+                ref right,
+                ref operator,
+            }
+            | Expr::Logical {
+                ref left,
+                ref right,
+                ref operator,
+            } => {
+                self.expr(left);
+                self.space();
+                self.name(operator);
+                self.space();
+                self.expr(right);
+            }
+            Expr::Call {
+                ref callee,
+                ref arguments,
+                ..
+            } => {
+                self.expr(callee);
+                self.token("(");
+                self.separated(arguments, |p, a| p.expr(a));
+                self.token(")");
+            }
+            Expr::Get {
+                ref object,
+                ref name,
+            } => {
+                self.expr(object);
+                self.token(".");
+                self.name(name);
+            }
+            Expr::Set {
+                ref object,
+                ref name,
+                ref value,
+            } => {
+                self.expr(object);
+                self.token(".");
+                self.name(name);
+                self.assigned(value);
+            }
+            Expr::Index {
+                ref object,
+                ref index,
+                ..
+            } => {
+                self.expr(object);
+                self.subscript(index);
+            }
+            Expr::IndexSet {
+                ref object,
+                ref index,
+                ref value,
+                ..
+            } => {
+                self.expr(object);
+                self.subscript(index);
+                self.assigned(value);
+            }
+            Expr::Grouping { ref expression, .. } => {
+                self.token("(");
+                self.expr(expression);
+                self.token(")");
+            }
+            Expr::Literal { ref token, .. } => self.name(token),
+            Expr::List { ref elements, .. } => {
+                self.token("[");
+                self.separated(elements, |p, e| p.expr(e));
+                self.token("]");
+            }
+            Expr::Lambda {
+                ref declaration, ..
+            } => {
+                self.token("fun");
+                self.space();
+                self.parameters(declaration);
+            }
+            Expr::Map { ref entries, .. } => {
+                self.token("{");
+                self.separated(entries, |p, (k, v)| {
+                    p.expr(k);
+                    p.token(":");
+                    p.space();
+                    p.expr(v);
+                });
+                self.token("}");
+            }
+            Expr::Super { ref method, .. } => {
+                self.token("super");
+                self.token(".");
+                self.name(method);
+            }
+            Expr::This { .. } => self.token("this"),
+            Expr::Unary {
+                ref right,
+                ref operator,
+            } => {
+                self.name(operator);
+                self.expr(right);
+            }
+            Expr::Variable { ref name } => self.name(name),
+        }
+    }
+
+    fn assigned(&mut self, value: &Expr) {
+        self.space();
+        self.token("=");
+        self.space();
+        self.expr(value);
+    }
+
+    fn subscript(&mut self, index: &Expr) {
+        self.token("[");
+        self.expr(index);
+        self.token("]");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use formatter::{format, Printer};
+    use scanner::Scanner;
+
+    #[test]
+    fn reprints_for_loops_and_comments_as_written() {
+        let source = "// count\nfor(var i=0;i<3;i=i+1){print i;// each\n\n\n  if (i>1) print \"big\"; else {}\n}\nfun f(a,b){return a+b;}\n// done\n";
+        let expected = "// count\nfor (var i = 0; i < 3; i = i + 1) {\n  print i; // each\n\n  if (i > 1) print \"big\"; else {}\n}\nfun f(a, b) {\n  return a + b;\n}\n// done\n";
+        let formatted = format(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn indents_code_continued_after_a_comment() {
+        let source = "fun f() {\n  var l = [1, // one\n2];\n  if (l) print l;\n  // otherwise\n  else g(fun () { // callback\n  return 1; }, // last\n  2);\n}\n";
+        let expected = "fun f() {\n  var l = [1, // one\n    2];\n  if (l) print l;\n  // otherwise\n  else g(fun () { // callback\n    return 1;\n  }, // last\n    2);\n}\n";
+        let formatted = format(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn stops_when_the_tree_and_tokens_disagree() {
+        let mut scanner = Scanner::new("print 1;".to_string());
+        scanner.scan_tokens();
+        let mut printer = Printer::new(&scanner.tokens, &[]);
+        printer.token("print");
+        printer.token("2");
+        printer.token(";");
+        assert_eq!(printer.out, "print");
+        assert_eq!(printer.out_of_step.map(|t| t.lexeme), Some("1".to_string()));
+    }
+}
@@ -0,0 +1,196 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+/// Collections start once this many containers are tracked, and afterwards
+/// whenever the tracked count doubles.
+const INITIAL_THRESHOLD: usize = 10_000;
+
+/// Anything a reference cycle can pass through.
+pub trait Trace {
+    /// Reports every `Rc` this object holds, once per reference.
+    fn trace(&self, tracer: &mut Tracer);
+
+    /// Drops whatever this object holds. Only called on garbage, to break the
+    /// cycles keeping it alive.
+    fn clear(&self) {}
+}
+
+/// Builds the graph of objects reachable from the tracked containers while
+/// holding exactly one reference to each of them.
+pub struct Tracer {
+    index: HashMap<usize, usize>,
+    nodes: Vec<Rc<dyn Trace>>,
+    edges: Vec<Vec<usize>>,
+    current: usize,
+}
+
+impl Tracer {
+    pub fn edge<T: Trace + 'static>(&mut self, object: &Rc<T>) {
+        self.edge_dyn(Rc::clone(object) as Rc<dyn Trace>)
+    }
+
+    pub fn edge_dyn(&mut self, object: Rc<dyn Trace>) {
+        let target = self.node(object);
+        self.edges[self.current].push(target);
+    }
+
+    fn node(&mut self, object: Rc<dyn Trace>) -> usize {
+        let address = Rc::as_ptr(&object) as *const () as usize;
+        if let Some(&i) = self.index.get(&address) {
+            return i;
+        }
+        let i = self.nodes.len();
+        self.index.insert(address, i);
+        self.nodes.push(object);
+        self.edges.push(Vec::new());
+        i
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub collections: usize,
+    /// Objects freed by all collections so far.
+    pub collected: usize,
+    /// Containers currently alive and tracked.
+    pub tracked: usize,
+}
+
+struct Heap {
+    objects: Vec<Weak<dyn Trace>>,
+    threshold: usize,
+    stats: Stats,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap {
+        objects: Vec::new(),
+        threshold: INITIAL_THRESHOLD,
+        stats: Stats::default(),
+    });
+}
+
+/// Registers a newly allocated container, which is anything with interior
+/// mutability that can hold a value: only mutation can close a cycle.
+pub fn track<T: Trace + 'static>(object: &Rc<T>) {
+    let weak: Weak<dyn Trace> = Rc::downgrade(object) as Weak<T>;
+    HEAP.with(|heap| heap.borrow_mut().objects.push(weak));
+}
+
+/// Collects if enough containers have been allocated since the last
+/// collection. Callers must not be holding a mutable borrow of any
+/// container.
+pub fn maybe_collect() {
+    let due = HEAP.with(|heap| {
+        let heap = heap.borrow();
+        heap.objects.len() >= heap.threshold
+    });
+    if due {
+        collect();
+    }
+}
+
+/// Frees every object that is only reachable from other tracked objects and
+/// returns how many there were.
+///
+/// Like CPython's collector this needs no list of roots: an object whose
+/// strong count is higher than the number of references found by tracing
+/// is referenced from somewhere outside the heap, such as the interpreter's
+/// environment or a value on the Rust stack, and so is live.
+pub fn collect() -> usize {
+    let candidates: Vec<Rc<dyn Trace>> = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.objects.retain(|o| o.strong_count() > 0);
+        heap.objects.iter().filter_map(Weak::upgrade).collect()
+    });
+
+    let mut tracer = Tracer {
+        index: HashMap::new(),
+        nodes: Vec::new(),
+        edges: Vec::new(),
+        current: 0,
+    };
+    for object in candidates {
+        tracer.node(object);
+    }
+    let mut i = 0;
+    while i < tracer.nodes.len() {
+        tracer.current = i;
+        let object = Rc::clone(&tracer.nodes[i]);
+        object.trace(&mut tracer);
+        i += 1;
+    }
+
+    let mut internal = vec![0; tracer.nodes.len()];
+    for targets in &tracer.edges {
+        for &t in targets {
+            internal[t] += 1;
+        }
+    }
+
+    // the tracer's own reference accounts for the `- 1`
+    let mut reachable = vec![false; tracer.nodes.len()];
+    let mut stack: Vec<usize> = (0..tracer.nodes.len())
+        .filter(|&i| Rc::strong_count(&tracer.nodes[i]) - 1 > internal[i])
+        .collect();
+    while let Some(i) = stack.pop() {
+        if reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        stack.extend(tracer.edges[i].iter().filter(|&&t| !reachable[t]));
+    }
+
+    let mut collected = 0;
+    for (object, _) in tracer.nodes.iter().zip(&reachable).filter(|&(_, &r)| !r) {
+        object.clear();
+        collected += 1;
+    }
+    drop(tracer);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.objects.retain(|o| o.strong_count() > 0);
+        heap.threshold = INITIAL_THRESHOLD.max(heap.objects.len() * 2);
+        heap.stats.collections += 1;
+        heap.stats.collected += collected;
+    });
+    collected
+}
+
+pub fn stats() -> Stats {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        Stats {
+            tracked: heap.objects.iter().filter(|o| o.strong_count() > 0).count(),
+            ..heap.stats
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use gc;
+    use lox_value::LoxValue;
+
+    fn self_referencing_list() -> LoxValue {
+        let list = LoxValue::list(vec![]);
+        if let LoxValue::List(ref inner) = list {
+            inner.borrow_mut().push(list.clone());
+        }
+        list
+    }
+
+    #[test]
+    fn frees_unreachable_cycles_only() {
+        let live = self_referencing_list();
+        drop(self_referencing_list());
+
+        assert_eq!(gc::collect(), 1);
+        assert_eq!(gc::stats().tracked, 1);
+        if let LoxValue::List(ref inner) = live {
+            assert_eq!(inner.borrow().len(), 1);
+        }
+    }
+}
@@ -4,35 +4,185 @@ use token::{Token, TokenType};
 use lox_value::LoxValue;
 use lox_callable::LoxCallable;
 use lox_function::LoxFunction;
+use lox_class::LoxClass;
 use runtime::std_fns::Clock;
+use runtime::native_fn::NativeFn;
+use runtime::list_fns::{self, list_index};
+use runtime::map_fns::{self, map_get, map_key};
+use runtime::gc_fns;
+use gc;
+use lox_map::{LoxMap, MapKey};
 use environment::Environment;
+use diagnostic::{self, Diagnostic};
 use std::rc::Rc;
 use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// How many Lox calls can be active at once before the next one fails with
+/// a stack overflow, on either backend, so that a script hits the limit at
+/// the same depth whichever runs it. Each tree-walker call takes several
+/// native frames, so allowing many more also takes a bigger native stack;
+/// the VM keeps its frames on the heap.
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+/// How big a native stack the tree walker needs to reach `max_depth` calls
+/// and report the next one as a stack overflow. A call nested in blocks,
+/// loops, and `try` takes far more than a bare one, and an unoptimized
+/// build takes several times more again, so this is generous; the pages
+/// are only touched if the recursion gets that deep.
+pub fn stack_size(max_depth: usize) -> usize {
+    max_depth.saturating_mul(STACK_PER_CALL).saturating_add(1 << 20)
+}
+
+/// The deepest `max_depth` that a native stack of `bytes` has room for.
+pub fn depth_for(bytes: usize) -> usize {
+    bytes.saturating_sub(1 << 20) / STACK_PER_CALL
+}
+
+/// The biggest native stack worth asking for without being told to, which
+/// caps how deep the tree walker can go by default.
+pub const MAX_STACK_SIZE: usize = 2 << 30;
+
+const STACK_PER_CALL: usize = if cfg!(debug_assertions) { 512 << 10 } else { 64 << 10 };
 
 pub enum Error {
     Return(LoxValue),
-    RuntimeError { token: Token, message: String },
+    Break,
+    Continue,
+    /// `trace` holds the calls that were active where the error was
+    /// raised, outermost first. It stays empty until the error leaves a
+    /// function.
+    RuntimeError {
+        token: Token,
+        message: String,
+        trace: Box<[(String, Token)]>,
+    },
+    /// A value raised by `throw`, carrying a trace like a runtime error's.
+    Throw {
+        token: Token,
+        value: LoxValue,
+        trace: Box<[(String, Token)]>,
+    },
 }
 
-pub struct ReturnValue(LoxValue);
-
 type IResult<T> = Result<T, Error>;
 
+/// Gets control before every statement and around every call to a Lox
+/// function while attached to an interpreter, which is how the debugger
+/// works. Hooks are detached while they run, so the interpreter they are
+/// handed can evaluate code without re-entering them.
+pub trait Hook {
+    fn statement(&mut self, interpreter: &mut Interpreter, statement: &Statement);
+    /// Called in the caller's environment. `paren` closes the call.
+    fn call(&mut self, interpreter: &mut Interpreter, function: &str, paren: &Token);
+    fn returned(&mut self, interpreter: &mut Interpreter);
+}
+
 pub struct Interpreter {
     pub environment: Environment,
-    locals: HashMap<usize, usize>,
+    locals: HashMap<usize, (usize, usize)>,
+    hook: Option<Box<dyn Hook>>,
+    /// The Lox functions being called and the tokens that closed their
+    /// calls, outermost first.
+    call_stack: Vec<(String, Token)>,
+    max_depth: usize,
+    /// Where `print` writes.
+    output: Box<dyn Write>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Interpreter {
         let mut env = Environment::new();
-        env.define("clock".to_string(), LoxValue::Fn(Rc::new(Clock)));
+        env.define("clock", LoxValue::Fn(Rc::new(Clock)));
+        let natives = [
+            NativeFn::new("len", 1, list_fns::len),
+            NativeFn::new("push", 2, list_fns::push),
+            NativeFn::new("pop", 1, list_fns::pop),
+            NativeFn::new("insert", 3, list_fns::insert),
+            NativeFn::new("remove", 2, list_fns::remove),
+            NativeFn::new("slice", 3, list_fns::slice),
+            NativeFn::new("keys", 1, map_fns::keys),
+            NativeFn::new("values", 1, map_fns::values),
+            NativeFn::new("has", 2, map_fns::has),
+            NativeFn::new("delete", 2, map_fns::delete),
+            NativeFn::new("gc", 0, gc_fns::gc),
+            NativeFn::new("gcStats", 0, gc_fns::gc_stats),
+        ];
+        for native in natives {
+            let name = native.name().to_string();
+            env.define(&name, LoxValue::Fn(Rc::new(native)));
+        }
         Interpreter {
             environment: env,
             locals: HashMap::new(),
+            hook: None,
+            call_stack: vec![],
+            max_depth: DEFAULT_MAX_DEPTH,
+            output: Box::new(io::stdout()),
         }
     }
 
+    /// Sends the output of `print` statements somewhere other than stdout.
+    pub fn print_to(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    pub fn attach(&mut self, hook: Box<dyn Hook>) {
+        self.hook = Some(hook);
+    }
+
+    /// Without a hook, this costs a single branch.
+    fn statement_hook(&mut self, s: &Statement) {
+        if let Some(mut hook) = self.hook.take() {
+            hook.statement(self, s);
+            self.hook = Some(hook);
+        }
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// Pushes a call to a Lox function onto the call stack, unless the stack
+    /// is already as deep as it may go.
+    pub fn enter(&mut self, function: &str, paren: &Token) -> IResult<()> {
+        if self.call_stack.len() >= self.max_depth {
+            return Err(Error::RuntimeError {
+                token: paren.clone(),
+                message: "Stack overflow.".to_string(),
+                trace: Box::new([]),
+            });
+        }
+        self.call_stack.push((function.to_string(), paren.clone()));
+        if let Some(mut hook) = self.hook.take() {
+            hook.call(self, function, paren);
+            self.hook = Some(hook);
+        }
+        Ok(())
+    }
+
+    /// Pops the innermost call. A runtime error leaving it takes the stack
+    /// as it was when the error was raised.
+    pub fn leave(&mut self, mut result: IResult<LoxValue>) -> IResult<LoxValue> {
+        if let Some(trace) = result.as_mut().err().and_then(|e| e.trace_mut()) {
+            if trace.is_empty() {
+                *trace = self.call_stack.clone().into_boxed_slice();
+            }
+        }
+        self.call_stack.pop();
+        if let Some(mut hook) = self.hook.take() {
+            hook.returned(self);
+            self.hook = Some(hook);
+        }
+        result
+    }
+
     pub fn interpret(&mut self, e: Program) -> IResult<()> {
         for s in e.statements {
             self.execute(&s)?;
@@ -42,44 +192,93 @@ impl Interpreter {
     }
 
     fn execute(&mut self, s: &Statement) -> IResult<()> {
+        if self.hook.is_some() {
+            self.statement_hook(s);
+        }
         match *s {
-            Statement::Block { ref statements } => {
+            Statement::Block { ref statements, .. } => {
                 self.environment.push();
                 let ret = self.execute_block(statements);
                 self.environment.pop();
                 ret
             }
-            Statement::Expression { ref expression } => {
-                self.evaluate(&*expression)?;
+            Statement::Class {
+                ref name,
+                ref superclass,
+                ref methods,
+                ..
+            } => {
+                let superclass = match *superclass {
+                    Some(ref expr) => match self.evaluate(expr)? {
+                        LoxValue::Class(class) => Some(class),
+                        _ => {
+                            return Err(Error::RuntimeError {
+                                token: expr.token().clone(),
+                                message: "Superclass must be a class.".to_string(),
+                                trace: Box::new([]),
+                            })
+                        }
+                    },
+                    None => None,
+                };
+
+                if let Some(ref class) = superclass {
+                    self.environment.push();
+                    self.environment
+                        .define("super", LoxValue::Class(Rc::clone(class)));
+                }
+
+                let mut class_methods = HashMap::new();
+                for method in methods {
+                    let function = LoxFunction::new(
+                        Rc::new(method.clone()),
+                        &self.environment,
+                        method.name.lexeme == "init",
+                    );
+                    class_methods.insert(method.name.lexeme.clone(), Rc::new(function));
+                }
+
+                if superclass.is_some() {
+                    self.environment.pop();
+                }
+
+                let class = LoxClass::new(name.lexeme.clone(), superclass, class_methods);
+                self.environment
+                    .define(&name.lexeme, LoxValue::Class(Rc::new(class)));
                 Ok(())
             }
-            Statement::Function(ref stmt) => {
-                let c = stmt.clone();
-                let function = LoxFunction::new(c, &self.environment);
+            Statement::Expression { ref expression, .. } => {
+                self.evaluate(expression)?;
+                Ok(())
+            }
+            Statement::Function { declaration: ref stmt, .. } => {
+                let c = Rc::new(stmt.clone());
+                let function = LoxFunction::new(c, &self.environment, false);
                 self.environment
-                    .define(stmt.name.lexeme.clone(), LoxValue::Fn(Rc::new(function)));
+                    .define(&stmt.name.lexeme, LoxValue::Fn(Rc::new(function)));
                 Ok(())
             }
             Statement::If {
                 ref condition,
                 ref then_branch,
                 ref else_branch,
+                ..
             } => {
-                if is_truthy(&self.evaluate(&*condition)?) {
-                    self.execute(&*then_branch)?;
+                if is_truthy(&self.evaluate(condition)?) {
+                    self.execute(then_branch)?;
                 } else if let Some(ref b) = *else_branch {
-                    self.execute(&*b)?;
+                    self.execute(b)?;
                 }
                 Ok(())
             }
-            Statement::Print { ref expression } => {
-                let val = self.evaluate(&*expression)?;
-                println!("{}", val);
+            Statement::Print { ref expression, .. } => {
+                let val = self.evaluate(expression)?;
+                writeln!(self.output, "{}", val).expect("failed printing to output");
                 Ok(())
             }
             Statement::Return { ref value, .. } => {
                 let val = match *value {
-                    Some(ref v) => self.evaluate(&*v)?,
+                    Some(ref v) => self.evaluate(v)?,
                     None => LoxValue::Nil,
                 };
                 Err(Error::Return(val))
@@ -87,30 +286,141 @@ impl Interpreter {
             Statement::Var {
                 ref name,
                 ref initializer,
+                ..
             } => {
                 let val = match *initializer {
-                    Some(ref v) => self.evaluate(&*v)?,
+                    Some(ref v) => self.evaluate(v)?,
                     None => LoxValue::Nil,
                 };
 
-                self.environment.define(name.lexeme.clone(), val.clone());
+                self.environment.define(&name.lexeme, val);
                 Ok(())
             }
+            Statement::For {
+                ref initializer,
+                ref condition,
+                ref increment,
+                ref body,
+                ..
+            } => {
+                self.environment.push();
+                let ret = match *initializer {
+                    Some(ref i) => self.execute(i),
+                    None => Ok(()),
+                };
+                let ret = ret.and_then(|()| {
+                    self.run_loop(condition.as_deref(), body, increment.as_deref())
+                });
+                self.environment.pop();
+                ret
+            }
             Statement::While {
                 ref condition,
                 ref body,
+                ..
+            } => self.run_loop(Some(condition), body, None),
+            Statement::Break { .. } => Err(Error::Break),
+            Statement::Continue { .. } => Err(Error::Continue),
+            Statement::Throw {
+                ref keyword,
+                ref value,
+                ..
+            } => Err(Error::Throw {
+                token: keyword.clone(),
+                value: self.evaluate(value)?,
+                trace: Box::new([]),
+            }),
+            Statement::Try {
+                ref body,
+                ref catch,
+                ref finally,
+                ..
             } => {
-                while is_truthy(&self.evaluate(&*condition)?) {
-                    self.execute(&*body)?;
+                self.environment.push();
+                let mut ret = self.execute_block(body);
+                self.environment.pop();
+                if let Some(ref catch) = *catch {
+                    if let Some(exception) = ret.as_ref().err().and_then(|e| self.exception(e)) {
+                        self.environment.push();
+                        self.environment.define(&catch.name.lexeme, exception);
+                        ret = self.execute_block(&catch.body);
+                        self.environment.pop();
+                    }
                 }
-                Ok(())
+                // whatever leaves the finally block wins over what left the
+                // rest of the statement
+                if let Some(ref finally) = *finally {
+                    self.environment.push();
+                    let finished = self.execute_block(finally);
+                    self.environment.pop();
+                    finished?;
+                }
+                ret
             }
         }
     }
 
-    // note that idx 0 would be globals
-    pub fn resolve(&mut self, e: &Expr, idx: usize) {
-        self.locals.insert(e.hash_key(), idx);
+    /// What a `catch` receives for an error: the thrown value, or for a
+    /// runtime error a map of its message, line and trace. `None` for the
+    /// signals that `return`, `break` and `continue` send through it.
+    fn exception(&self, error: &Error) -> Option<LoxValue> {
+        match *error {
+            Error::Throw { ref value, .. } => Some(value.clone()),
+            Error::RuntimeError {
+                ref token,
+                ref message,
+                ref trace,
+            } => {
+                // the trace is only filled in once the error leaves a function
+                let trace = if trace.is_empty() {
+                    self.call_stack.clone().into_boxed_slice()
+                } else {
+                    trace.clone()
+                };
+                let error = Error::RuntimeError {
+                    token: token.clone(),
+                    message: message.clone(),
+                    trace,
+                };
+                let trace = error.diagnostic()?.trace;
+                Some(exception_value(message, token.line, &trace))
+            }
+            Error::Return(_) | Error::Break | Error::Continue => None,
+        }
+    }
+
+    /// Runs a loop until its condition is falsey, or forever without one.
+    /// The increment runs after every iteration, including ones cut short by
+    /// `continue`.
+    fn run_loop(
+        &mut self,
+        condition: Option<&Expr>,
+        body: &Statement,
+        increment: Option<&Expr>,
+    ) -> IResult<()> {
+        loop {
+            if let Some(condition) = condition {
+                if !is_truthy(&self.evaluate(condition)?) {
+                    break;
+                }
+            }
+            gc::maybe_collect();
+            match self.execute(body) {
+                Ok(()) | Err(Error::Continue) => (),
+                Err(Error::Break) => break,
+                Err(e) => return Err(e),
+            }
+            if let Some(increment) = increment {
+                self.evaluate(increment)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records where a local lives: how many scopes out from the one the
+    /// expression is evaluated in, and its slot within that scope.
+    pub fn resolve(&mut self, e: &Expr, depth: usize, slot: usize) {
+        self.locals.insert(e.hash_key(), (depth, slot));
     }
 
     pub fn execute_block(&mut self, statements: &[Statement]) -> IResult<()> {
@@ -125,48 +435,195 @@ impl Interpreter {
         Ok(())
     }
 
-    fn evaluate(&mut self, e: &Expr) -> IResult<LoxValue> {
+    pub fn evaluate(&mut self, e: &Expr) -> IResult<LoxValue> {
         match *e {
             Expr::Assign {
                 ref name,
                 ref value,
             } => {
-                let val = self.evaluate(&*value)?;
-                let idx = self.locals.get(&e.hash_key()).unwrap_or(&0);
-                self.environment.assign_at(*idx, &name.clone(), val)
+                let val = self.evaluate(value)?;
+                match self.locals.get(&e.hash_key()) {
+                    Some(&(depth, slot)) => Ok(self.environment.assign_at(depth, slot, val)),
+                    None => self.environment.assign_global(name, val),
+                }
             }
             Expr::Call {
                 ref callee,
                 ref paren,
                 ref arguments,
             } => {
-                let c = self.evaluate(&*callee)?;
+                let c = self.evaluate(callee)?;
                 let mut args = Vec::new();
                 for a in arguments {
-                    let arg_val = self.evaluate(&*a)?;
+                    let arg_val = self.evaluate(a)?;
                     args.push(arg_val);
                 }
 
-                match into_callable(c) {
-                    Some(f) => f.call(self, args),
-                    None => Err(Error::RuntimeError {
+                let f = match into_callable(c) {
+                    Some(f) => f,
+                    None => {
+                        return Err(Error::RuntimeError {
+                            token: paren.clone(),
+                            message: "Expression is not callable".to_string(),
+                            trace: Box::new([]),
+                        })
+                    }
+                };
+                if !f.arity().accepts(args.len()) {
+                    return Err(Error::RuntimeError {
                         token: paren.clone(),
-                        message: format!("Expression is not callable"),
+                        message: format!(
+                            "Expected {} arguments but got {}.",
+                            f.arity(),
+                            args.len()
+                        ),
+                        trace: Box::new([]),
+                    });
+                }
+                f.call(self, paren, args)
+            }
+            Expr::Get {
+                ref object,
+                ref name,
+            } => match self.evaluate(object)? {
+                LoxValue::Instance(ref instance) => instance.get(name),
+                _ => Err(Error::RuntimeError {
+                    token: name.clone(),
+                    message: "Only instances have properties.".to_string(),
+                    trace: Box::new([]),
+                }),
+            },
+            Expr::Set {
+                ref object,
+                ref name,
+                ref value,
+            } => match self.evaluate(object)? {
+                LoxValue::Instance(ref instance) => {
+                    let val = self.evaluate(value)?;
+                    instance.set(name, val.clone());
+                    Ok(val)
+                }
+                _ => Err(Error::RuntimeError {
+                    token: name.clone(),
+                    message: "Only instances have fields.".to_string(),
+                    trace: Box::new([]),
+                }),
+            },
+            Expr::This { ref keyword } => self.look_up_variable(keyword, e),
+            Expr::Super { ref method, .. } => {
+                // `super` and `this` each get a scope to themselves, with the
+                // `this` scope just inside the `super` one
+                let depth = match self.locals.get(&e.hash_key()) {
+                    Some(&(depth, _)) => depth,
+                    None => unreachable!("resolver always resolves 'super' to a local"),
+                };
+                let superclass = self.environment.get_at(depth, 0);
+                let object = self.environment.get_at(depth - 1, 0);
+                let found = match superclass {
+                    LoxValue::Class(ref class) => class.find_method(&method.lexeme),
+                    _ => None,
+                };
+
+                match found {
+                    Some(m) => Ok(LoxValue::Fn(Rc::new(m.bind(object)))),
+                    None => Err(Error::RuntimeError {
+                        token: method.clone(),
+                        message: format!("Undefined property '{}'.", method.lexeme),
+                        trace: Box::new([]),
+                    }),
+                }
+            }
+            Expr::Index {
+                ref object,
+                ref bracket,
+                ref index,
+            } => {
+                let target = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                match target {
+                    LoxValue::List(ref list) => {
+                        let list = list.borrow();
+                        let idx = list_index(bracket, &index, list.len())?;
+                        Ok(list[idx].clone())
+                    }
+                    LoxValue::Map(ref map) => map_get(bracket, &map.borrow(), &index),
+                    _ => Err(Error::RuntimeError {
+                        token: bracket.clone(),
+                        message: "Only lists and maps can be indexed.".to_string(),
+                        trace: Box::new([]),
+                    }),
+                }
+            }
+            Expr::IndexSet {
+                ref object,
+                ref bracket,
+                ref index,
+                ref value,
+            } => {
+                let target = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                let val = self.evaluate(value)?;
+                match target {
+                    LoxValue::List(ref list) => {
+                        let mut list = list.borrow_mut();
+                        let idx = list_index(bracket, &index, list.len())?;
+                        list[idx] = val.clone();
+                        Ok(val)
+                    }
+                    LoxValue::Map(ref map) => {
+                        let key = map_key(bracket, &index)?;
+                        map.borrow_mut().insert(key, val.clone());
+                        Ok(val)
+                    }
+                    _ => Err(Error::RuntimeError {
+                        token: bracket.clone(),
+                        message: "Only lists and maps can be indexed.".to_string(),
+                        trace: Box::new([]),
                     }),
                 }
             }
+            Expr::List { ref elements, .. } => {
+                let mut values = Vec::with_capacity(elements.len());
+                for e in elements {
+                    values.push(self.evaluate(e)?);
+                }
+                Ok(LoxValue::list(values))
+            }
+            Expr::Lambda {
+                ref declaration,
+                ..
+            } => {
+                let function = LoxFunction::new(Rc::clone(declaration), &self.environment, false);
+                Ok(LoxValue::Fn(Rc::new(function)))
+            }
+            Expr::Map {
+                ref brace,
+                ref entries,
+            } => {
+                let mut map = LoxMap::default();
+                for (key, value) in entries {
+                    let k = self.evaluate(key)?;
+                    let k = map_key(brace, &k)?;
+                    map.insert(k, self.evaluate(value)?);
+                }
+                Ok(LoxValue::map(map))
+            }
             Expr::Literal { ref value, .. } => Ok(value.clone()),
-            Expr::Grouping { ref expression, .. } => self.evaluate(&*expression),
+            Expr::Grouping { ref expression, .. } => self.evaluate(expression),
             Expr::Unary {
                 ref right,
                 ref operator,
             } => {
-                let r_val = self.evaluate(&*right)?;
+                let r_val = self.evaluate(right)?;
                 match operator.token_type {
                     TokenType::Bang => Ok(LoxValue::Bool(!is_truthy(&r_val))),
                     TokenType::Minus => match r_val {
                         LoxValue::Number(n) => Ok(LoxValue::Number(-n)),
-                        _ => Ok(LoxValue::Nil),
+                        _ => Err(Error::RuntimeError {
+                            token: operator.clone(),
+                            message: "Operand must be a number.".to_string(),
+                            trace: Box::new([]),
+                        }),
                     },
                     _ => Ok(LoxValue::Nil),
                 }
@@ -176,8 +633,8 @@ impl Interpreter {
                 ref right,
                 ref operator,
             } => {
-                let l_val = self.evaluate(&*left)?;
-                let r_val = self.evaluate(&*right)?;
+                let l_val = self.evaluate(left)?;
+                let r_val = self.evaluate(right)?;
                 check_number_operands(operator, &l_val, &r_val)?;
 
                 match (operator.token_type.clone(), l_val, r_val) {
@@ -215,12 +672,10 @@ impl Interpreter {
                     (TokenType::Plus, LoxValue::String(a), LoxValue::String(b)) => {
                         Ok(LoxValue::String(format!("{}{}", a, b)))
                     }
-                    (_, a, b) => Err(Error::RuntimeError {
+                    (_, _, _) => Err(Error::RuntimeError {
                         token: operator.clone(),
-                        message: format!(
-                            "There was some problem applying {:?} to operands {:?} and {:?}",
-                            operator, a, b
-                        ),
+                        message: "Operands must be two numbers or two strings.".to_string(),
+                        trace: Box::new([]),
                     }),
                 }
             }
@@ -230,16 +685,16 @@ impl Interpreter {
                 ref right,
                 ref operator,
             } => {
-                let l = self.evaluate(&*left)?;
-                if operator.token_type == TokenType::Or && is_truthy(&l) {
-                    return Ok(l);
-                }
-
-                if !is_truthy(&l) {
+                let l = self.evaluate(left)?;
+                if operator.token_type == TokenType::Or {
+                    if is_truthy(&l) {
+                        return Ok(l);
+                    }
+                } else if !is_truthy(&l) {
                     return Ok(l);
                 }
 
-                self.evaluate(&*right)
+                self.evaluate(right)
             }
 
             Expr::Variable { ref name } => self.look_up_variable(name, e),
@@ -247,21 +702,86 @@ impl Interpreter {
     }
 
     fn look_up_variable(&mut self, name: &Token, e: &Expr) -> Result<LoxValue, Error> {
-        // if locals don't have the expr then it must be a global (top of the environment stack)
-        let idx = self.locals.get(&e.hash_key()).unwrap_or(&0);
-        self.environment.get_at(*idx, name)
+        // if locals don't have the expr then it must be a global
+        match self.locals.get(&e.hash_key()) {
+            Some(&(depth, slot)) => Ok(self.environment.get_at(depth, slot)),
+            None => self.environment.get_global(name),
+        }
+    }
+}
+
+impl Error {
+    /// Control-flow signals that escape to the top level have no location to
+    /// report, so only runtime errors and uncaught exceptions produce a
+    /// diagnostic.
+    pub fn diagnostic(&self) -> Option<Diagnostic> {
+        let (token, message, trace) = match *self {
+            Error::RuntimeError {
+                ref token,
+                ref message,
+                ref trace,
+            } => (token, message.clone(), trace),
+            Error::Throw {
+                ref token,
+                ref value,
+                ref trace,
+            } => (token, diagnostic::uncaught_message(value, value.message()), trace),
+            Error::Return(_) | Error::Break | Error::Continue => return None,
+        };
+        let mut diagnostic = Diagnostic::new("runtime error", token, &message);
+        // each call was at the line its callee was called from
+        let mut line = token.line;
+        for (function, call_site) in trace.iter().rev() {
+            diagnostic.trace.push((function.clone(), line));
+            line = call_site.line;
+        }
+        if !trace.is_empty() {
+            diagnostic.trace.push(("<script>".to_string(), line));
+        }
+        Some(diagnostic)
+    }
+
+    fn trace_mut(&mut self) -> Option<&mut Box<[(String, Token)]>> {
+        match *self {
+            Error::RuntimeError { ref mut trace, .. } | Error::Throw { ref mut trace, .. } => {
+                Some(trace)
+            }
+            Error::Return(_) | Error::Break | Error::Continue => None,
+        }
     }
 }
 
+/// A caught runtime error as Lox sees it. The trace lists its calls
+/// innermost first, like the one printed for an uncaught error.
+fn exception_value(message: &str, line: usize, trace: &[(String, usize)]) -> LoxValue {
+    let trace = trace
+        .iter()
+        .map(|(function, line)| LoxValue::String(format!("{} (line {})", function, line)))
+        .collect();
+    let mut map = LoxMap::default();
+    map.insert(MapKey::String("message".to_string()), LoxValue::String(message.to_string()));
+    map.insert(MapKey::String("line".to_string()), LoxValue::Number(line as f64));
+    map.insert(MapKey::String("trace".to_string()), LoxValue::list(trace));
+    LoxValue::map(map)
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::RuntimeError {
                 ref token,
                 ref message,
+                ..
             } => write!(f, "Runtime error at {:?}: {}", token, message),
+            Error::Throw {
+                ref token,
+                ref value,
+                ..
+            } => write!(f, "Uncaught exception at {:?}: {}", token, value),
 
             Error::Return(ref v) => write!(f, "Return {}", v),
+            Error::Break => write!(f, "Break"),
+            Error::Continue => write!(f, "Continue"),
         }
     }
 }
@@ -278,7 +798,8 @@ fn check_number_operands(t: &Token, a: &LoxValue, b: &LoxValue) -> IResult<()> {
             (&LoxValue::Number(_), &LoxValue::Number(_)) => Ok(()),
             (_, _) => Err(Error::RuntimeError {
                 token: t.clone(),
-                message: format!("Operands {:?} and {:?} must both be numbers.", a, b),
+                message: "Operands must be numbers.".to_string(),
+                trace: Box::new([]),
             }),
         },
         _ => Ok(()),
@@ -297,9 +818,10 @@ fn is_truthy(e: &LoxValue) -> bool {
     }
 }
 
-fn into_callable(e: LoxValue) -> Option<Rc<LoxCallable>> {
+fn into_callable(e: LoxValue) -> Option<Rc<dyn LoxCallable>> {
     match e {
         LoxValue::Fn(f) => Some(f),
+        LoxValue::Class(c) => Some(c),
         _ => None,
     }
 }
@@ -1,3 +1,5 @@
+extern crate serde_json;
+
 pub mod scanner;
 pub mod token;
 pub mod ast;
@@ -5,7 +7,24 @@ pub mod parser;
 pub mod interpreter;
 pub mod environment;
 pub mod resolver;
+pub mod diagnostic;
+pub mod chunk;
+pub mod compiler;
+pub mod vm;
+pub mod session;
+pub mod emit;
+pub mod formatter;
+pub mod lint;
+pub mod framing;
+pub mod lsp;
+pub mod debugger;
 mod lox_value;
+mod lox_map;
+mod lox_list;
 mod lox_callable;
 mod lox_function;
+mod lox_class;
+mod lox_instance;
 mod runtime;
+mod gc;
+mod cycle;
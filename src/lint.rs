@@ -0,0 +1,164 @@
+use diagnostic::Diagnostic;
+use interpreter::Interpreter;
+use parser::Parser;
+use resolver::Resolver;
+use scanner::Scanner;
+use token::Token;
+
+/// A kind of problem `lint` looks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// A local variable, function or class that nothing reads.
+    UnusedLocal,
+    /// A parameter with the same name as a variable it hides.
+    ShadowedParameter,
+    /// Statements after a `return`, `break` or `continue` in the same block.
+    UnreachableCode,
+    /// Assigning to a name that isn't declared anywhere, which fails at
+    /// runtime.
+    UndeclaredGlobal,
+}
+
+pub const RULES: [Rule; 4] = [
+    Rule::UnusedLocal,
+    Rule::ShadowedParameter,
+    Rule::UnreachableCode,
+    Rule::UndeclaredGlobal,
+];
+
+impl Rule {
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::UnusedLocal => "unused-local",
+            Rule::ShadowedParameter => "shadowed-parameter",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::UndeclaredGlobal => "undeclared-global",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        RULES.iter().cloned().find(|r| r.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Allow,
+    Warn,
+    /// Reported as an error, so that a script can be failed on it.
+    Deny,
+}
+
+#[derive(Debug)]
+pub struct Warning {
+    pub rule: Rule,
+    pub token: Token,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn diagnostic(&self, level: Level) -> Diagnostic {
+        let kind = if level == Level::Deny { "error" } else { "warning" };
+        let message = format!("{} [{}]", self.message, self.rule.name());
+        Diagnostic::new(kind, &self.token, &message)
+    }
+}
+
+/// The level each rule is reported at. Every rule warns by default.
+#[derive(Debug, Clone)]
+pub struct Config {
+    levels: [Level; 4],
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            levels: [Level::Warn; 4],
+        }
+    }
+}
+
+impl Config {
+    pub fn set(&mut self, rule: Rule, level: Level) {
+        self.levels[rule as usize] = level;
+    }
+
+    pub fn level(&self, rule: Rule) -> Level {
+        self.levels[rule as usize]
+    }
+}
+
+/// Checks `source` without running it. Returns the problems found, in source
+/// order, with the level they are reported at, or the errors that stopped
+/// the program from parsing or resolving.
+pub fn lint(source: &str, config: &Config) -> Result<Vec<(Level, Warning)>, Vec<Diagnostic>> {
+    let mut scanner = Scanner::new(source.to_string());
+    scanner.scan_tokens();
+    let program = Parser::new(scanner.tokens)
+        .parse()
+        .map_err(|errors| errors.iter().map(|e| e.diagnostic()).collect::<Vec<_>>())?;
+
+    let mut resolver = Resolver::new(Interpreter::new());
+    resolver.collect_warnings();
+    resolver.resolve(&program).map_err(|e| vec![e.diagnostic()])?;
+
+    let mut warnings = resolver.warnings();
+    warnings.sort_by_key(|w| w.token.position);
+    Ok(warnings
+        .into_iter()
+        .map(|w| (config.level(w.rule), w))
+        .filter(|&(level, _)| level != Level::Allow)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use lint::{lint, Config, Level, Rule};
+
+    fn rules(source: &str, config: &Config) -> Vec<(Rule, usize)> {
+        lint(source, config)
+            .unwrap()
+            .iter()
+            .map(|(_, w)| (w.rule, w.token.line))
+            .collect()
+    }
+
+    #[test]
+    fn reports_each_rule() {
+        let source = "var a = 1;\n\
+                      fun f(a, _b) {\n\
+                      \x20 var unused = 1;\n\
+                      \x20 return a;\n\
+                      \x20 print a;\n\
+                      }\n\
+                      typo = 2;\n";
+        assert_eq!(
+            rules(source, &Config::default()),
+            vec![
+                (Rule::ShadowedParameter, 2),
+                (Rule::UnusedLocal, 3),
+                (Rule::UnreachableCode, 5),
+                (Rule::UndeclaredGlobal, 7),
+            ]
+        );
+
+        let mut config = Config::default();
+        config.set(Rule::UnusedLocal, Level::Allow);
+        config.set(Rule::UndeclaredGlobal, Level::Allow);
+        assert_eq!(
+            rules(source, &config),
+            vec![(Rule::ShadowedParameter, 2), (Rule::UnreachableCode, 5)]
+        );
+    }
+
+    #[test]
+    fn points_at_the_start_of_unreachable_statements() {
+        let source = "fun f() {\n  return;\n  print 1 + 2;\n  {}\n}\n";
+        let locations: Vec<(usize, usize)> = lint(source, &Config::default())
+            .unwrap()
+            .iter()
+            .map(|(level, w)| w.diagnostic(*level).location(source))
+            .collect();
+        assert_eq!(locations, vec![(3, 3)]);
+    }
+}
@@ -1,17 +1,58 @@
 use lox_value::LoxValue;
 use std::fmt;
 use interpreter::{Error, Interpreter};
-pub trait LoxCallable: fmt::Debug {
+use token::Token;
+use gc::Trace;
+/// How many arguments a callable accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Arity {
+    pub min: usize,
+    pub max: usize,
+    /// Whether any number of arguments past `max` is accepted too.
+    pub variadic: bool,
+}
+
+impl Arity {
+    pub fn exactly(count: usize) -> Arity {
+        Arity {
+            min: count,
+            max: count,
+            variadic: false,
+        }
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        self.min <= count && (count <= self.max || self.variadic)
+    }
+}
+
+/// Reads as the number in "Expected _ arguments".
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.variadic {
+            write!(f, "at least {}", self.min)
+        } else if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else {
+            write!(f, "{} to {}", self.min, self.max)
+        }
+    }
+}
+
+pub trait LoxCallable: fmt::Debug + Trace {
+    /// `paren` is the closing parenthesis of the call expression, used to
+    /// locate errors raised by the callee itself.
     fn call(
         &self,
         interpreter: &mut Interpreter,
+        paren: &Token,
         arguments: Vec<LoxValue>,
     ) -> Result<LoxValue, Error>;
-    fn arity(&self) -> usize;
+    fn arity(&self) -> Arity;
     fn name(&self) -> &str;
 }
 
-impl fmt::Display for LoxCallable {
+impl fmt::Display for dyn LoxCallable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "<fn {}>", self.name())
     }
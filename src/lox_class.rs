@@ -0,0 +1,103 @@
+use lox_value::LoxValue;
+use interpreter::{Error, Interpreter};
+use lox_callable::{Arity, LoxCallable};
+use lox_function::LoxFunction;
+use lox_instance::LoxInstance;
+use token::Token;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use gc::{self, Trace, Tracer};
+
+/// A class is cheap to clone so that every instance can carry its own handle
+/// to the shared method table.
+#[derive(Clone)]
+pub struct LoxClass {
+    name: String,
+    superclass: Option<Rc<LoxClass>>,
+    methods: Rc<HashMap<String, Rc<LoxFunction>>>,
+}
+
+impl LoxClass {
+    pub fn new(
+        name: String,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<String, Rc<LoxFunction>>,
+    ) -> LoxClass {
+        LoxClass {
+            name,
+            superclass,
+            methods: Rc::new(methods),
+        }
+    }
+
+    /// Looks the method up on this class first, then walks the superclass
+    /// chain.
+    /// Reports the references held by this handle. Instances embed a handle
+    /// rather than pointing at the class, so they trace it the same way.
+    pub fn trace_handle(&self, tracer: &mut Tracer) {
+        if let Some(ref superclass) = self.superclass {
+            tracer.edge(superclass);
+        }
+        tracer.edge(&self.methods);
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        match self.methods.get(name) {
+            Some(method) => Some(Rc::clone(method)),
+            None => self.superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name)),
+        }
+    }
+}
+
+impl LoxCallable for LoxClass {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<LoxValue>,
+    ) -> Result<LoxValue, Error> {
+        let instance = Rc::new(LoxInstance::new(self.clone()));
+        gc::track(&instance);
+        let instance = LoxValue::Instance(instance);
+        if let Some(initializer) = self.find_method("init") {
+            initializer
+                .bind(instance.clone())
+                .call(interpreter, paren, arguments)?;
+        }
+        Ok(instance)
+    }
+
+    fn arity(&self) -> Arity {
+        match self.find_method("init") {
+            Some(initializer) => initializer.arity(),
+            None => Arity::exactly(0),
+        }
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Debug for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<class {}>", self.name)
+    }
+}
+
+impl Trace for LoxClass {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.trace_handle(tracer);
+    }
+}
+
+impl Trace for HashMap<String, Rc<LoxFunction>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for method in self.values() {
+            tracer.edge(method);
+        }
+    }
+}
@@ -1,32 +1,84 @@
 use lox_value::LoxValue;
 use interpreter::{Error, Interpreter};
-use lox_callable::LoxCallable;
+use lox_callable::{Arity, LoxCallable};
 use ast::FunctionDeclaration;
 use environment::Environment;
+use token::Token;
 use std::mem::replace;
+use std::rc::Rc;
+use gc::{self, Trace, Tracer};
 
 #[derive(Debug)]
 pub struct LoxFunction {
-    declaration: FunctionDeclaration,
+    declaration: Rc<FunctionDeclaration>,
     closure: Environment,
+    is_initializer: bool,
 }
 
 impl LoxFunction {
-    pub fn new(declaration: FunctionDeclaration, env: &Environment) -> LoxFunction {
+    pub fn new(
+        declaration: Rc<FunctionDeclaration>,
+        env: &Environment,
+        is_initializer: bool,
+    ) -> LoxFunction {
         let closure = Environment::from(env);
         LoxFunction {
             declaration,
             closure,
+            is_initializer,
         }
     }
+
+    /// Returns a copy of this method whose closure has `this` bound to the
+    /// given instance, one scope outside the method's parameters.
+    pub fn bind(&self, instance: LoxValue) -> LoxFunction {
+        let mut closure = Environment::from(&self.closure);
+        closure.push();
+        closure.define("this", instance);
+        LoxFunction {
+            declaration: Rc::clone(&self.declaration),
+            closure,
+            is_initializer: self.is_initializer,
+        }
+    }
+
+    /// Defines the parameters in the callee's new scope, evaluating the
+    /// defaults of any the call left out and collecting extra arguments for
+    /// the rest parameter. The arity has already been checked.
+    fn bind_arguments(&self, interpreter: &mut Interpreter, arguments: Vec<LoxValue>) -> Result<(), Error> {
+        let mut arguments = arguments.into_iter();
+        for p in &self.declaration.parameters {
+            let value = match (arguments.next(), p.default.as_ref()) {
+                (Some(argument), _) => argument,
+                (None, Some(default)) => interpreter.evaluate(default)?,
+                (None, None) => LoxValue::Nil,
+            };
+            interpreter.environment.define(&p.name.lexeme, value);
+        }
+        if let Some(ref rest) = self.declaration.rest {
+            let rest_arguments = LoxValue::list(arguments.collect());
+            interpreter.environment.define(&rest.lexeme, rest_arguments);
+        }
+        Ok(())
+    }
+
+    /// Only valid on a bound method, where `this` is the sole slot of the
+    /// innermost closure frame.
+    fn this(&self) -> LoxValue {
+        self.closure.get_at(0, 0)
+    }
 }
 
 impl LoxCallable for LoxFunction {
     fn call(
         &self,
         interpreter: &mut Interpreter,
+        paren: &Token,
         arguments: Vec<LoxValue>,
     ) -> Result<LoxValue, Error> {
+        gc::maybe_collect();
+        interpreter.enter(self.name(), paren)?;
+
         // we need to evaluate the function in the context of its closure,
         // not whatever the interpreter's current environment is.
         let old_env = replace(
@@ -35,29 +87,31 @@ impl LoxCallable for LoxFunction {
         );
 
         interpreter.environment.push();
-        for (p, arg) in self.declaration
-            .parameters
-            .iter()
-            .zip(arguments.into_iter())
-        {
-            interpreter.environment.define(p.lexeme.clone(), arg);
-        }
-
-        let ret = match interpreter.execute_block(&self.declaration.body) {
-            Ok(_) => Ok(LoxValue::Nil),
-            Err(Error::Return(v)) => Ok(v),
+        let ret = match self.bind_arguments(interpreter, arguments) {
+            Ok(()) => match interpreter.execute_block(&self.declaration.body) {
+                Ok(_) | Err(Error::Return(_)) if self.is_initializer => Ok(self.this()),
+                Ok(_) => Ok(LoxValue::Nil),
+                Err(Error::Return(v)) => Ok(v),
+                Err(e) => Err(e),
+            },
             Err(e) => Err(e),
         };
         interpreter.environment.pop();
 
         // swap the environment back
         interpreter.environment = old_env;
-        ret
+        interpreter.leave(ret)
     }
-    fn arity(&self) -> usize {
-        self.declaration.parameters.len()
+    fn arity(&self) -> Arity {
+        self.declaration.arity()
     }
     fn name(&self) -> &str {
         &self.declaration.name.lexeme
     }
 }
+
+impl Trace for LoxFunction {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.closure.trace(tracer);
+    }
+}
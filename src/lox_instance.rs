@@ -0,0 +1,73 @@
+use lox_value::LoxValue;
+use interpreter::Error;
+use lox_callable::LoxCallable;
+use lox_class::LoxClass;
+use token::Token;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use gc::{Trace, Tracer};
+
+pub struct LoxInstance {
+    class: LoxClass,
+    fields: RefCell<HashMap<String, LoxValue>>,
+}
+
+impl LoxInstance {
+    pub fn new(class: LoxClass) -> LoxInstance {
+        LoxInstance {
+            class,
+            fields: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Fields shadow methods. Methods are bound to this instance on every
+    /// access, so `var m = obj.method;` keeps working after `obj` goes away.
+    pub fn get(self: &Rc<Self>, name: &Token) -> Result<LoxValue, Error> {
+        if let Some(value) = self.fields.borrow().get(&name.lexeme) {
+            return Ok(value.clone());
+        }
+
+        match self.class.find_method(&name.lexeme) {
+            Some(method) => {
+                let bound = method.bind(LoxValue::Instance(Rc::clone(self)));
+                Ok(LoxValue::Fn(Rc::new(bound)))
+            }
+            None => Err(Error::RuntimeError {
+                token: name.clone(),
+                message: format!("Undefined property '{}'.", name.lexeme),
+                trace: Box::new([]),
+            }),
+        }
+    }
+
+    pub fn set(&self, name: &Token, value: LoxValue) {
+        self.fields.borrow_mut().insert(name.lexeme.clone(), value);
+    }
+}
+
+impl fmt::Display for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} instance", self.class.name())
+    }
+}
+
+impl fmt::Debug for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{} instance>", self.class.name())
+    }
+}
+
+impl Trace for LoxInstance {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.class.trace_handle(tracer);
+        for value in self.fields.borrow().values() {
+            value.trace(tracer);
+        }
+    }
+
+    fn clear(&self) {
+        self.fields.borrow_mut().clear();
+    }
+}
@@ -0,0 +1,87 @@
+//! The list natives and index checks, shared by both backends. These are
+//! generic over the item type; each backend only converts its own values
+//! and wraps the error messages in its own error type. An index is `None`
+//! when the value given wasn't a number.
+
+use std::ops::Range;
+
+/// The message for a native given something other than a list.
+pub fn expected(function: &str) -> String {
+    format!("{}() expects a list.", function)
+}
+
+pub fn pop<T>(list: &mut Vec<T>) -> Result<T, String> {
+    list.pop().ok_or_else(|| "Cannot pop from an empty list.".to_string())
+}
+
+/// Inserting at the list's length appends.
+pub fn insert<T>(list: &mut Vec<T>, index: Option<f64>, value: T) -> Result<(), String> {
+    let index = boundary(index, list.len())?;
+    list.insert(index, value);
+    Ok(())
+}
+
+pub fn remove<T>(list: &mut Vec<T>, index: Option<f64>) -> Result<T, String> {
+    let index = self::index(index, list.len())?;
+    Ok(list.remove(index))
+}
+
+/// Returns a new list holding the items from `start` up to but not
+/// including `end`.
+pub fn slice<T: Clone>(list: &[T], start: Option<f64>, end: Option<f64>) -> Result<Vec<T>, String> {
+    let range = range(start, end, list.len())?;
+    Ok(list[range].to_vec())
+}
+
+/// Converts an index into the position of one of a list's `len` items.
+pub fn index(index: Option<f64>, len: usize) -> Result<usize, String> {
+    position(index, len, len)
+}
+
+/// Converts an index into a position between items, where `len` itself,
+/// the end of the list, is allowed too.
+fn boundary(index: Option<f64>, len: usize) -> Result<usize, String> {
+    position(index, len + 1, len)
+}
+
+fn range(start: Option<f64>, end: Option<f64>, len: usize) -> Result<Range<usize>, String> {
+    let start = boundary(start, len)?;
+    let end = boundary(end, len)?;
+    if start > end {
+        return Err(format!("Slice start {} is greater than end {}.", start, end));
+    }
+    Ok(start..end)
+}
+
+/// Positions below `bound` are allowed; `len` is only for the message.
+fn position(index: Option<f64>, bound: usize, len: usize) -> Result<usize, String> {
+    match index {
+        Some(n) if n.fract() == 0.0 => {
+            if n >= 0.0 && n < bound as f64 {
+                Ok(n as usize)
+            } else {
+                Err(format!(
+                    "Index {} is out of range for a list of length {}.",
+                    n, len
+                ))
+            }
+        }
+        _ => Err("List index must be an integer.".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lox_list::{boundary, index, range};
+
+    #[test]
+    fn reports_the_real_length() {
+        assert_eq!(index(Some(2.0), 2), Err("Index 2 is out of range for a list of length 2.".to_string()));
+        assert_eq!(boundary(Some(2.0), 2), Ok(2));
+        assert_eq!(boundary(Some(3.0), 2), Err("Index 3 is out of range for a list of length 2.".to_string()));
+        assert_eq!(range(Some(0.0), Some(3.0), 2), Err("Index 3 is out of range for a list of length 2.".to_string()));
+        assert_eq!(range(Some(1.0), Some(2.0), 2), Ok(1..2));
+        assert_eq!(index(Some(0.5), 2), Err("List index must be an integer.".to_string()));
+        assert_eq!(index(None, 2), Err("List index must be an integer.".to_string()));
+    }
+}
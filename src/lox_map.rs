@@ -0,0 +1,164 @@
+use cycle;
+use std::collections::HashMap;
+use std::fmt;
+
+/// The hashable subset of Lox values. Numbers are keyed by their bits, with
+/// `-0` folded into `0` so that keys which compare equal hash equally.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    String(String),
+    Number(u64),
+    Bool(bool),
+    Nil,
+}
+
+const INVALID_KEY: &str = "Map keys must be strings, numbers, booleans, or nil.";
+
+impl MapKey {
+    pub fn number(n: f64) -> MapKey {
+        if n == 0.0 {
+            MapKey::Number(0f64.to_bits())
+        } else {
+            MapKey::Number(n.to_bits())
+        }
+    }
+}
+
+impl fmt::Display for MapKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MapKey::String(ref s) => write!(f, "{}", s),
+            MapKey::Number(bits) => write!(f, "{}", f64::from_bits(bits)),
+            MapKey::Bool(b) => write!(f, "{}", b),
+            MapKey::Nil => write!(f, "nil"),
+        }
+    }
+}
+
+/// A map that remembers insertion order, so printing a map or asking for its
+/// keys gives the same answer on every run and on both backends.
+#[derive(Debug, Clone)]
+pub struct LoxMap<V> {
+    entries: Vec<(MapKey, V)>,
+    index: HashMap<MapKey, usize>,
+}
+
+impl<V> Default for LoxMap<V> {
+    fn default() -> LoxMap<V> {
+        LoxMap {
+            entries: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+}
+
+impl<V> LoxMap<V> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<&V> {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn contains_key(&self, key: &MapKey) -> bool {
+        self.index.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: MapKey, value: V) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<V> {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for (k, _) in &self.entries[i..] {
+            *self.index.get_mut(k).unwrap() -= 1;
+        }
+        Some(value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.index.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&MapKey, &V)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    /// Reads `key`, treating a missing key as an error rather than `nil` so
+    /// that typos don't pass silently; `has()` checks first.
+    pub fn lookup(&self, key: Option<MapKey>) -> Result<&V, String> {
+        let key = valid(key)?;
+        self.get(&key)
+            .ok_or_else(|| format!("Key '{}' is not in the map.", key))
+    }
+
+    pub fn has(&self, key: Option<MapKey>) -> Result<bool, String> {
+        Ok(self.contains_key(&valid(key)?))
+    }
+
+    /// Returns whether the key was present.
+    pub fn delete(&mut self, key: Option<MapKey>) -> Result<bool, String> {
+        Ok(self.remove(&valid(key)?).is_some())
+    }
+}
+
+/// Checks a key converted from a value, which is `None` when the value
+/// can't be a key.
+pub fn valid(key: Option<MapKey>) -> Result<MapKey, String> {
+    key.ok_or_else(|| INVALID_KEY.to_string())
+}
+
+/// The message for a native given something other than a map.
+pub fn expected(function: &str) -> String {
+    format!("{}() expects a map.", function)
+}
+
+impl<V: fmt::Display> fmt::Display for LoxMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        cycle::print_once(self, f, "{...}", |f| {
+            write!(f, "{{")?;
+            for (i, (key, value)) in self.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: {}", key, value)?;
+            }
+            write!(f, "}}")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lox_map::{LoxMap, MapKey};
+
+    #[test]
+    fn remove_keeps_insertion_order() {
+        let mut map = LoxMap::default();
+        map.insert(MapKey::String("a".to_string()), 1);
+        map.insert(MapKey::number(2.0), 2);
+        map.insert(MapKey::Nil, 3);
+        assert_eq!(map.remove(&MapKey::number(2.0)), Some(2));
+        map.insert(MapKey::Bool(true), 4);
+
+        let keys: Vec<String> = map.iter().map(|(k, _)| k.to_string()).collect();
+        assert_eq!(keys, vec!["a", "nil", "true"]);
+        assert_eq!(map.get(&MapKey::Nil), Some(&3));
+
+        map.insert(MapKey::number(-0.0), 5);
+        assert_eq!(map.get(&MapKey::number(0.0)), Some(&5));
+    }
+}
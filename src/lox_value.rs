@@ -1,4 +1,10 @@
 use lox_callable::LoxCallable;
+use lox_class::LoxClass;
+use lox_instance::LoxInstance;
+use lox_map::{LoxMap, MapKey};
+use cycle;
+use gc::{self, Trace, Tracer};
+use std::cell::RefCell;
 use std::fmt;
 use std::rc::Rc;
 #[derive(Debug, Clone)]
@@ -7,17 +13,87 @@ pub enum LoxValue {
     Number(f64),
     Bool(bool),
     Nil,
-    Fn(Rc<LoxCallable>),
+    Fn(Rc<dyn LoxCallable>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<LoxInstance>),
+    List(Rc<RefCell<Vec<LoxValue>>>),
+    Map(Rc<RefCell<LoxMap<LoxValue>>>),
+}
+
+impl LoxValue {
+    pub fn list(values: Vec<LoxValue>) -> LoxValue {
+        let list = Rc::new(RefCell::new(values));
+        gc::track(&list);
+        LoxValue::List(list)
+    }
+
+    pub fn map(map: LoxMap<LoxValue>) -> LoxValue {
+        let map = Rc::new(RefCell::new(map));
+        gc::track(&map);
+        LoxValue::Map(map)
+    }
+
+    pub fn trace(&self, tracer: &mut Tracer) {
+        match *self {
+            LoxValue::Fn(ref f) => tracer.edge_dyn(Rc::clone(f) as Rc<dyn Trace>),
+            LoxValue::Class(ref class) => tracer.edge(class),
+            LoxValue::Instance(ref instance) => tracer.edge(instance),
+            LoxValue::List(ref list) => tracer.edge(list),
+            LoxValue::Map(ref map) => tracer.edge(map),
+            LoxValue::String(_) | LoxValue::Number(_) | LoxValue::Bool(_) | LoxValue::Nil => (),
+        }
+    }
+
+    /// Returns `None` for values that cannot be used as map keys.
+    pub fn to_key(&self) -> Option<MapKey> {
+        match *self {
+            LoxValue::String(ref s) => Some(MapKey::String(s.clone())),
+            LoxValue::Number(n) => Some(MapKey::number(n)),
+            LoxValue::Bool(b) => Some(MapKey::Bool(b)),
+            LoxValue::Nil => Some(MapKey::Nil),
+            _ => None,
+        }
+    }
+
+    pub fn from_key(key: &MapKey) -> LoxValue {
+        match *key {
+            MapKey::String(ref s) => LoxValue::String(s.clone()),
+            MapKey::Number(bits) => LoxValue::Number(f64::from_bits(bits)),
+            MapKey::Bool(b) => LoxValue::Bool(b),
+            MapKey::Nil => LoxValue::Nil,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match *self {
+            LoxValue::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// The `message` of a thrown map, if it has a string one.
+    pub fn message(&self) -> Option<String> {
+        if let LoxValue::Map(ref map) = *self {
+            if let Some(LoxValue::String(s)) = map.borrow().get(&MapKey::String("message".to_string())) {
+                return Some(s.clone());
+            }
+        }
+        None
+    }
 }
 
 impl PartialEq for LoxValue {
     fn eq(&self, other: &LoxValue) -> bool {
         match (self, other) {
-            (&LoxValue::String(ref a), &LoxValue::String(ref b)) => a == b,
-            (&LoxValue::Number(ref a), &LoxValue::Number(ref b)) => a == b,
-            (&LoxValue::Bool(ref a), &LoxValue::Bool(ref b)) => a == b,
+            (LoxValue::String(a), LoxValue::String(b)) => a == b,
+            (LoxValue::Number(a), LoxValue::Number(b)) => a == b,
+            (LoxValue::Bool(a), LoxValue::Bool(b)) => a == b,
             (&LoxValue::Nil, &LoxValue::Nil) => true,
-            (&LoxValue::Fn(ref a), &LoxValue::Fn(ref b)) => Rc::ptr_eq(a, b),
+            (LoxValue::Fn(a), LoxValue::Fn(b)) => Rc::ptr_eq(a, b),
+            (LoxValue::Class(a), LoxValue::Class(b)) => Rc::ptr_eq(a, b),
+            (LoxValue::Instance(a), LoxValue::Instance(b)) => Rc::ptr_eq(a, b),
+            (LoxValue::List(a), LoxValue::List(b)) => Rc::ptr_eq(a, b),
+            (LoxValue::Map(a), LoxValue::Map(b)) => Rc::ptr_eq(a, b),
             _ => false,
         }
     }
@@ -29,8 +105,75 @@ impl fmt::Display for LoxValue {
             LoxValue::String(ref s) => write!(f, "{}", s),
             LoxValue::Number(ref s) => write!(f, "{}", s),
             LoxValue::Bool(ref s) => write!(f, "{}", s),
-            LoxValue::Nil => write!(f, "{}", "nil"),
+            LoxValue::Nil => write!(f, "nil"),
             LoxValue::Fn(ref fun) => write!(f, "{}", fun),
+            LoxValue::Class(ref class) => write!(f, "{}", class.name()),
+            LoxValue::Instance(ref instance) => write!(f, "{}", instance),
+            LoxValue::List(ref list) => cycle::print_once(Rc::as_ptr(list), f, "[...]", |f| {
+                write!(f, "[")?;
+                for (i, value) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }),
+            LoxValue::Map(ref map) => write!(f, "{}", map.borrow()),
+        }
+    }
+}
+
+impl Trace for RefCell<Vec<LoxValue>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for value in self.borrow().iter() {
+            value.trace(tracer);
+        }
+    }
+
+    fn clear(&self) {
+        self.borrow_mut().clear();
+    }
+}
+
+impl Trace for RefCell<LoxMap<LoxValue>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for (_, value) in self.borrow().iter() {
+            value.trace(tracer);
+        }
+    }
+
+    fn clear(&self) {
+        self.borrow_mut().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lox_map::{LoxMap, MapKey};
+    use lox_value::LoxValue;
+
+    #[test]
+    fn prints_a_list_that_holds_itself() {
+        let list = LoxValue::list(vec![LoxValue::Number(1.0)]);
+        if let LoxValue::List(ref l) = list {
+            l.borrow_mut().push(list.clone());
+            l.borrow_mut().push(LoxValue::list(vec![list.clone()]));
+        }
+        assert_eq!(list.to_string(), "[1, [...], [[...]]]");
+        // a list that appears twice without holding itself prints in full
+        let inner = LoxValue::list(vec![LoxValue::Nil]);
+        let outer = LoxValue::list(vec![inner.clone(), inner]);
+        assert_eq!(outer.to_string(), "[[nil], [nil]]");
+    }
+
+    #[test]
+    fn prints_a_map_that_holds_itself() {
+        let map = LoxValue::map(LoxMap::default());
+        if let LoxValue::Map(ref m) = map {
+            m.borrow_mut().insert(MapKey::String("self".to_string()), map.clone());
+            m.borrow_mut().insert(MapKey::String("list".to_string()), LoxValue::list(vec![map.clone()]));
         }
+        assert_eq!(map.to_string(), "{self: {...}, list: [{...}]}");
     }
 }
@@ -0,0 +1,423 @@
+use ast::{Expr, FunctionDeclaration, Program, Statement};
+use diagnostic::Diagnostic;
+use lint::Level;
+use lox_callable::Arity;
+use lox_value::LoxValue;
+use parser::Parser;
+use resolver::Resolver;
+use scanner::Scanner;
+use std::collections::HashMap;
+use token::{Token, TokenType, KEYWORDS};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Class,
+    Function,
+    Method,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompletionKind {
+    Keyword,
+    Function,
+    Variable,
+}
+
+pub struct Symbol {
+    pub name: Token,
+    pub kind: SymbolKind,
+    /// The class a method belongs to.
+    pub container: Option<String>,
+}
+
+/// What a name refers to: a declaration in the document, or a global it
+/// doesn't declare, like a native function.
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Declared(usize),
+    Global(String),
+}
+
+/// Everything the server knows about one version of a document. Positions
+/// are character offsets into its text.
+pub struct Analysis {
+    /// Position of the first character of each line.
+    lines: Vec<usize>,
+    pub diagnostics: Vec<(Diagnostic, Severity)>,
+    pub symbols: Vec<Symbol>,
+    identifiers: Vec<String>,
+    /// Every occurrence of a variable's name, declarations included.
+    references: Vec<(Token, Target)>,
+    /// Hover text for each declaration, by position.
+    descriptions: HashMap<usize, String>,
+    /// Arity of each native function.
+    natives: Vec<(String, Arity)>,
+}
+
+impl Analysis {
+    /// `resolver` must collect warnings and references. It is reused from
+    /// one analysis to the next, along with its interpreter, whose globals
+    /// are the natives.
+    pub fn new(source: &str, resolver: &mut Resolver) -> Analysis {
+        let mut lines = vec![0];
+        for (i, c) in source.chars().enumerate() {
+            if c == '\n' {
+                lines.push(i + 1);
+            }
+        }
+
+        let natives = resolver
+            .interpreter
+            .environment
+            .globals()
+            .into_iter()
+            .filter_map(|(name, value)| match value {
+                LoxValue::Fn(ref f) => Some((name, f.arity())),
+                _ => None,
+            })
+            .collect();
+
+        let mut scanner = Scanner::new(source.to_string());
+        scanner.scan_tokens();
+        let mut identifiers: Vec<String> = scanner
+            .tokens
+            .iter()
+            .filter(|t| t.token_type == TokenType::Identifier)
+            .map(|t| t.lexeme.clone())
+            .collect();
+        identifiers.sort();
+        identifiers.dedup();
+
+        let mut analysis = Analysis {
+            lines,
+            diagnostics: vec![],
+            symbols: vec![],
+            identifiers,
+            references: vec![],
+            descriptions: HashMap::new(),
+            natives,
+        };
+
+        // what did parse is still worth indexing, but resolving it would
+        // report the names the broken statements declare as missing
+        let (program, errors) = Parser::new(scanner.tokens).parse_recovering();
+        analysis.diagnostics = errors
+            .iter()
+            .map(|e| (e.diagnostic(), Severity::Error))
+            .collect();
+        let resolved = resolver.resolve(&program);
+        let warnings = resolver.warnings();
+        if errors.is_empty() {
+            if let Err(e) = resolved {
+                analysis.diagnostics.push((e.diagnostic(), Severity::Error));
+            }
+            for warning in warnings {
+                let diagnostic = warning.diagnostic(Level::Warn);
+                analysis.diagnostics.push((diagnostic, Severity::Warning));
+            }
+        }
+
+        let globals = global_declarations(&program);
+        analysis.references = resolver
+            .references()
+            .into_iter()
+            .map(|r| {
+                let declaration = match r.declaration {
+                    Some(ref d) => Some(d),
+                    None => globals.get(&r.name.lexeme),
+                };
+                let target = match declaration {
+                    Some(d) => Target::Declared(d.position),
+                    None => Target::Global(r.name.lexeme.clone()),
+                };
+                (r.name, target)
+            })
+            .collect();
+        analysis.references.sort_by_key(|(name, _)| name.position);
+        for s in &program.statements {
+            analysis.index_statement(s);
+        }
+        analysis
+    }
+
+    /// Zero-based line and character of a position.
+    pub fn location(&self, position: usize) -> (usize, usize) {
+        let line = match self.lines.binary_search(&position) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        (line, position - self.lines[line])
+    }
+
+    pub fn position(&self, line: usize, character: usize) -> usize {
+        self.lines.get(line).map_or(usize::MAX, |start| start + character)
+    }
+
+    /// The reference under the cursor. A cursor just past a name is still on
+    /// it.
+    fn reference_at(&self, position: usize) -> Option<&(Token, Target)> {
+        self.references.iter().find(|&(name, _)| {
+            name.position <= position && position <= name.position + name.lexeme.chars().count()
+        })
+    }
+
+    pub fn definition(&self, position: usize) -> Option<&Token> {
+        match self.reference_at(position)?.1 {
+            Target::Declared(declaration) => self
+                .references
+                .iter()
+                .map(|(name, _)| name)
+                .find(|name| name.position == declaration),
+            Target::Global(_) => None,
+        }
+    }
+
+    pub fn references(&self, position: usize, include_declaration: bool) -> Vec<&Token> {
+        let target = match self.reference_at(position) {
+            Some((_, target)) => target,
+            None => return vec![],
+        };
+        self.references
+            .iter()
+            .filter(|&(_, t)| t == target)
+            .map(|(name, _)| name)
+            .filter(|name| include_declaration || *target != Target::Declared(name.position))
+            .collect()
+    }
+
+    /// Markdown describing the name under the cursor, and the name itself.
+    pub fn hover(&self, position: usize) -> Option<(String, &Token)> {
+        let (name, target) = self.reference_at(position)?;
+        let description = match *target {
+            Target::Declared(declaration) => self.descriptions.get(&declaration)?.clone(),
+            Target::Global(ref global) => {
+                let &(_, arity) = self.natives.iter().find(|&(n, _)| n == global)?;
+                describe(&format!("native fun {}", global), Some(arity))
+            }
+        };
+        Some((description, name))
+    }
+
+    pub fn completions(&self) -> Vec<(String, CompletionKind)> {
+        let keywords = KEYWORDS
+            .iter()
+            .map(|&(k, _)| (k.to_string(), CompletionKind::Keyword));
+        let natives = self
+            .natives
+            .iter()
+            .map(|(n, _)| (n.clone(), CompletionKind::Function));
+        let identifiers = self
+            .identifiers
+            .iter()
+            .filter(|i| !self.natives.iter().any(|(n, _)| n == *i))
+            .map(|i| (i.clone(), CompletionKind::Variable));
+        keywords.chain(natives).chain(identifiers).collect()
+    }
+
+    fn index_statement(&mut self, s: &Statement) {
+        match *s {
+            Statement::Block { ref statements, .. } => {
+                for s in statements {
+                    self.index_statement(s);
+                }
+            }
+            Statement::Class {
+                ref name,
+                ref methods,
+                ..
+            } => {
+                let init = methods.iter().find(|m| m.name.lexeme == "init");
+                let arity = init.map_or(Arity::exactly(0), |i| i.arity());
+                let parameters = init.map_or(String::new(), parameter_list);
+                let description = describe(&format!("class {}{}", name.lexeme, parameters), Some(arity));
+                self.descriptions.insert(name.position, description);
+                self.symbols.push(Symbol {
+                    name: name.clone(),
+                    kind: SymbolKind::Class,
+                    container: None,
+                });
+                for method in methods {
+                    self.index_function(method, Some(&name.lexeme));
+                }
+            }
+            Statement::Function { ref declaration, .. } => self.index_function(declaration, None),
+            Statement::If {
+                ref then_branch,
+                ref else_branch,
+                ..
+            } => {
+                self.index_statement(then_branch);
+                if let Some(ref e) = *else_branch {
+                    self.index_statement(e);
+                }
+            }
+            Statement::For {
+                ref initializer,
+                ref body,
+                ..
+            } => {
+                if let Some(ref i) = *initializer {
+                    self.index_statement(i);
+                }
+                self.index_statement(body);
+            }
+            Statement::While { ref body, .. } => self.index_statement(body),
+            Statement::Try {
+                ref body,
+                ref catch,
+                ref finally,
+                ..
+            } => {
+                let catch_body = catch.as_ref().map_or(&[][..], |c| &c.body[..]);
+                let finally = finally.as_ref().map_or(&[][..], |f| &f[..]);
+                if let Some(ref catch) = *catch {
+                    let description = describe(&format!("exception {}", catch.name.lexeme), None);
+                    self.descriptions.insert(catch.name.position, description);
+                }
+                for s in body.iter().chain(catch_body).chain(finally) {
+                    self.index_statement(s);
+                }
+            }
+            Statement::Var {
+                ref name,
+                ref initializer,
+                ..
+            } => {
+                let description = match initializer.as_deref() {
+                    Some(Expr::Lambda {
+                        ref declaration, ..
+                    }) => {
+                        self.index_parameters(declaration);
+                        let signature = format!("var {} = fun {}", name.lexeme, parameter_list(declaration));
+                        describe(&signature, Some(declaration.arity()))
+                    }
+                    _ => describe(&format!("var {}", name.lexeme), None),
+                };
+                self.descriptions.insert(name.position, description);
+            }
+            Statement::Break { .. }
+            | Statement::Continue { .. }
+            | Statement::Expression { .. }
+            | Statement::Print { .. }
+            | Statement::Return { .. }
+            | Statement::Throw { .. } => (),
+        }
+    }
+
+    fn index_function(&mut self, declaration: &FunctionDeclaration, class: Option<&str>) {
+        let name = &declaration.name;
+        let signature = format!("fun {}{}", name.lexeme, parameter_list(declaration));
+        let description = describe(&signature, Some(declaration.arity()));
+        self.descriptions.insert(name.position, description);
+        self.symbols.push(Symbol {
+            name: name.clone(),
+            kind: if class.is_some() {
+                SymbolKind::Method
+            } else {
+                SymbolKind::Function
+            },
+            container: class.map(|c| c.to_string()),
+        });
+        self.index_parameters(declaration);
+        for s in &declaration.body {
+            self.index_statement(s);
+        }
+    }
+
+    fn index_parameters(&mut self, declaration: &FunctionDeclaration) {
+        let names = declaration.parameters.iter().map(|p| &p.name);
+        for name in names.chain(declaration.rest.as_ref()) {
+            let description = describe(&format!("parameter {}", name.lexeme), None);
+            self.descriptions.insert(name.position, description);
+        }
+    }
+}
+
+fn parameter_list(declaration: &FunctionDeclaration) -> String {
+    let mut names: Vec<String> = declaration
+        .parameters
+        .iter()
+        .map(|p| match p.default {
+            Some(_) => format!("{} = …", p.name.lexeme),
+            None => p.name.lexeme.clone(),
+        })
+        .collect();
+    if let Some(ref rest) = declaration.rest {
+        names.push(format!("...{}", rest.lexeme));
+    }
+    format!("({})", names.join(", "))
+}
+
+fn describe(signature: &str, arity: Option<Arity>) -> String {
+    let code = format!("```lox\n{}\n```", signature);
+    match arity {
+        Some(arity) if arity == Arity::exactly(1) => format!("{}\nTakes 1 argument.", code),
+        Some(arity) => format!("{}\nTakes {} arguments.", code, arity),
+        None => code,
+    }
+}
+
+/// The first top-level declaration of each global.
+fn global_declarations(program: &Program) -> HashMap<String, Token> {
+    let mut globals = HashMap::new();
+    for s in &program.statements {
+        let name = match *s {
+            Statement::Var { ref name, .. } | Statement::Class { ref name, .. } => name,
+            Statement::Function { ref declaration, .. } => &declaration.name,
+            _ => continue,
+        };
+        globals
+            .entry(name.lexeme.clone())
+            .or_insert_with(|| name.clone());
+    }
+    globals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Analysis;
+    use interpreter::Interpreter;
+    use resolver::Resolver;
+
+    fn analyze(source: &str) -> Analysis {
+        let mut resolver = Resolver::new(Interpreter::new());
+        resolver.collect_warnings();
+        resolver.collect_references();
+        Analysis::new(source, &mut resolver)
+    }
+
+    #[test]
+    fn follows_locals_and_globals_to_their_declarations() {
+        let source = "fun add(a, b) { return a + b; }\n{ var a = add(1, 2); print a; }\nprint add;";
+        let analysis = analyze(source);
+        assert!(analysis.diagnostics.is_empty());
+
+        let use_of_a = source.find("print a").unwrap() + 6;
+        let declaration = analysis.definition(use_of_a).unwrap();
+        assert_eq!(analysis.location(declaration.position), (1, 6));
+
+        let add = analysis.references(4, true);
+        let lines: Vec<usize> = add.iter().map(|t| analysis.location(t.position).0).collect();
+        assert_eq!(lines, vec![0, 1, 2]);
+
+        let (hover, _) = analysis.hover(source.find("add(1").unwrap()).unwrap();
+        assert!(hover.ends_with("Takes 2 arguments."));
+    }
+
+    #[test]
+    fn indexes_what_parsed_around_a_syntax_error() {
+        let source = "fun add(a, b) { return a + b; }\nvar x = ;\nprint add(1, 2);";
+        let analysis = analyze(source);
+        assert_eq!(analysis.diagnostics.len(), 1);
+        let symbols: Vec<&str> = analysis.symbols.iter().map(|s| s.name.lexeme.as_str()).collect();
+        assert_eq!(symbols, vec!["add"]);
+        let call = source.rfind("add").unwrap();
+        assert_eq!(analysis.definition(call).map(|d| d.position), Some(4));
+    }
+}
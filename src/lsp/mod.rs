@@ -0,0 +1,268 @@
+//! A Language Server Protocol server, spoken over a pair of streams (stdin
+//! and stdout for `lox lsp`).
+//!
+//! Documents are synced in full and re-analyzed on every change. Positions
+//! count characters rather than UTF-16 code units, which only matters on
+//! lines with characters outside the Basic Multilingual Plane.
+
+mod analysis;
+
+use self::analysis::{Analysis, CompletionKind, Severity, SymbolKind};
+use framing::{read_message, write_message};
+use interpreter::Interpreter;
+use resolver::Resolver;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use token::Token;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+type RequestResult = Result<Value, (i64, String)>;
+
+struct Server<W: Write> {
+    output: W,
+    documents: HashMap<String, Analysis>,
+    /// Shared by every analysis, so that re-analyzing a document doesn't
+    /// set up a new interpreter each time.
+    resolver: Resolver,
+    shutdown: bool,
+}
+
+/// Handles messages until the client sends `exit` or closes `input`, and
+/// returns the exit code: 0 if the client asked to shut down first.
+pub fn serve<R: BufRead, W: Write>(mut input: R, output: W) -> io::Result<i32> {
+    let mut resolver = Resolver::new(Interpreter::new());
+    resolver.collect_warnings();
+    resolver.collect_references();
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+        resolver,
+        shutdown: false,
+    };
+    while let Some(body) = read_message(&mut input)? {
+        let message: Value = match serde_json::from_str(&body) {
+            Ok(message) => message,
+            Err(e) => {
+                server.respond(Value::Null, Err((PARSE_ERROR, e.to_string())))?;
+                continue;
+            }
+        };
+        let method = message["method"].as_str().unwrap_or("");
+        if method == "exit" {
+            break;
+        }
+        let params = &message["params"];
+        match message.get("id") {
+            Some(id) if !method.is_empty() => {
+                let result = server.request(method, params);
+                server.respond(id.clone(), result)?;
+            }
+            // a response to a request we never make
+            Some(_) => (),
+            None => server.notification(method, params)?,
+        }
+    }
+    Ok(if server.shutdown { 0 } else { 1 })
+}
+
+impl<W: Write> Server<W> {
+    fn send(&mut self, message: Value) -> io::Result<()> {
+        write_message(&mut self.output, &message)
+    }
+
+    fn respond(&mut self, id: Value, result: RequestResult) -> io::Result<()> {
+        let message = match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {"code": code, "message": message},
+            }),
+        };
+        self.send(message)
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> io::Result<()> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            // only full syncs are offered, so the last change is the whole text
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return self.publish_diagnostics(&uri);
+            }
+            _ => return Ok(()),
+        };
+        if let Some(text) = text {
+            self.documents.insert(uri.clone(), Analysis::new(text, &mut self.resolver));
+            self.publish_diagnostics(&uri)?;
+        }
+        Ok(())
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics: Vec<Value> = match self.documents.get(uri) {
+            Some(analysis) => analysis
+                .diagnostics
+                .iter()
+                .map(|(d, severity)| {
+                    json!({
+                        "range": range(analysis, d.position, d.length),
+                        "severity": match *severity {
+                            Severity::Error => 1,
+                            Severity::Warning => 2,
+                        },
+                        "source": "lox",
+                        "message": format!("{}: {}", d.kind, d.message),
+                    })
+                })
+                .collect(),
+            None => vec![],
+        };
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": diagnostics},
+        }))
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> RequestResult {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": {"name": "lox", "version": env!("CARGO_PKG_VERSION")},
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => {
+                let (uri, analysis, position) = self.document_position(params)?;
+                Ok(match analysis.definition(position) {
+                    Some(token) => location(uri, analysis, token),
+                    None => Value::Null,
+                })
+            }
+            "textDocument/references" => {
+                let (uri, analysis, position) = self.document_position(params)?;
+                let include_declaration = params["context"]["includeDeclaration"]
+                    .as_bool()
+                    .unwrap_or(true);
+                let locations: Vec<Value> = analysis
+                    .references(position, include_declaration)
+                    .into_iter()
+                    .map(|token| location(uri, analysis, token))
+                    .collect();
+                Ok(Value::from(locations))
+            }
+            "textDocument/hover" => {
+                let (_, analysis, position) = self.document_position(params)?;
+                Ok(match analysis.hover(position) {
+                    Some((markdown, token)) => json!({
+                        "contents": {"kind": "markdown", "value": markdown},
+                        "range": token_range(analysis, token),
+                    }),
+                    None => Value::Null,
+                })
+            }
+            "textDocument/documentSymbol" => {
+                let (uri, analysis) = self.document(params)?;
+                let symbols: Vec<Value> = analysis
+                    .symbols
+                    .iter()
+                    .map(|symbol| {
+                        let mut information = json!({
+                            "name": symbol.name.lexeme,
+                            "kind": match symbol.kind {
+                                SymbolKind::Class => 5,
+                                SymbolKind::Method => 6,
+                                SymbolKind::Function => 12,
+                            },
+                            "location": location(uri, analysis, &symbol.name),
+                        });
+                        if let Some(ref container) = symbol.container {
+                            information["containerName"] = Value::from(container.as_str());
+                        }
+                        information
+                    })
+                    .collect();
+                Ok(Value::from(symbols))
+            }
+            "textDocument/completion" => {
+                let (_, analysis) = self.document(params)?;
+                let items: Vec<Value> = analysis
+                    .completions()
+                    .into_iter()
+                    .map(|(label, kind)| {
+                        json!({
+                            "label": label,
+                            "kind": match kind {
+                                CompletionKind::Function => 3,
+                                CompletionKind::Variable => 6,
+                                CompletionKind::Keyword => 14,
+                            },
+                        })
+                    })
+                    .collect();
+                Ok(Value::from(items))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'.", method))),
+        }
+    }
+
+    fn document<'a>(&'a self, params: &'a Value) -> Result<(&'a str, &'a Analysis), (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        match self.documents.get(uri) {
+            Some(analysis) => Ok((uri, analysis)),
+            None => Err((INVALID_PARAMS, format!("Unknown document '{}'.", uri))),
+        }
+    }
+
+    fn document_position<'a>(
+        &'a self,
+        params: &'a Value,
+    ) -> Result<(&'a str, &'a Analysis, usize), (i64, String)> {
+        let (uri, analysis) = self.document(params)?;
+        let line = params["position"]["line"].as_u64();
+        let character = params["position"]["character"].as_u64();
+        match (line, character) {
+            (Some(line), Some(character)) => {
+                let position = analysis.position(line as usize, character as usize);
+                Ok((uri, analysis, position))
+            }
+            _ => Err((INVALID_PARAMS, "Expected a position.".to_string())),
+        }
+    }
+}
+
+fn range(analysis: &Analysis, position: usize, length: usize) -> Value {
+    let (start_line, start_character) = analysis.location(position);
+    let (end_line, end_character) = analysis.location(position + length);
+    json!({
+        "start": {"line": start_line, "character": start_character},
+        "end": {"line": end_line, "character": end_character},
+    })
+}
+
+fn token_range(analysis: &Analysis, token: &Token) -> Value {
+    range(analysis, token.position, token.lexeme.chars().count())
+}
+
+fn location(uri: &str, analysis: &Analysis, token: &Token) -> Value {
+    json!({"uri": uri, "range": token_range(analysis, token)})
+}
@@ -1,70 +1,555 @@
 extern crate lox;
-extern crate rprompt;
+extern crate home;
+extern crate rustyline;
 
-use std::fs::File;
+use std::collections::BTreeSet;
+use std::fs::{self, File};
 use std::io::BufReader;
 use std::io::prelude::*;
 use std::env;
+use std::process;
+use std::thread;
+use std::time::Instant;
 
 use lox::parser::Parser;
-use lox::interpreter::Interpreter;
+use lox::ast::AstPrint;
+use lox::debugger::{self, console::Console};
+use lox::emit;
+use lox::formatter;
+use lox::lint::{self, Level};
+use lox::interpreter::{self, Interpreter, DEFAULT_MAX_DEPTH};
 use lox::scanner::Scanner;
 use lox::resolver::Resolver;
+use lox::diagnostic::Diagnostic;
+use lox::interpreter::Error;
+use lox::compiler::Compiler;
+use lox::vm::Vm;
+use lox::session::{self, Session};
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Editor, Helper};
+
+/// What to print instead of running the script.
+#[derive(Debug, PartialEq)]
+enum Dump {
+    Ast,
+    TokensJson,
+    AstJson,
+}
+
+#[derive(Debug, PartialEq)]
+enum Backend {
+    Tree,
+    Vm,
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: lox [--backend=tree|vm] [--dump-ast | --emit=tokens-json|ast-json]");
+    eprintln!("           [--max-depth=CALLS] [--stack-size=MB] [script]");
+    eprintln!("       lox fmt [--check] [file...]");
+    eprintln!("       lox lint [--allow=RULE] [--warn=RULE] [--deny=RULE] file...");
+    eprintln!("       lox lsp");
+    eprintln!("       lox debug [--break=LINE...] file");
+    eprintln!("       lox dap");
+    process::exit(64);
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    match args.get(1) {
-        Some(file_path) => interpret_file(file_path),
-        None => repl(),
+    match env::args().nth(1).as_deref() {
+        Some("fmt") => return format_files(env::args().skip(2).collect()),
+        Some("lint") => return lint_files(env::args().skip(2).collect()),
+        Some("debug") => return debug_file(env::args().skip(2).collect()),
+        Some("lsp") => {
+            let stdin = std::io::stdin();
+            match lox::lsp::serve(stdin.lock(), std::io::stdout()) {
+                Ok(code) => process::exit(code),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(74);
+                }
+            }
+        }
+        Some("dap") => {
+            // the program runs on the tree-walking interpreter, so it needs
+            // the same stack as `lox debug`
+            return run_with_stack(interpreter::stack_size(DEFAULT_MAX_DEPTH), || {
+                match debugger::dap::serve(std::io::stdin().lock(), std::io::stdout()) {
+                    Ok(code) => process::exit(code),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        process::exit(74);
+                    }
+                }
+            });
+        }
+        _ => (),
+    }
+
+    let mut backend = Backend::Tree;
+    let mut dump = None;
+    let mut file_path = None;
+    let mut max_depth = None;
+    let mut stack_size = None;
+    for arg in env::args().skip(1) {
+        if let Some(depth) = arg.strip_prefix("--max-depth=") {
+            max_depth = Some(depth.parse().unwrap_or_else(|_| usage()));
+        } else if let Some(size) = arg.strip_prefix("--stack-size=") {
+            // a thread needs some stack, and the size in bytes has to fit
+            let bytes = size.parse::<usize>().ok().filter(|&mb| mb > 0).and_then(|mb| mb.checked_mul(1 << 20));
+            stack_size = Some(bytes.unwrap_or_else(|| usage()));
+        } else if let Some(name) = arg.strip_prefix("--backend=") {
+            backend = match name {
+                "tree" => Backend::Tree,
+                "vm" => Backend::Vm,
+                other => {
+                    eprintln!("Unknown backend '{}'.", other);
+                    usage();
+                }
+            };
+        } else if arg == "--dump-ast" {
+            dump = Some(Dump::Ast);
+        } else if let Some(format) = arg.strip_prefix("--emit=") {
+            dump = match format {
+                "tokens-json" => Some(Dump::TokensJson),
+                "ast-json" => Some(Dump::AstJson),
+                other => {
+                    eprintln!("Unknown emit format '{}'.", other);
+                    usage();
+                }
+            };
+        } else if arg.starts_with("--") || file_path.is_some() {
+            usage();
+        } else {
+            file_path = Some(arg);
+        }
+    }
+
+    // the tree-walking interpreter recurses natively for each Lox call, so
+    // it runs on a thread with room for as many calls as it allows
+    if backend == Backend::Tree {
+        let depth = max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+        let room = interpreter::depth_for(stack_size.unwrap_or(interpreter::MAX_STACK_SIZE));
+        if depth > room {
+            match stack_size {
+                Some(bytes) => eprintln!(
+                    "A {} MB stack only has room for {} calls; raise --stack-size or lower --max-depth.",
+                    bytes >> 20,
+                    room
+                ),
+                None => eprintln!("The tree backend allows a --max-depth of at most {}.", room),
+            }
+            usage();
+        }
+        stack_size = stack_size.or(Some(interpreter::stack_size(depth)));
+    }
+    let run = move || match (file_path, dump) {
+        (Some(file_path), Some(dump)) => print_dump(&file_path, &dump),
+        (Some(file_path), None) => interpret_file(&file_path, &backend, max_depth),
+        (None, Some(_)) => usage(),
+        (None, None) if backend == Backend::Tree => repl(max_depth),
+        (None, None) => {
+            eprintln!("The vm backend can only run script files.");
+            usage();
+        }
+    };
+    match stack_size {
+        Some(bytes) => run_with_stack(bytes, run),
+        None => run(),
     }
 }
 
-fn interpret_file(path: &str) {
+/// Runs `run` on a thread with a native stack of `bytes`.
+fn run_with_stack<F: FnOnce() + Send + 'static>(bytes: usize, run: F) {
+    let thread = thread::Builder::new()
+        .stack_size(bytes)
+        .spawn(run)
+        .unwrap_or_else(|e| {
+            eprintln!("Could not reserve a {} MB stack for the interpreter: {}", bytes >> 20, e);
+            process::exit(71);
+        });
+    if thread.join().is_err() {
+        process::exit(70);
+    }
+}
+
+fn read_file(path: &str) -> String {
     let file = File::open(path).unwrap();
     let mut buf_reader = BufReader::new(file);
     let mut contents = String::new();
     buf_reader.read_to_string(&mut contents).unwrap();
+    contents
+}
 
-    let mut scanner = Scanner::new(contents);
+fn print_dump(path: &str, dump: &Dump) {
+    let contents = read_file(path);
+    let mut scanner = Scanner::new(contents.clone());
+    scanner.scan_tokens();
+    if *dump == Dump::TokensJson {
+        return println!("{}", emit::tokens_json(&scanner.tokens));
+    }
+
+    match Parser::new(scanner.tokens).parse() {
+        Ok(ast) if *dump == Dump::AstJson => println!("{}", emit::ast_json(&ast, &contents)),
+        Ok(ast) => println!("{}", ast.pretty_print()),
+        Err(errors) => {
+            for e in errors {
+                report(path, &contents, &e.diagnostic());
+            }
+            process::exit(65);
+        }
+    }
+}
+
+/// Rewrites each file in the canonical layout, or with `--check` lists the
+/// ones that aren't and fails. Without files, formats stdin to stdout.
+fn format_files(args: Vec<String>) {
+    let check = args.iter().any(|a| a == "--check");
+    let files: Vec<&String> = args.iter().filter(|a| *a != "--check").collect();
+    if files.iter().any(|f| f.starts_with("--")) {
+        usage();
+    }
+
+    if files.is_empty() {
+        let mut source = String::new();
+        if let Err(e) = std::io::stdin().read_to_string(&mut source) {
+            eprintln!("Could not read stdin: {}", e);
+            process::exit(74);
+        }
+        match formatter::format(&source) {
+            Ok(formatted) if check => process::exit(if formatted == source { 0 } else { 1 }),
+            Ok(formatted) => print!("{}", formatted),
+            Err(diagnostics) => {
+                for d in diagnostics {
+                    report("<stdin>", &source, &d);
+                }
+                process::exit(65);
+            }
+        }
+        return;
+    }
+
+    let mut status = 0;
+    for path in files {
+        let source = read_file(path);
+        match formatter::format(&source) {
+            Ok(ref formatted) if *formatted == source => (),
+            Ok(_) if check => {
+                println!("{}", path);
+                status = 1;
+            }
+            Ok(formatted) => {
+                if let Err(e) = fs::write(path, formatted) {
+                    eprintln!("Could not write {}: {}", path, e);
+                    process::exit(74);
+                }
+            }
+            Err(diagnostics) => {
+                for d in diagnostics {
+                    report(path, &source, &d);
+                }
+                status = 65;
+            }
+        }
+    }
+    process::exit(status);
+}
+
+/// Reports lint warnings for each file. Fails if any rule set to `deny`
+/// fires or a file doesn't compile.
+fn lint_files(args: Vec<String>) {
+    let mut config = lint::Config::default();
+    let mut files = vec![];
+    for arg in args {
+        let setting = [
+            ("--allow=", Level::Allow),
+            ("--warn=", Level::Warn),
+            ("--deny=", Level::Deny),
+        ]
+        .iter()
+        .find_map(|&(prefix, level)| arg.strip_prefix(prefix).map(|rule| (rule.to_string(), level)));
+        match setting {
+            Some((name, level)) => match lint::Rule::from_name(&name) {
+                Some(rule) => config.set(rule, level),
+                None => {
+                    let names: Vec<&str> = lint::RULES.iter().map(|r| r.name()).collect();
+                    eprintln!("Unknown lint rule '{}'. Rules are: {}.", name, names.join(", "));
+                    process::exit(64);
+                }
+            },
+            None if arg.starts_with("--") => usage(),
+            None => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        usage();
+    }
+
+    let mut status = 0;
+    for path in files {
+        let source = read_file(&path);
+        match lint::lint(&source, &config) {
+            Ok(warnings) => {
+                for (level, warning) in warnings {
+                    report(&path, &source, &warning.diagnostic(level));
+                    if level == Level::Deny {
+                        status = status.max(1);
+                    }
+                }
+            }
+            Err(diagnostics) => {
+                for d in diagnostics {
+                    report(&path, &source, &d);
+                }
+                status = 65;
+            }
+        }
+    }
+    process::exit(status);
+}
+
+/// Runs a script under the console debugger, paused before its first
+/// statement.
+fn debug_file(args: Vec<String>) {
+    let mut breakpoints = BTreeSet::new();
+    let mut file_path = None;
+    for arg in args {
+        if let Some(line) = arg.strip_prefix("--break=") {
+            match line.parse() {
+                Ok(line) => breakpoints.insert(line),
+                Err(_) => usage(),
+            };
+        } else if arg.starts_with("--") || file_path.is_some() {
+            usage();
+        } else {
+            file_path = Some(arg);
+        }
+    }
+    let path = file_path.unwrap_or_else(|| usage());
+    let source = read_file(&path);
+    run_with_stack(interpreter::stack_size(DEFAULT_MAX_DEPTH), move || {
+        let console = Console::new(std::io::stdin().lock(), std::io::stdout());
+        if let Err(diagnostics) = debugger::run(Interpreter::new(), &source, console, breakpoints, true) {
+            for d in &diagnostics {
+                report(&path, &source, d);
+            }
+            process::exit(if diagnostics.iter().any(|d| d.kind == "runtime error") { 70 } else { 65 });
+        }
+    });
+}
+
+fn interpret_file(path: &str, backend: &Backend, max_depth: Option<usize>) {
+    let contents = read_file(path);
+
+    let mut scanner = Scanner::new(contents.clone());
     scanner.scan_tokens();
 
     let mut parser = Parser::new(scanner.tokens);
     match parser.parse() {
         Ok(ast) => {
-            // println!("{}", ast.pretty_print());
             let mut interpreter = Interpreter::new();
+            if let Some(depth) = max_depth {
+                interpreter.set_max_depth(depth);
+            }
             let mut resolver = Resolver::new(interpreter);
-            match resolver.resolve(&ast) {
-                Ok(_) => match resolver.interpreter.interpret(ast) {
+            if let Err(e) = resolver.resolve(&ast) {
+                return report(path, &contents, &e.diagnostic());
+            }
+
+            match *backend {
+                Backend::Tree => match resolver.interpreter.interpret(ast) {
                     Ok(_) => (),
-                    Err(e) => eprintln!("{}", e),
+                    Err(e) => report_runtime_error(path, &contents, &e),
+                },
+                Backend::Vm => match Compiler::compile(&ast) {
+                    Ok(script) => {
+                        let mut vm = Vm::new();
+                        if let Some(depth) = max_depth {
+                            vm.set_max_depth(depth);
+                        }
+                        match vm.interpret(script) {
+                            Ok(_) => (),
+                            Err(e) => report(path, &contents, &e.diagnostic()),
+                        }
+                    }
+                    Err(e) => report(path, &contents, &e.diagnostic()),
                 },
-                Err(e) => eprintln!("{:?}", e),
             }
         }
-        Err(e) => eprintln!("{:?}", e),
+        Err(errors) => for e in errors {
+            report(path, &contents, &e.diagnostic());
+        },
+    }
+}
+
+fn report(file_name: &str, source: &str, diagnostic: &Diagnostic) {
+    eprintln!("{}\n", diagnostic.render(file_name, source));
+}
+
+fn report_runtime_error(file_name: &str, source: &str, error: &Error) {
+    match error.diagnostic() {
+        Some(d) => report(file_name, source, &d),
+        None => eprintln!("{}", error),
+    }
+}
+
+/// Keeps the editor reading lines until the input is a complete statement.
+struct ReplHelper;
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if session::is_complete(ctx.input()) {
+            Ok(ValidationResult::Valid(None))
+        } else {
+            Ok(ValidationResult::Incomplete)
+        }
     }
 }
 
-fn repl() {
+impl Completer for ReplHelper {
+    type Candidate = String;
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+fn repl(max_depth: Option<usize>) {
     println!("Lox Repl");
-    let mut interpreter = Interpreter::new();
+    let mut editor: Editor<ReplHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("Could not start the line editor: {}", e);
+            process::exit(74);
+        }
+    };
+    editor.set_helper(Some(ReplHelper));
+    let history = home::home_dir().map(|dir| dir.join(".lox_history"));
+    if let Some(ref path) = history {
+        // there is no history file the first time the REPL runs
+        let _ = editor.load_history(path);
+    }
+
+    let mut session = new_session(max_depth);
     loop {
-        let input = rprompt::prompt_reply_stdout(">").unwrap();
+        let input = match editor.readline("> ") {
+            Ok(input) => input,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("{}", e);
+                break;
+            }
+        };
+        if input.trim().is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(input.as_str());
 
-        let mut scanner = Scanner::new(input);
-        scanner.scan_tokens();
+        if input.trim_start().starts_with(':') {
+            run_command(&mut session, input.trim(), max_depth);
+        } else {
+            run_input(&mut session, &input);
+        }
+    }
 
-        let mut parser = Parser::new(scanner.tokens);
-        match parser.parse() {
-            Ok(output) => {
-                // println!("{}", output.pretty_print());
-                match interpreter.interpret(output) {
-                    Ok(_) => (),
-                    Err(e) => eprintln!("{}", e),
+    if let Some(ref path) = history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("Could not save history to {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn run_input(session: &mut Session, input: &str) {
+    match session.run(input) {
+        Ok(Some(value)) => println!("{}", value),
+        Ok(None) => (),
+        Err(diagnostics) => for d in diagnostics {
+            report("<repl>", session.source(), &d);
+        },
+    }
+}
+
+const COMMANDS: &str = "\
+:env            list global bindings
+:ast <code>     show the syntax tree for <code>
+:tokens <code>  show the tokens in <code>
+:load <file>    run a script in this session
+:reset          discard every binding and start over
+:time <code>    run <code> and report how long it took
+:help           show this list";
+
+/// A fresh session with the command line's call depth limit, if it set one.
+fn new_session(max_depth: Option<usize>) -> Session {
+    let mut session = Session::new();
+    if let Some(depth) = max_depth {
+        session.set_max_depth(depth);
+    }
+    session
+}
+
+fn run_command(session: &mut Session, line: &str, max_depth: Option<usize>) {
+    let (command, argument) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, ""),
+    };
+    match command {
+        ":env" => for (name, value) in session.globals() {
+            println!("{} = {}", name, value);
+        },
+        ":ast" => {
+            let mut scanner = Scanner::new(argument.to_string());
+            scanner.scan_tokens();
+            match Parser::new(scanner.tokens).parse() {
+                Ok(program) => println!("{}", program.pretty_print()),
+                Err(errors) => for e in errors {
+                    report("<input>", argument, &e.diagnostic());
+                },
+            }
+        }
+        ":tokens" => {
+            let mut scanner = Scanner::new(argument.to_string());
+            scanner.scan_tokens();
+            for t in scanner.tokens {
+                println!("{}:{} {:?} '{}'", t.line, t.column, t.token_type, t.lexeme);
+            }
+        }
+        ":load" => {
+            let contents = match std::fs::read_to_string(argument) {
+                Ok(contents) => contents,
+                Err(e) => return eprintln!("Could not read '{}': {}", argument, e),
+            };
+            // locate errors in the file rather than in the session transcript,
+            // unless they come from code entered before it
+            let offset = session.source().chars().count();
+            if let Err(diagnostics) = session.run(&contents) {
+                for mut d in diagnostics {
+                    match d.position.checked_sub(offset) {
+                        Some(position) => {
+                            d.position = position;
+                            report(argument, &contents, &d);
+                        }
+                        None => report("<repl>", session.source(), &d),
+                    }
                 }
             }
-            Err(e) => eprintln!("{:?}", e),
         }
+        ":reset" => *session = new_session(max_depth),
+        ":time" => {
+            let start = Instant::now();
+            run_input(session, argument);
+            let elapsed = start.elapsed();
+            println!("took {:.3}ms", elapsed.as_secs_f64() * 1000.0);
+        }
+        ":help" => println!("{}", COMMANDS),
+        _ => eprintln!("Unknown command '{}'. Try :help.", command),
     }
 }
@@ -1,6 +1,13 @@
 use token::{Token, TokenType};
 use lox_value::LoxValue;
-use ast::{Expr, FunctionDeclaration, Program, Statement};
+use ast::{Bounds, Catch, Expr, FunctionDeclaration, Parameter, Program, Statement};
+use diagnostic::Diagnostic;
+use std::fmt;
+use std::rc::Rc;
+
+/// The most parameters a function can declare, and arguments a call can
+/// pass, so that a count always fits in a byte of bytecode.
+pub const MAX_PARAMETERS: usize = 255;
 
 #[derive(Debug)]
 pub struct ParseError {
@@ -11,53 +18,213 @@ pub struct ParseError {
 pub struct Parser {
     pub tokens: Vec<Token>,
     current: usize,
+    errors: Vec<ParseError>,
 }
 
 type ParseResult<T> = Result<T, ParseError>;
 
+impl ParseError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new("parse error", &self.token, &self.message)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Parse error at '{}': {}",
+            self.token.line, self.token.lexeme, self.message
+        )
+    }
+}
+
+/// The error for a token the scanner couldn't make sense of, if it is one.
+fn unexpected(token: &Token) -> Option<ParseError> {
+    let message = match token.token_type {
+        TokenType::Unexpected if token.lexeme.starts_with('"') => "Unterminated string.",
+        TokenType::Unexpected => "Unexpected character.",
+        _ => return None,
+    };
+    Some(ParseError {
+        token: token.clone(),
+        message: message.to_string(),
+    })
+}
+
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Parser {
         Parser {
-            tokens: tokens,
+            tokens,
             current: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Parses the whole token stream. A syntax error doesn't stop the parse:
+    /// the parser skips to the next statement boundary and keeps going, so
+    /// every error in the input is returned together.
+    pub fn parse(&mut self) -> Result<Program, Vec<ParseError>> {
+        let (program, errors) = self.parse_recovering();
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(errors)
         }
     }
 
-    pub fn parse(&mut self) -> Result<Program, ParseError> {
+    /// Like `parse`, but keeps the statements that did parse alongside the
+    /// errors, for tools that can make use of a partial program.
+    pub fn parse_recovering(&mut self) -> (Program, Vec<ParseError>) {
         let mut statements = Vec::new();
         while !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if let Some(s) = self.recovering_declaration() {
+                statements.push(s);
+            }
+        }
+        (Program { statements }, self.errors.drain(..).collect())
+    }
+
+    fn recovering_declaration(&mut self) -> Option<Statement> {
+        match self.declaration() {
+            Ok(s) => Some(s),
+            Err(e) => {
+                self.errors.push(e);
+                self.synchronize();
+                None
+            }
         }
+    }
+
+    /// Discards tokens until the start of what looks like the next statement.
+    /// Characters the scanner couldn't make sense of are reported on the way,
+    /// since no statement will get to complain about them.
+    fn synchronize(&mut self) {
+        self.advance();
+        while !self.is_at_end() {
+            let skipped = self.previous();
+            if skipped.token_type == TokenType::Semicolon {
+                return;
+            }
+            let reported = self.errors.iter().any(|e| e.token.position == skipped.position);
+            if let Some(e) = unexpected(skipped).filter(|_| !reported) {
+                self.errors.push(e);
+            }
 
-        Ok(Program { statements })
+            match self.peek().token_type {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Break
+                | TokenType::Continue
+                | TokenType::Return
+                | TokenType::Throw
+                | TokenType::Try => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
     }
 
     fn declaration(&mut self) -> Result<Statement, ParseError> {
-        if self.match_token(&[TokenType::Var]) {
+        if self.match_token(&[TokenType::Class]) {
+            self.class_declaration()
+        } else if self.match_token(&[TokenType::Var]) {
             self.var_declaration()
-        } else if self.match_token(&[TokenType::Fun]) {
-            self.function("function")
+        } else if self.check(&TokenType::Fun) && self.check_next(&TokenType::Identifier) {
+            let first = self.advance().clone();
+            let declaration = self.function("function")?;
+            Ok(Statement::Function {
+                declaration,
+                bounds: self.bounds(first),
+            })
         } else {
             self.statement()
         }
     }
 
-    fn function(&mut self, _kind: &str) -> ParseResult<Statement> {
-        let name = self.consume(&TokenType::Identifier, "Expect function name.")?
+    fn class_declaration(&mut self) -> ParseResult<Statement> {
+        let first = self.previous().clone();
+        let name = self.consume(&TokenType::Identifier, "Expect class name.")?
             .clone();
-        self.consume(&TokenType::LeftParen, "Expect '(' after function name.")?;
-        let mut params = Vec::new();
+
+        let superclass = if self.match_token(&[TokenType::Less]) {
+            let name = self.consume(&TokenType::Identifier, "Expect superclass name.")?
+                .clone();
+            Some(Box::new(Expr::Variable { name }))
+        } else {
+            None
+        };
+
+        self.consume(&TokenType::LeftBrace, "Expect '{' before class body.")?;
+
+        let mut methods = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+
+        self.consume(&TokenType::RightBrace, "Expect '}' after class body.")?;
+        Ok(Statement::Class {
+            name,
+            superclass,
+            methods,
+            bounds: self.bounds(first),
+        })
+    }
+
+    fn function(&mut self, kind: &str) -> ParseResult<FunctionDeclaration> {
+        let name = self.consume(&TokenType::Identifier, &format!("Expect {} name.", kind))?
+            .clone();
+        self.consume(
+            &TokenType::LeftParen,
+            &format!("Expect '(' after {} name.", kind),
+        )?;
+        self.function_body(name, kind)
+    }
+
+    /// Parses the parameter list and body, starting just after the '('.
+    fn function_body(&mut self, name: Token, kind: &str) -> ParseResult<FunctionDeclaration> {
+        let mut params: Vec<Parameter> = Vec::new();
+        let mut rest = None;
         if !self.check(&TokenType::RightParen) {
             loop {
-                if params.len() >= 8 {
-                    return Err(ParseError {
+                if params.len() == MAX_PARAMETERS {
+                    // reported, but the parser isn't confused
+                    self.errors.push(ParseError {
                         token: self.peek().clone(),
-                        message: "Cannot have more than 8 parameters.".to_string(),
+                        message: format!("Cannot have more than {} parameters.", MAX_PARAMETERS),
                     });
                 }
+                if self.match_token(&[TokenType::Ellipsis]) {
+                    let r = self.consume(&TokenType::Identifier, "Expect parameter name after '...'.")?;
+                    rest = Some(r.clone());
+                    if self.check(&TokenType::Comma) {
+                        return Err(ParseError {
+                            token: self.peek().clone(),
+                            message: "Rest parameter must be last.".to_string(),
+                        });
+                    }
+                    break;
+                }
                 let p = self.consume(&TokenType::Identifier, "Expect parameter name.")?
                     .clone();
-                params.push(p);
+                let default = if self.match_token(&[TokenType::Equal]) {
+                    Some(*self.expression()?)
+                } else {
+                    if params.iter().any(|p| p.default.is_some()) {
+                        self.errors.push(ParseError {
+                            token: p.clone(),
+                            message: "Parameters with default values must come last.".to_string(),
+                        });
+                    }
+                    None
+                };
+                params.push(Parameter { name: p, default });
                 if !self.match_token(&[TokenType::Comma]) {
                     break;
                 }
@@ -65,16 +232,21 @@ impl Parser {
         }
         self.consume(&TokenType::RightParen, "Expect ')' after parameters.")?;
 
-        self.consume(&TokenType::LeftBrace, "Expect '{' before function body.")?;
+        self.consume(
+            &TokenType::LeftBrace,
+            &format!("Expect '{{' before {} body.", kind),
+        )?;
         let body = self.block()?;
-        Ok(Statement::Function(FunctionDeclaration {
-            name: name,
-            body: body,
+        Ok(FunctionDeclaration {
+            name,
+            body,
             parameters: params,
-        }))
+            rest,
+        })
     }
 
     fn var_declaration(&mut self) -> Result<Statement, ParseError> {
+        let first = self.previous().clone();
         let name = self.consume(&TokenType::Identifier, "Expect variable name.")?
             .clone();
 
@@ -88,7 +260,11 @@ impl Parser {
             &TokenType::Semicolon,
             "Expect ';' after variable declaration.",
         )?;
-        Ok(Statement::Var { name, initializer })
+        Ok(Statement::Var {
+            name,
+            initializer,
+            bounds: self.bounds(first),
+        })
     }
 
     fn statement(&mut self) -> Result<Statement, ParseError> {
@@ -98,9 +274,34 @@ impl Parser {
         if self.match_token(&[TokenType::Return]) {
             return self.return_statement();
         }
+        if self.match_token(&[TokenType::Throw]) {
+            let keyword = self.previous().clone();
+            let value = self.expression()?;
+            self.consume(&TokenType::Semicolon, "Expect ';' after thrown value.")?;
+            let bounds = self.bounds(keyword.clone());
+            return Ok(Statement::Throw { keyword, value, bounds });
+        }
+        if self.match_token(&[TokenType::Try]) {
+            return self.try_statement();
+        }
+        if self.match_token(&[TokenType::Break]) {
+            let keyword = self.previous().clone();
+            self.consume(&TokenType::Semicolon, "Expect ';' after 'break'.")?;
+            let bounds = self.bounds(keyword.clone());
+            return Ok(Statement::Break { keyword, bounds });
+        }
+        if self.match_token(&[TokenType::Continue]) {
+            let keyword = self.previous().clone();
+            self.consume(&TokenType::Semicolon, "Expect ';' after 'continue'.")?;
+            let bounds = self.bounds(keyword.clone());
+            return Ok(Statement::Continue { keyword, bounds });
+        }
         if self.match_token(&[TokenType::LeftBrace]) {
+            let first = self.previous().clone();
+            let statements = self.block()?;
             return Ok(Statement::Block {
-                statements: self.block()?,
+                statements,
+                bounds: self.bounds(first),
             });
         }
         if self.match_token(&[TokenType::If]) {
@@ -119,70 +320,85 @@ impl Parser {
     }
 
     fn for_statement(&mut self) -> ParseResult<Statement> {
+        let keyword = self.previous().clone();
         self.consume(&TokenType::LeftParen, "Expect '(' after 'for'.")?;
         let initializer = if self.match_token(&[TokenType::Semicolon]) {
             None
         } else if self.match_token(&[TokenType::Var]) {
-            Some(self.var_declaration()?)
+            Some(Box::new(self.var_declaration()?))
         } else {
-            Some(self.expression_statement()?)
+            Some(Box::new(self.expression_statement()?))
         };
 
-        let for_condition = if !self.check(&TokenType::Semicolon) {
+        let condition = if !self.check(&TokenType::Semicolon) {
             Some(self.expression()?)
         } else {
             None
         };
         self.consume(&TokenType::Semicolon, "Expect ';' after loop condition.")?;
 
-        let for_increment = if !self.check(&TokenType::RightParen) {
+        let increment = if !self.check(&TokenType::RightParen) {
             Some(self.expression()?)
         } else {
             None
         };
         self.consume(&TokenType::RightParen, "Expect ')' after 'for' clauses.")?;
 
-        let mut body = self.statement()?;
-        if let Some(increment) = for_increment {
-            body = Statement::Block {
-                statements: vec![
-                    body,
-                    Statement::Expression {
-                        expression: increment,
-                    },
-                ],
-            };
-        }
-
-        match for_condition {
-            None => {
-                let loop_condition = Expr::Literal {
-                    value: LoxValue::Bool(true),
-                    token: self.previous().clone(),
-                };
-                body = Statement::While {
-                    condition: Box::new(loop_condition),
-                    body: Box::new(body),
-                };
-            }
-            Some(c) => {
-                body = Statement::While {
-                    condition: c,
-                    body: Box::new(body),
-                };
-            }
-        }
+        let body = self.statement()?;
+        let bounds = self.bounds(keyword.clone());
+        Ok(Statement::For {
+            keyword,
+            initializer,
+            condition,
+            increment,
+            body: Box::new(body),
+            bounds,
+        })
+    }
 
-        if let Some(i) = initializer {
-            body = Statement::Block {
-                statements: vec![i, body],
-            };
+    fn try_statement(&mut self) -> ParseResult<Statement> {
+        let keyword = self.previous().clone();
+        self.consume(&TokenType::LeftBrace, "Expect '{' after 'try'.")?;
+        let body = self.block()?;
+
+        let catch = if self.match_token(&[TokenType::Catch]) {
+            self.consume(&TokenType::LeftParen, "Expect '(' after 'catch'.")?;
+            let name = self.consume(&TokenType::Identifier, "Expect exception name.")?
+                .clone();
+            self.consume(&TokenType::RightParen, "Expect ')' after exception name.")?;
+            self.consume(&TokenType::LeftBrace, "Expect '{' before catch body.")?;
+            Some(Catch {
+                name,
+                body: self.block()?,
+            })
+        } else {
+            None
+        };
+        let finally = if self.match_token(&[TokenType::Finally]) {
+            self.consume(&TokenType::LeftBrace, "Expect '{' after 'finally'.")?;
+            Some(self.block()?)
+        } else {
+            None
+        };
+        if catch.is_none() && finally.is_none() {
+            return Err(ParseError {
+                token: self.peek().clone(),
+                message: "Expect 'catch' or 'finally' after try block.".to_string(),
+            });
         }
 
-        Ok(body)
+        let bounds = self.bounds(keyword.clone());
+        Ok(Statement::Try {
+            keyword,
+            body,
+            catch,
+            finally,
+            bounds,
+        })
     }
 
     fn while_statement(&mut self) -> ParseResult<Statement> {
+        let first = self.previous().clone();
         self.consume(&TokenType::LeftParen, "Expect '(' after 'while'.")?;
         let condition = self.expression()?;
         self.consume(&TokenType::RightParen, "Expect ')' after 'while'.")?;
@@ -191,13 +407,16 @@ impl Parser {
         Ok(Statement::While {
             condition,
             body: Box::new(body),
+            bounds: self.bounds(first),
         })
     }
 
     fn block(&mut self) -> Result<Vec<Statement>, ParseError> {
         let mut statements = Vec::new();
         while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if let Some(s) = self.recovering_declaration() {
+                statements.push(s);
+            }
         }
 
         self.consume(&TokenType::RightBrace, "Expect '}' after block.")?;
@@ -205,9 +424,13 @@ impl Parser {
     }
 
     fn print_statement(&mut self) -> Result<Statement, ParseError> {
+        let first = self.previous().clone();
         let expression = self.expression()?;
         self.consume(&TokenType::Semicolon, "Expect ';' after statement.")?;
-        Ok(Statement::Print { expression })
+        Ok(Statement::Print {
+            expression,
+            bounds: self.bounds(first),
+        })
     }
 
     fn return_statement(&mut self) -> ParseResult<Statement> {
@@ -219,10 +442,12 @@ impl Parser {
         };
 
         self.consume(&TokenType::Semicolon, "Expect ';' after return value.")?;
-        Ok(Statement::Return { keyword, value })
+        let bounds = self.bounds(keyword.clone());
+        Ok(Statement::Return { keyword, value, bounds })
     }
 
     fn if_statement(&mut self) -> ParseResult<Statement> {
+        let first = self.previous().clone();
         self.consume(&TokenType::LeftParen, "Expect '(' before 'if'.")?;
         let condition = self.expression()?;
         self.consume(&TokenType::RightParen, "Expect ')' after 'if'.")?;
@@ -237,13 +462,27 @@ impl Parser {
             condition,
             then_branch,
             else_branch,
+            bounds: self.bounds(first),
         })
     }
 
     fn expression_statement(&mut self) -> ParseResult<Statement> {
+        let first = self.peek().clone();
         let expression = self.expression()?;
         self.consume(&TokenType::Semicolon, "Expect ';' after statement.")?;
-        Ok(Statement::Expression { expression })
+        Ok(Statement::Expression {
+            expression,
+            bounds: self.bounds(first),
+        })
+    }
+
+    /// The bounds of a statement that started at `first` and has just been
+    /// parsed.
+    fn bounds(&self, first: Token) -> Bounds {
+        Bounds {
+            first,
+            last: self.previous().clone(),
+        }
     }
 
     fn expression(&mut self) -> ParseResult<Box<Expr>> {
@@ -256,10 +495,25 @@ impl Parser {
             let equals = self.previous().clone();
             let value = self.assignment()?;
             match *expr {
-                Expr::Variable { name, .. } => Ok(Box::new(Expr::Assign { name, value: value })),
+                Expr::Variable { name, .. } => Ok(Box::new(Expr::Assign { name, value })),
+                Expr::Get { object, name } => Ok(Box::new(Expr::Set {
+                    object,
+                    name,
+                    value,
+                })),
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                } => Ok(Box::new(Expr::IndexSet {
+                    object,
+                    bracket,
+                    index,
+                    value,
+                })),
                 _ => Err(ParseError {
                     token: equals,
-                    message: "Invalid assignment target".to_string(),
+                    message: "Invalid assignment target.".to_string(),
                 }),
             }
         } else {
@@ -360,8 +614,8 @@ impl Parser {
             let operator = self.previous().clone();
             let right = self.unary()?;
             Ok(Box::new(Expr::Unary {
-                operator: operator,
-                right: right,
+                operator,
+                right,
             }))
         } else {
             self.call()
@@ -373,6 +627,19 @@ impl Parser {
         loop {
             if self.match_token(&[TokenType::LeftParen]) {
                 expr = self.finish_call(expr)?;
+            } else if self.match_token(&[TokenType::Dot]) {
+                let name = self.consume(&TokenType::Identifier, "Expect property name after '.'.")?
+                    .clone();
+                expr = Box::new(Expr::Get { object: expr, name });
+            } else if self.match_token(&[TokenType::LeftBracket]) {
+                let bracket = self.previous().clone();
+                let index = self.expression()?;
+                self.consume(&TokenType::RightBracket, "Expect ']' after index.")?;
+                expr = Box::new(Expr::Index {
+                    object: expr,
+                    bracket,
+                    index,
+                });
             } else {
                 break;
             }
@@ -385,11 +652,10 @@ impl Parser {
         let mut arguments = Vec::new();
         if !self.check(&TokenType::RightParen) {
             loop {
-                if arguments.len() >= 8 {
-                    // TODO: This should not make the parser blow up
-                    return Err(ParseError {
+                if arguments.len() == MAX_PARAMETERS {
+                    self.errors.push(ParseError {
                         token: self.peek().clone(),
-                        message: "Cannot have more than 8 arguments".to_string(),
+                        message: format!("Cannot have more than {} arguments.", MAX_PARAMETERS),
                     });
                 }
                 let next_expr = self.expression()?;
@@ -410,6 +676,14 @@ impl Parser {
     }
 
     fn primary(&mut self) -> ParseResult<Box<Expr>> {
+        if self.is_at_end() {
+            return Err(ParseError {
+                token: self.peek().clone(),
+                message: "Expect expression.".to_string(),
+            });
+        }
+
+        let start = self.current;
         match self.advance().token_type {
             TokenType::True => Ok(Expr::Literal {
                 value: LoxValue::Bool(true),
@@ -436,26 +710,100 @@ impl Parser {
                 })
             }
 
+            TokenType::LeftBracket => {
+                let bracket = self.previous().clone();
+                let mut elements = Vec::new();
+                if !self.check(&TokenType::RightBracket) {
+                    loop {
+                        elements.push(*self.expression()?);
+                        if !self.match_token(&[TokenType::Comma]) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(&TokenType::RightBracket, "Expect ']' after list elements.")?;
+                Ok(Expr::List { bracket, elements })
+            }
+
+            // Statements starting with '{' are blocks, so a brace only reaches
+            // here in expression position, where it always opens a map.
+            TokenType::LeftBrace => {
+                let brace = self.previous().clone();
+                let mut entries = Vec::new();
+                if !self.check(&TokenType::RightBrace) {
+                    loop {
+                        let key = self.expression()?;
+                        self.consume(&TokenType::Colon, "Expect ':' after map key.")?;
+                        let value = self.expression()?;
+                        entries.push((*key, *value));
+                        if !self.match_token(&[TokenType::Comma]) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(&TokenType::RightBrace, "Expect '}' after map entries.")?;
+                Ok(Expr::Map { brace, entries })
+            }
+
+            TokenType::Fun => {
+                let keyword = self.previous().clone();
+                let name = Token {
+                    lexeme: format!("<anonymous@{}:{}>", keyword.line, keyword.column),
+                    token_type: TokenType::Identifier,
+                    ..keyword.clone()
+                };
+                self.consume(&TokenType::LeftParen, "Expect '(' after 'fun'.")?;
+                let declaration = self.function_body(name, "function")?;
+                Ok(Expr::Lambda {
+                    keyword,
+                    declaration: Rc::new(declaration),
+                })
+            }
+
+            TokenType::Super => {
+                let keyword = self.previous().clone();
+                self.consume(&TokenType::Dot, "Expect '.' after 'super'.")?;
+                let method = self.consume(
+                    &TokenType::Identifier,
+                    "Expect superclass method name.",
+                )?
+                    .clone();
+                Ok(Expr::Super { keyword, method })
+            }
+
+            TokenType::This => Ok(Expr::This {
+                keyword: self.previous().clone(),
+            }),
+
             TokenType::Identifier => {
                 let name = self.previous().clone();
                 Ok(Expr::Variable { name })
             }
 
-            _ => Err(ParseError {
-                token: self.previous().to_owned(),
-                message: "No matching primary".to_string(),
-            }),
-        }.and_then(|e| Ok(Box::new(e)))
+            _ => {
+                // leave the offending token in place so that synchronize()
+                // can tell whether it starts the next statement
+                self.current = start;
+                let token = self.peek().clone();
+                Err(unexpected(&token).unwrap_or(ParseError {
+                    token,
+                    message: "Expect expression.".to_string(),
+                }))
+            }
+        }.map(Box::new)
     }
 
+    /// Reports a missing token at the one found in its place, or with the
+    /// scanner's complaint if that one isn't a token at all.
     fn consume(&mut self, t: &TokenType, message: &str) -> ParseResult<&Token> {
         if self.check(t) {
             Ok(self.advance())
         } else {
-            Err(ParseError {
-                token: self.previous().to_owned(),
+            let token = self.peek().clone();
+            Err(unexpected(&token).unwrap_or(ParseError {
+                token,
                 message: message.to_string(),
-            })
+            }))
         }
     }
 
@@ -478,6 +826,13 @@ impl Parser {
         }
     }
 
+    fn check_next(&self, t: &TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.token_type == *t,
+            None => false,
+        }
+    }
+
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -498,3 +853,69 @@ impl Parser {
         &self.tokens[self.current - 1]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use parser::Parser;
+    use scanner::Scanner;
+
+    fn parse_errors(source: &str) -> Vec<String> {
+        let mut scanner = Scanner::new(source.to_string());
+        scanner.scan_tokens();
+        match Parser::new(scanner.tokens).parse() {
+            Ok(_) => vec![],
+            Err(errors) => errors.iter().map(|e| e.diagnostic().message).collect(),
+        }
+    }
+
+    #[test]
+    fn reports_every_syntax_error() {
+        let errors = parse_errors("print 1 +;\nvar = 2;\nprint 3;\nprint (4;\nprint @;\nprint \"5");
+        assert_eq!(
+            errors,
+            vec![
+                "Expect expression.",
+                "Expect variable name.",
+                "Expect ')' after expression.",
+                "Unexpected character.",
+                "Unterminated string.",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_errors_at_the_offending_token() {
+        let source = "var x = 1 @ 2;\nfun f( { }\nprint 1 # 2 $ 3;";
+        let mut scanner = Scanner::new(source.to_string());
+        scanner.scan_tokens();
+        let errors = match Parser::new(scanner.tokens).parse() {
+            Ok(_) => vec![],
+            Err(errors) => errors,
+        };
+        let located: Vec<(String, (usize, usize))> = errors
+            .iter()
+            .map(|e| (e.diagnostic().message, e.diagnostic().location(source)))
+            .collect();
+        assert_eq!(
+            located,
+            vec![
+                ("Unexpected character.".to_string(), (1, 11)),
+                ("Expect parameter name.".to_string(), (2, 8)),
+                ("Unexpected character.".to_string(), (3, 9)),
+                ("Unexpected character.".to_string(), (3, 13)),
+            ]
+        );
+    }
+
+    #[test]
+    fn allows_up_to_255_parameters_and_arguments() {
+        let names: Vec<String> = (0..256).map(|i| format!("p{}", i)).collect();
+        let ok = format!("fun f({}) {{}}\nf({});", names[..255].join(", "), names[..255].join(", "));
+        assert!(parse_errors(&ok).is_empty());
+        let too_many = format!("fun f({}) {{}}\nf({});", names.join(", "), names.join(", "));
+        assert_eq!(
+            parse_errors(&too_many),
+            vec!["Cannot have more than 255 parameters.", "Cannot have more than 255 arguments."]
+        );
+    }
+}
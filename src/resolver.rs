@@ -1,8 +1,11 @@
 use ast::{Expr, FunctionDeclaration, Program, Statement};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use interpreter::Interpreter;
 use token::Token;
-use std::mem::replace;
+use diagnostic::Diagnostic;
+use lint::{Rule, Warning};
+use std::mem::{replace, take};
+use std::fmt;
 
 #[derive(Debug, PartialEq)]
 enum Status {
@@ -13,6 +16,14 @@ enum Status {
 enum FunctionType {
     None,
     Fuction,
+    Initializer,
+    Method,
+}
+
+enum ClassType {
+    None,
+    Class,
+    Subclass,
 }
 
 #[derive(Debug)]
@@ -21,9 +32,62 @@ pub struct Error {
     message: String,
 }
 
+impl Error {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new("resolution error", &self.token, &self.message)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Resolution error at '{}': {}",
+            self.token.line, self.token.lexeme, self.message
+        )
+    }
+}
+
+struct Local {
+    status: Status,
+    /// Where the local lives in its scope.
+    slot: usize,
+    /// The declaring token. `None` for names the interpreter binds itself.
+    token: Option<Token>,
+    /// Parameters, and the names `catch` binds, are never reported unused.
+    parameter: bool,
+    read: bool,
+}
+
+/// A name in the source and the local it refers to, or `None` for a global,
+/// which is only known by name.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub name: Token,
+    pub declaration: Option<Token>,
+}
+
+type Scope = HashMap<String, Local>;
+
 pub struct Resolver {
-    scopes: Vec<HashMap<String, Status>>,
+    scopes: Vec<Scope>,
     current_function: FunctionType,
+    current_class: ClassType,
+    /// How many loops enclose the current statement within the current
+    /// function.
+    loop_depth: usize,
+    /// Set by `collect_warnings`. Resolution is the same either way.
+    linting: bool,
+    warnings: Vec<Warning>,
+    /// Set by `collect_references`.
+    indexing: bool,
+    references: Vec<Reference>,
+    /// Set by `collect_scopes`.
+    snapshotting: bool,
+    snapshots: HashMap<usize, Vec<Vec<String>>>,
+    /// Names declared at the top level of the program being linted, plus
+    /// the interpreter's globals.
+    globals: HashSet<String>,
     pub interpreter: Interpreter,
 }
 
@@ -31,33 +95,169 @@ impl Resolver {
     pub fn new(interpreter: Interpreter) -> Resolver {
         Resolver {
             scopes: vec![],
-            interpreter: interpreter,
+            interpreter,
             current_function: FunctionType::None,
+            current_class: ClassType::None,
+            loop_depth: 0,
+            linting: false,
+            warnings: vec![],
+            indexing: false,
+            references: vec![],
+            snapshotting: false,
+            snapshots: HashMap::new(),
+            globals: HashSet::new(),
+        }
+    }
+
+    /// Makes later calls to `resolve` also look for the problems `lint`
+    /// reports, which `warnings` then returns.
+    pub fn collect_warnings(&mut self) {
+        self.linting = true;
+    }
+
+    pub fn warnings(&mut self) -> Vec<Warning> {
+        take(&mut self.warnings)
+    }
+
+    /// Makes later calls to `resolve` record every declaration of and
+    /// reference to a variable, which `references` then returns.
+    /// Declarations refer to themselves.
+    pub fn collect_references(&mut self) {
+        self.indexing = true;
+    }
+
+    pub fn references(&mut self) -> Vec<Reference> {
+        take(&mut self.references)
+    }
+
+    /// Makes later calls to `resolve` record the locals in scope at every
+    /// statement, which `scopes` then returns keyed by the position of the
+    /// statement's token. Blocks are left out. Each scope, outermost first,
+    /// lists its locals in slot order, so it lines up with the frames of the
+    /// environment the statement runs in.
+    pub fn collect_scopes(&mut self) {
+        self.snapshotting = true;
+    }
+
+    pub fn scopes(&mut self) -> HashMap<usize, Vec<Vec<String>>> {
+        take(&mut self.snapshots)
+    }
+
+    /// Resolves an expression as if it appeared where `scopes` were
+    /// recorded.
+    pub fn resolve_in(&mut self, e: &Expr, scopes: &[Vec<String>]) -> Result<(), Error> {
+        self.scopes = scopes
+            .iter()
+            .map(|names| {
+                names
+                    .iter()
+                    .enumerate()
+                    .map(|(slot, name)| {
+                        let local = Local {
+                            status: Status::Initialized,
+                            slot,
+                            token: None,
+                            parameter: false,
+                            read: false,
+                        };
+                        (name.clone(), local)
+                    })
+                    .collect()
+            })
+            .collect();
+        let resolved = self.resolve_expr(e);
+        self.scopes.clear();
+        resolved
+    }
+
+    fn snapshot(&mut self, s: &Statement) {
+        if let Some(token) = s.token() {
+            let scopes = self
+                .scopes
+                .iter()
+                .map(|scope| {
+                    let mut locals: Vec<(&String, usize)> =
+                        scope.iter().map(|(name, l)| (name, l.slot)).collect();
+                    locals.sort_by_key(|&(_, slot)| slot);
+                    locals.into_iter().map(|(name, _)| name.clone()).collect()
+                })
+                .collect();
+            self.snapshots.insert(token.position, scopes);
+        }
+    }
+
+    fn record(&mut self, name: &Token, declaration: Option<Token>) {
+        if self.indexing {
+            self.references.push(Reference {
+                name: name.clone(),
+                declaration,
+            });
+        }
+    }
+
+    fn warn(&mut self, rule: Rule, token: &Token, message: String) {
+        if self.linting {
+            self.warnings.push(Warning {
+                rule,
+                token: token.clone(),
+                message,
+            });
         }
     }
 
+    /// Top-level declarations are globals and stay unresolved, so they are
+    /// looked up by name at runtime.
+    ///
+    /// An error leaves the resolver back at the top level, so it can go on to
+    /// resolve the next program in the same session.
     pub fn resolve(&mut self, p: &Program) -> Result<(), Error> {
-        self.begin_scope();
+        if self.linting {
+            self.globals = self
+                .interpreter
+                .environment
+                .globals()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect();
+            for s in &p.statements {
+                match *s {
+                    Statement::Var { ref name, .. } | Statement::Class { ref name, .. } => {
+                        self.globals.insert(name.lexeme.clone());
+                    }
+                    Statement::Function { ref declaration, .. } => {
+                        self.globals.insert(declaration.name.lexeme.clone());
+                    }
+                    _ => (),
+                }
+            }
+        }
         for s in &p.statements {
-            self.resolve_statement(s)?;
+            if let Err(e) = self.resolve_statement(s) {
+                self.scopes.clear();
+                self.current_function = FunctionType::None;
+                self.current_class = ClassType::None;
+                self.loop_depth = 0;
+                return Err(e);
+            }
         }
-        self.end_scope();
         Ok(())
     }
 
     pub fn visit_statement(&mut self, s: &Statement) -> Result<(), Error> {
+        if self.snapshotting && !matches!(*s, Statement::Block { .. }) {
+            self.snapshot(s);
+        }
         match *s {
-            Statement::Block { ref statements } => {
+            Statement::Block { ref statements, .. } => {
                 self.begin_scope();
-                for s in statements {
-                    self.resolve_statement(s)?;
-                }
+                self.resolve_statements(statements)?;
                 self.end_scope();
             }
 
             Statement::Var {
                 ref name,
                 ref initializer,
+                ..
             } => {
                 self.declare(name)?;
                 if let Some(ref e) = *initializer {
@@ -66,13 +266,56 @@ impl Resolver {
                 self.define(name);
             }
 
-            Statement::Function(ref statement) => {
+            Statement::Class {
+                ref name,
+                ref superclass,
+                ref methods,
+                ..
+            } => {
+                let enclosing_class = replace(&mut self.current_class, ClassType::Class);
+                self.declare(name)?;
+                self.define(name);
+
+                if let Some(ref superclass) = *superclass {
+                    if superclass.token().lexeme == name.lexeme {
+                        return Err(Error {
+                            token: superclass.token().clone(),
+                            message: "A class cannot inherit from itself.".to_string(),
+                        });
+                    }
+                    self.current_class = ClassType::Subclass;
+                    self.resolve_expr(superclass)?;
+
+                    self.begin_scope();
+                    self.define_implicit("super");
+                }
+
+                self.begin_scope();
+                self.define_implicit("this");
+
+                for method in methods {
+                    let declaration = if method.name.lexeme == "init" {
+                        FunctionType::Initializer
+                    } else {
+                        FunctionType::Method
+                    };
+                    self.resolve_function(method, declaration)?;
+                }
+
+                self.end_scope();
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+                self.current_class = enclosing_class;
+            }
+
+            Statement::Function { declaration: ref statement, .. } => {
                 self.declare(&statement.name)?;
                 self.define(&statement.name);
                 self.resolve_function(statement, FunctionType::Fuction)?;
             }
 
-            Statement::Expression { ref expression } | Statement::Print { ref expression } => {
+            Statement::Expression { ref expression, .. } | Statement::Print { ref expression, .. } => {
                 self.resolve_expr(expression)?;
             }
 
@@ -80,9 +323,10 @@ impl Resolver {
                 ref condition,
                 ref then_branch,
                 ref else_branch,
+                ..
             } => {
                 self.resolve_expr(condition)?;
-                self.resolve_statement(&*then_branch)?;
+                self.resolve_statement(then_branch)?;
                 if let Some(ref s) = *else_branch {
                     self.resolve_statement(s)?;
                 }
@@ -91,6 +335,7 @@ impl Resolver {
             Statement::Return {
                 ref value,
                 ref keyword,
+                ..
             } => {
                 match self.current_function {
                     FunctionType::None => {
@@ -99,19 +344,86 @@ impl Resolver {
                             message: "Cannot return from top-level code.".to_string(),
                         });
                     }
-                    FunctionType::Fuction => (),
+                    FunctionType::Initializer => {
+                        if value.is_some() {
+                            return Err(Error {
+                                token: keyword.clone(),
+                                message: "Cannot return a value from an initializer.".to_string(),
+                            });
+                        }
+                    }
+                    FunctionType::Fuction | FunctionType::Method => (),
                 };
                 if let Some(ref v) = *value {
                     self.resolve_expr(v)?;
                 }
             }
 
+            Statement::For {
+                ref initializer,
+                ref condition,
+                ref increment,
+                ref body,
+                ..
+            } => {
+                self.begin_scope();
+                if let Some(ref i) = *initializer {
+                    self.resolve_statement(i)?;
+                }
+                if let Some(ref c) = *condition {
+                    self.resolve_expr(c)?;
+                }
+                self.loop_depth += 1;
+                self.resolve_statement(body)?;
+                self.loop_depth -= 1;
+                if let Some(ref i) = *increment {
+                    self.resolve_expr(i)?;
+                }
+                self.end_scope();
+            }
+
             Statement::While {
                 ref condition,
                 ref body,
+                ..
             } => {
                 self.resolve_expr(condition)?;
+                self.loop_depth += 1;
                 self.resolve_statement(body)?;
+                self.loop_depth -= 1;
+            }
+
+            Statement::Throw { ref value, .. } => self.resolve_expr(value)?,
+
+            Statement::Try {
+                ref body,
+                ref catch,
+                ref finally,
+                ..
+            } => {
+                self.begin_scope();
+                self.resolve_statements(body)?;
+                self.end_scope();
+                if let Some(ref catch) = *catch {
+                    self.begin_scope();
+                    self.parameter(&catch.name)?;
+                    self.resolve_statements(&catch.body)?;
+                    self.end_scope();
+                }
+                if let Some(ref finally) = *finally {
+                    self.begin_scope();
+                    self.resolve_statements(finally)?;
+                    self.end_scope();
+                }
+            }
+
+            Statement::Break { ref keyword, .. } | Statement::Continue { ref keyword, .. } => {
+                if self.loop_depth == 0 {
+                    return Err(Error {
+                        token: keyword.clone(),
+                        message: format!("Cannot use '{}' outside of a loop.", keyword.lexeme),
+                    });
+                }
             }
         }
 
@@ -124,18 +436,40 @@ impl Resolver {
         function_type: FunctionType,
     ) -> Result<(), Error> {
         let enclosing_type = replace(&mut self.current_function, function_type);
+        let enclosing_loop_depth = replace(&mut self.loop_depth, 0);
+        let names = function.parameters.iter().map(|p| &p.name);
+        for p in names.chain(function.rest.iter()) {
+            if self.is_visible(&p.lexeme) {
+                let message = format!("Parameter '{}' shadows a variable in an outer scope.", p.lexeme);
+                self.warn(Rule::ShadowedParameter, p, message);
+            }
+        }
         self.begin_scope();
+        // a default can use the parameters before it
         for p in &function.parameters {
-            self.declare(p)?;
-            self.define(p);
+            if let Some(ref default) = p.default {
+                self.visit_expression(default)?;
+            }
+            self.parameter(&p.name)?;
         }
-
-        for statement in &function.body {
-            self.resolve_statement(statement)?;
+        if let Some(ref rest) = function.rest {
+            self.parameter(rest)?;
         }
 
+        self.resolve_statements(&function.body)?;
+
         self.end_scope();
         self.current_function = enclosing_type;
+        self.loop_depth = enclosing_loop_depth;
+        Ok(())
+    }
+
+    fn parameter(&mut self, name: &Token) -> Result<(), Error> {
+        self.declare(name)?;
+        self.define(name);
+        if let Some(local) = self.scopes.last_mut().and_then(|s| s.get_mut(&name.lexeme)) {
+            local.parameter = true;
+        }
         Ok(())
     }
 
@@ -143,7 +477,11 @@ impl Resolver {
         match *e {
             Expr::Variable { ref name } => {
                 if let Some(scope) = self.scopes.last_mut() {
-                    if let Some(&Status::Uninitialized) = scope.get(&name.lexeme) {
+                    if let Some(&Local {
+                        status: Status::Uninitialized,
+                        ..
+                    }) = scope.get(&name.lexeme)
+                    {
                         return Err(Error {
                             token: name.clone(),
                             message: "Cannot read local variable in its own initializer."
@@ -151,7 +489,14 @@ impl Resolver {
                         });
                     }
                 }
-                self.resolve_local(e, name);
+                let declaration = match self.resolve_local(e, name) {
+                    Some(local) => {
+                        local.read = true;
+                        local.token.clone()
+                    }
+                    None => None,
+                };
+                self.record(name, declaration);
                 Ok(())
             }
 
@@ -160,7 +505,12 @@ impl Resolver {
                 ref name,
             } => {
                 self.resolve_expr(value)?;
-                self.resolve_local(e, name);
+                let local = self.resolve_local(e, name).map(|l| l.token.clone());
+                if local.is_none() && self.linting && !self.globals.contains(&name.lexeme) {
+                    let message = format!("Assignment to undeclared global '{}'.", name.lexeme);
+                    self.warn(Rule::UndeclaredGlobal, name, message);
+                }
+                self.record(name, local.flatten());
                 Ok(())
             }
 
@@ -191,6 +541,95 @@ impl Resolver {
                 Ok(())
             }
 
+            Expr::Get { ref object, .. } => {
+                self.resolve_expr(object)?;
+                Ok(())
+            }
+
+            Expr::Set {
+                ref object,
+                ref value,
+                ..
+            } => {
+                self.resolve_expr(value)?;
+                self.resolve_expr(object)?;
+                Ok(())
+            }
+
+            Expr::This { ref keyword } => {
+                if let ClassType::None = self.current_class {
+                    return Err(Error {
+                        token: keyword.clone(),
+                        message: "Cannot use 'this' outside of a class.".to_string(),
+                    });
+                }
+                self.resolve_local(e, keyword);
+                Ok(())
+            }
+
+            Expr::Super { ref keyword, .. } => {
+                match self.current_class {
+                    ClassType::None => {
+                        return Err(Error {
+                            token: keyword.clone(),
+                            message: "Cannot use 'super' outside of a class.".to_string(),
+                        });
+                    }
+                    ClassType::Class => {
+                        return Err(Error {
+                            token: keyword.clone(),
+                            message: "Cannot use 'super' in a class with no superclass."
+                                .to_string(),
+                        });
+                    }
+                    ClassType::Subclass => (),
+                }
+                self.resolve_local(e, keyword);
+                Ok(())
+            }
+
+            Expr::Index {
+                ref object,
+                ref index,
+                ..
+            } => {
+                self.resolve_expr(object)?;
+                self.resolve_expr(index)?;
+                Ok(())
+            }
+
+            Expr::IndexSet {
+                ref object,
+                ref index,
+                ref value,
+                ..
+            } => {
+                self.resolve_expr(object)?;
+                self.resolve_expr(index)?;
+                self.resolve_expr(value)?;
+                Ok(())
+            }
+
+            Expr::List { ref elements, .. } => {
+                for e in elements {
+                    self.resolve_expr(e)?;
+                }
+                Ok(())
+            }
+
+            Expr::Lambda {
+                ref declaration,
+                ..
+            } => self.resolve_function(declaration, FunctionType::Fuction),
+
+            Expr::Map { ref entries, .. } => {
+                for (key, value) in entries {
+                    self.resolve_expr(key)?;
+                    self.resolve_expr(value)?;
+                }
+                Ok(())
+            }
+
             Expr::Grouping { ref expression, .. } => {
                 self.resolve_expr(expression)?;
                 Ok(())
@@ -205,24 +644,45 @@ impl Resolver {
         }
     }
 
-    /*
+    /// Returns the local `name` refers to, or `None` if it's a global.
+    fn resolve_local(&mut self, expr: &Expr, name: &Token) -> Option<&mut Local> {
+        for (depth, scope) in self.scopes.iter_mut().rev().enumerate() {
+            if let Some(local) = scope.get_mut(&name.lexeme) {
+                self.interpreter.resolve(expr, depth, local.slot);
+                return Some(local);
+            }
+        }
+        None
+    }
 
-   0 []
-   1 []
-   2 [foo]
-   3 []
+    fn is_visible(&self, name: &str) -> bool {
+        self.globals.contains(name) || self.scopes.iter().any(|s| s.contains_key(name))
+    }
 
-    */
-    fn resolve_local(&mut self, expr: &Expr, name: &Token) {
-        for (i, scope) in self.scopes.iter_mut().enumerate().rev() {
-            if scope.contains_key(&name.lexeme) {
-                self.interpreter.resolve(expr, i);
-                return;
+    /// Resolves the statements of a block or function body, warning about
+    /// any that follow a `return`, `break`, `continue` or `throw`.
+    fn resolve_statements(&mut self, statements: &[Statement]) -> Result<(), Error> {
+        let mut exit = None;
+        for s in statements {
+            if let Some(keyword) = exit.take() {
+                let message = format!("Unreachable code after '{}'.", keyword);
+                self.warn(Rule::UnreachableCode, &s.bounds().first, message);
             }
+            self.resolve_statement(s)?;
+            exit = match *s {
+                Statement::Return { ref keyword, .. }
+                | Statement::Break { ref keyword, .. }
+                | Statement::Continue { ref keyword, .. }
+                | Statement::Throw { ref keyword, .. } => Some(keyword.lexeme.clone()),
+                _ => None,
+            };
         }
+        Ok(())
     }
 
     fn declare(&mut self, name: &Token) -> Result<(), Error> {
+        let declaration = Some(name.clone()).filter(|_| !self.scopes.is_empty());
+        self.record(name, declaration);
         if let Some(scope) = self.scopes.last_mut() {
             if scope.contains_key(&name.lexeme) {
                 return Err(Error {
@@ -230,7 +690,17 @@ impl Resolver {
                     message: "Variable with this name already defined in scope.".to_string(),
                 });
             }
-            scope.insert(name.lexeme.clone(), Status::Uninitialized);
+            let slot = scope.len();
+            scope.insert(
+                name.lexeme.clone(),
+                Local {
+                    status: Status::Uninitialized,
+                    slot,
+                    token: Some(name.clone()),
+                    parameter: false,
+                    read: false,
+                },
+            );
         }
 
         Ok(())
@@ -238,7 +708,26 @@ impl Resolver {
 
     fn define(&mut self, name: &Token) {
         if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.lexeme.clone(), Status::Initialized);
+            if let Some(local) = scope.get_mut(&name.lexeme) {
+                local.status = Status::Initialized;
+            }
+        }
+    }
+
+    /// Declares and defines a name the interpreter binds itself, like `this`.
+    fn define_implicit(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            let slot = scope.len();
+            scope.insert(
+                name.to_string(),
+                Local {
+                    status: Status::Initialized,
+                    slot,
+                    token: None,
+                    parameter: false,
+                    read: false,
+                },
+            );
         }
     }
 
@@ -255,6 +744,20 @@ impl Resolver {
     }
 
     fn end_scope(&mut self) {
-        self.scopes.pop();
+        let scope = self.scopes.pop().unwrap_or_default();
+        if !self.linting {
+            return;
+        }
+        let mut unread: Vec<Token> = scope
+            .into_values()
+            .filter(|l| !l.read && !l.parameter)
+            .filter(|l| !l.token.as_ref().is_some_and(|t| t.lexeme.starts_with('_')))
+            .filter_map(|l| l.token)
+            .collect();
+        unread.sort_by_key(|t| t.position);
+        for token in unread {
+            let message = format!("Local '{}' is declared but never read.", token.lexeme);
+            self.warn(Rule::UnusedLocal, &token, message);
+        }
     }
 }
@@ -0,0 +1,23 @@
+use ::gc;
+use ::interpreter::Error;
+use ::lox_map::{LoxMap, MapKey};
+use ::lox_value::LoxValue;
+use ::token::Token;
+
+/// Runs a collection and returns how many objects it freed.
+pub fn gc(_paren: &Token, _arguments: &[LoxValue]) -> Result<LoxValue, Error> {
+    Ok(LoxValue::Number(gc::collect() as f64))
+}
+
+pub fn gc_stats(_paren: &Token, _arguments: &[LoxValue]) -> Result<LoxValue, Error> {
+    let stats = gc::stats();
+    let mut map = LoxMap::default();
+    for &(key, value) in &[
+        ("collections", stats.collections),
+        ("collected", stats.collected),
+        ("tracked", stats.tracked),
+    ] {
+        map.insert(MapKey::String(key.to_string()), LoxValue::Number(value as f64));
+    }
+    Ok(LoxValue::map(map))
+}
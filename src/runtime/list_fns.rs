@@ -0,0 +1,66 @@
+use ::interpreter::Error;
+use ::lox_list;
+use ::lox_value::LoxValue;
+use ::token::Token;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type List = Rc<RefCell<Vec<LoxValue>>>;
+
+fn error(token: &Token, message: String) -> Error {
+    Error::RuntimeError {
+        token: token.clone(),
+        message,
+        trace: Box::new([]),
+    }
+}
+
+fn as_list(token: &Token, value: &LoxValue, function: &str) -> Result<List, Error> {
+    match *value {
+        LoxValue::List(ref list) => Ok(Rc::clone(list)),
+        _ => Err(error(token, lox_list::expected(function))),
+    }
+}
+
+pub fn list_index(token: &Token, index: &LoxValue, len: usize) -> Result<usize, Error> {
+    lox_list::index(index.as_number(), len).map_err(|m| error(token, m))
+}
+
+pub fn len(paren: &Token, arguments: &[LoxValue]) -> Result<LoxValue, Error> {
+    match arguments[0] {
+        LoxValue::List(ref list) => Ok(LoxValue::Number(list.borrow().len() as f64)),
+        LoxValue::Map(ref map) => Ok(LoxValue::Number(map.borrow().len() as f64)),
+        LoxValue::String(ref s) => Ok(LoxValue::Number(s.chars().count() as f64)),
+        _ => Err(error(paren, "len() expects a list, map, or string.".to_string())),
+    }
+}
+
+pub fn push(paren: &Token, arguments: &[LoxValue]) -> Result<LoxValue, Error> {
+    let list = as_list(paren, &arguments[0], "push")?;
+    list.borrow_mut().push(arguments[1].clone());
+    Ok(LoxValue::Nil)
+}
+
+pub fn pop(paren: &Token, arguments: &[LoxValue]) -> Result<LoxValue, Error> {
+    let list = as_list(paren, &arguments[0], "pop")?;
+    let popped = lox_list::pop(&mut list.borrow_mut());
+    popped.map_err(|m| error(paren, m))
+}
+
+pub fn insert(paren: &Token, arguments: &[LoxValue]) -> Result<LoxValue, Error> {
+    let list = as_list(paren, &arguments[0], "insert")?;
+    let inserted = lox_list::insert(&mut list.borrow_mut(), arguments[1].as_number(), arguments[2].clone());
+    inserted.map(|()| LoxValue::Nil).map_err(|m| error(paren, m))
+}
+
+pub fn remove(paren: &Token, arguments: &[LoxValue]) -> Result<LoxValue, Error> {
+    let list = as_list(paren, &arguments[0], "remove")?;
+    let removed = lox_list::remove(&mut list.borrow_mut(), arguments[1].as_number());
+    removed.map_err(|m| error(paren, m))
+}
+
+pub fn slice(paren: &Token, arguments: &[LoxValue]) -> Result<LoxValue, Error> {
+    let list = as_list(paren, &arguments[0], "slice")?;
+    let sliced = lox_list::slice(&list.borrow(), arguments[1].as_number(), arguments[2].as_number());
+    sliced.map(LoxValue::list).map_err(|m| error(paren, m))
+}
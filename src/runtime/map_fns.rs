@@ -0,0 +1,55 @@
+use ::interpreter::Error;
+use ::lox_map::{self, LoxMap, MapKey};
+use ::lox_value::LoxValue;
+use ::token::Token;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type Map = Rc<RefCell<LoxMap<LoxValue>>>;
+
+fn error(token: &Token, message: String) -> Error {
+    Error::RuntimeError {
+        token: token.clone(),
+        message,
+        trace: Box::new([]),
+    }
+}
+
+fn as_map(token: &Token, value: &LoxValue, function: &str) -> Result<Map, Error> {
+    match *value {
+        LoxValue::Map(ref map) => Ok(Rc::clone(map)),
+        _ => Err(error(token, lox_map::expected(function))),
+    }
+}
+
+pub fn map_key(token: &Token, key: &LoxValue) -> Result<MapKey, Error> {
+    lox_map::valid(key.to_key()).map_err(|m| error(token, m))
+}
+
+pub fn map_get(token: &Token, map: &LoxMap<LoxValue>, key: &LoxValue) -> Result<LoxValue, Error> {
+    map.lookup(key.to_key()).cloned().map_err(|m| error(token, m))
+}
+
+pub fn keys(paren: &Token, arguments: &[LoxValue]) -> Result<LoxValue, Error> {
+    let map = as_map(paren, &arguments[0], "keys")?;
+    let keys = map.borrow().iter().map(|(k, _)| LoxValue::from_key(k)).collect();
+    Ok(LoxValue::list(keys))
+}
+
+pub fn values(paren: &Token, arguments: &[LoxValue]) -> Result<LoxValue, Error> {
+    let map = as_map(paren, &arguments[0], "values")?;
+    let values = map.borrow().iter().map(|(_, v)| v.clone()).collect();
+    Ok(LoxValue::list(values))
+}
+
+pub fn has(paren: &Token, arguments: &[LoxValue]) -> Result<LoxValue, Error> {
+    let map = as_map(paren, &arguments[0], "has")?;
+    let found = map.borrow().has(arguments[1].to_key());
+    found.map(LoxValue::Bool).map_err(|m| error(paren, m))
+}
+
+pub fn delete(paren: &Token, arguments: &[LoxValue]) -> Result<LoxValue, Error> {
+    let map = as_map(paren, &arguments[0], "delete")?;
+    let removed = map.borrow_mut().delete(arguments[1].to_key());
+    removed.map(LoxValue::Bool).map_err(|m| error(paren, m))
+}
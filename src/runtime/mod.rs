@@ -1 +1,5 @@
 pub mod std_fns;
+pub mod native_fn;
+pub mod list_fns;
+pub mod map_fns;
+pub mod gc_fns;
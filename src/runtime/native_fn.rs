@@ -0,0 +1,56 @@
+use ::interpreter::{Error, Interpreter};
+use ::lox_value::LoxValue;
+use ::lox_callable::{Arity, LoxCallable};
+use ::token::Token;
+use ::gc::{Trace, Tracer};
+use std::fmt;
+
+pub type NativeFnPtr = fn(&Token, &[LoxValue]) -> Result<LoxValue, Error>;
+
+/// A builtin that only needs its arguments, so it can be a plain function
+/// instead of its own `LoxCallable` type.
+pub struct NativeFn {
+    name: &'static str,
+    arity: usize,
+    function: NativeFnPtr,
+}
+
+impl NativeFn {
+    pub fn new(name: &'static str, arity: usize, function: NativeFnPtr) -> NativeFn {
+        NativeFn {
+            name,
+            arity,
+            function,
+        }
+    }
+}
+
+impl LoxCallable for NativeFn {
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<LoxValue>,
+    ) -> Result<LoxValue, Error> {
+        // the interpreter has already checked the arity
+        (self.function)(paren, &arguments)
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exactly(self.arity)
+    }
+
+    fn name(&self) -> &str {
+        self.name
+    }
+}
+
+impl fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl Trace for NativeFn {
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
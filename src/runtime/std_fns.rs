@@ -1,13 +1,16 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 use ::interpreter::{Error, Interpreter};
 use ::lox_value::LoxValue;
-use ::lox_callable::LoxCallable;
+use ::lox_callable::{Arity, LoxCallable};
+use ::token::Token;
+use ::gc::{Trace, Tracer};
 #[derive(Debug)]
 pub struct Clock;
 impl LoxCallable for Clock {
     fn call(
         &self,
         _interpreter: &mut Interpreter,
+        _paren: &Token,
         _arguments: Vec<LoxValue>,
     ) -> Result<LoxValue, Error> {
         let t = SystemTime::now()
@@ -17,11 +20,15 @@ impl LoxCallable for Clock {
         Ok(LoxValue::Number(t as f64))
     }
 
-    fn arity(&self) -> usize {
-        0
+    fn arity(&self) -> Arity {
+        Arity::exactly(0)
     }
 
     fn name(&self) -> &str {
         "clock"
     }
 }
+
+impl Trace for Clock {
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
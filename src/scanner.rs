@@ -1,20 +1,13 @@
-use std::process;
 use std::str::FromStr;
 use token::{Token, TokenType};
 use lox_value::LoxValue;
 
 fn is_digit(c: char) -> bool {
-    match c {
-        '0'...'9' => true,
-        _ => false,
-    }
+    c.is_ascii_digit()
 }
 
 fn is_alpha(c: char) -> bool {
-    match c {
-        'a'...'z' | 'A'...'Z' | '_' => true,
-        _ => false,
-    }
+    matches!(c, 'a'..='z' | 'A'..='Z' | '_')
 }
 
 fn is_alphanumeric(c: char) -> bool {
@@ -22,17 +15,29 @@ fn is_alphanumeric(c: char) -> bool {
 }
 
 pub struct Scanner {
-    source: String,
+    /// Positions, columns, and `start`/`current` all count characters.
+    source: Vec<char>,
     pub tokens: Vec<Token>,
+    /// `//` comments, kept apart from the tokens so that the parser never
+    /// sees them.
+    pub comments: Vec<Token>,
     start: usize,
     current: usize,
     line: usize,
+    /// Index of the first character on the current line.
+    line_start: usize,
+    /// Column of the token being scanned, fixed before the token can span
+    /// lines.
+    column: usize,
+    /// Added to every token's position.
+    offset: usize,
 }
 
 impl Scanner {
     pub fn scan_tokens(&mut self) -> &mut Scanner {
         while !self.is_at_end() {
             self.start = self.current;
+            self.column = self.start - self.line_start + 1;
             self.scan_token();
         }
 
@@ -40,8 +45,9 @@ impl Scanner {
             token_type: TokenType::Eof,
             lexeme: "".to_string(),
             literal: LoxValue::Nil,
-            position: self.start,
+            position: self.offset + self.start,
             line: self.line,
+            column: self.column,
         };
         self.tokens.push(end);
         self
@@ -51,14 +57,22 @@ impl Scanner {
         self.current >= self.source.len()
     }
 
-    fn scan_token(&mut self) -> () {
+    fn scan_token(&mut self) {
         let c = self.advance();
         match c {
             '(' => self.add_token(TokenType::LeftParen, LoxValue::Nil),
             ')' => self.add_token(TokenType::RightParen, LoxValue::Nil),
             '{' => self.add_token(TokenType::LeftBrace, LoxValue::Nil),
             '}' => self.add_token(TokenType::RightBrace, LoxValue::Nil),
+            '[' => self.add_token(TokenType::LeftBracket, LoxValue::Nil),
+            ']' => self.add_token(TokenType::RightBracket, LoxValue::Nil),
             ',' => self.add_token(TokenType::Comma, LoxValue::Nil),
+            ':' => self.add_token(TokenType::Colon, LoxValue::Nil),
+            '.' if self.peek() == '.' && self.peek_next() == '.' => {
+                self.advance();
+                self.advance();
+                self.add_token(TokenType::Ellipsis, LoxValue::Nil)
+            }
             '.' => self.add_token(TokenType::Dot, LoxValue::Nil),
             '-' => self.add_token(TokenType::Minus, LoxValue::Nil),
             '+' => self.add_token(TokenType::Plus, LoxValue::Nil),
@@ -102,6 +116,8 @@ impl Scanner {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    let comment = self.make_token(TokenType::Comment, LoxValue::Nil);
+                    self.comments.push(comment);
                 } else {
                     self.add_token(TokenType::Slash, LoxValue::Nil)
                 }
@@ -109,7 +125,7 @@ impl Scanner {
             '"' => self.string(),
 
             ' ' | '\r' | '\t' => (),
-            '\n' => self.line += 1,
+            '\n' => self.newline(),
             c if is_digit(c) => self.number(),
             c if is_alpha(c) => {
                 while is_alphanumeric(self.peek()) {
@@ -125,10 +141,10 @@ impl Scanner {
     }
 
     fn current_substring(&self) -> String {
-        self.source.clone()[(self.start)..(self.current)].to_string()
+        self.source[self.start..self.current].iter().collect()
     }
 
-    fn number(&mut self) -> () {
+    fn number(&mut self) {
         while is_digit(self.peek()) {
             self.advance();
         }
@@ -142,56 +158,63 @@ impl Scanner {
             self.advance();
         }
 
-        let value = &self.source.clone()[(self.start)..(self.current)];
-        let num = f64::from_str(value).unwrap();
+        let value = self.current_substring();
+        let num = f64::from_str(&value).unwrap();
         self.add_token(TokenType::Number, LoxValue::Number(num))
     }
 
-    fn string(&mut self) -> () {
+    fn string(&mut self) {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            if self.advance() == '\n' {
+                self.newline();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            eprintln!("Scanner error: unterminated string");
-            process::exit(1);
+            // the parser reports it
+            return self.add_token(TokenType::Unexpected, LoxValue::Nil);
         }
 
         self.advance(); // get the closing '"'
         let start = self.start + 1;
         let current = self.current - 1;
 
-        let value = &self.source.clone()[start..current];
-        self.add_token(TokenType::String, LoxValue::String(value.to_string()));
+        let value = self.source[start..current].iter().collect();
+        self.add_token(TokenType::String, LoxValue::String(value));
+    }
+
+    fn add_token(&mut self, t: TokenType, l: LoxValue) {
+        let token = self.make_token(t, l);
+        self.tokens.push(token);
     }
 
-    fn add_token(&mut self, t: TokenType, l: LoxValue) -> () {
-        let start = self.start;
-        let current = self.current;
-        let lexeme = &self.source[start..current];
-        let token = Token {
+    fn make_token(&self, t: TokenType, l: LoxValue) -> Token {
+        Token {
             token_type: t,
-            position: self.start,
-            lexeme: lexeme.to_string(),
+            position: self.offset + self.start,
+            lexeme: self.current_substring(),
             literal: l,
             line: self.line,
-        };
-        self.tokens.push(token);
+            column: self.column,
+        }
+    }
+
+    /// Called after consuming a '\n'.
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
     }
 
     fn advance(&mut self) -> char {
         self.current += 1;
-        self.source.chars().nth(self.current - 1).unwrap()
+        self.source[self.current - 1]
     }
 
     fn peek(&self) -> char {
         if self.is_at_end() {
             '\0'
         } else {
-            self.source.chars().nth(self.current).unwrap()
+            self.source[self.current]
         }
     }
 
@@ -200,7 +223,7 @@ impl Scanner {
         if pos >= self.source.len() {
             '\0'
         } else {
-            self.source.chars().nth(pos).unwrap()
+            self.source[pos]
         }
     }
 
@@ -208,8 +231,7 @@ impl Scanner {
         if self.is_at_end() {
             return false;
         }
-        let current_char = self.source.chars().nth(self.current).unwrap();
-        if current_char != expected {
+        if self.source[self.current] != expected {
             return false;
         }
 
@@ -218,24 +240,35 @@ impl Scanner {
     }
 
     pub fn new(source: String) -> Scanner {
+        Scanner::with_offset(source, 0, 1)
+    }
+
+    /// Scans `source` as if it began at `position` on `line` of a larger
+    /// text, so that tokens from successive REPL inputs never share a
+    /// position. `source` must start at the beginning of a line.
+    pub fn with_offset(source: String, position: usize, line: usize) -> Scanner {
         Scanner {
-            source: source,
+            source: source.chars().collect(),
             tokens: vec![],
+            comments: vec![],
             start: 0,
             current: 0,
-            line: 1,
+            line,
+            line_start: 0,
+            column: 1,
+            offset: position,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use Scanner;
-    use TokenType;
+    use scanner::Scanner;
+    use token::TokenType;
     fn check_token_type(s: &str, tt: TokenType) {
         let mut scanner = Scanner::new(s.to_string());
         scanner.scan_tokens();
-        let t = scanner.tokens.iter().nth(0).unwrap();
+        let t = scanner.tokens.first().unwrap();
         assert_eq!(t.token_type, tt);
     }
     #[test]
@@ -246,4 +279,23 @@ mod tests {
     fn parses_string() {
         check_token_type("\"cool\"", TokenType::String)
     }
+    #[test]
+    fn keeps_comments_out_of_the_tokens() {
+        let mut scanner = Scanner::new("a; // one\n// two\nb;".to_string());
+        scanner.scan_tokens();
+        assert_eq!(scanner.tokens.len(), 5);
+        let comments: Vec<(&str, usize)> = scanner
+            .comments
+            .iter()
+            .map(|c| (c.lexeme.as_str(), c.line))
+            .collect();
+        assert_eq!(comments, vec![("// one", 1), ("// two", 2)]);
+    }
+    #[test]
+    fn tracks_columns_across_lines() {
+        let mut scanner = Scanner::new("var a =\n  b;".to_string());
+        scanner.scan_tokens();
+        let columns: Vec<(usize, usize)> = scanner.tokens.iter().map(|t| (t.line, t.column)).collect();
+        assert_eq!(columns, vec![(1, 1), (1, 5), (1, 7), (2, 3), (2, 4), (2, 4)]);
+    }
 }
@@ -0,0 +1,217 @@
+use diagnostic::Diagnostic;
+use interpreter::Interpreter;
+use parser::Parser;
+use resolver::Resolver;
+use scanner::Scanner;
+use ast::{Program, Statement};
+use token::{Token, TokenType};
+
+/// An interactive session: one resolver and interpreter that live across
+/// inputs, plus a transcript of everything entered so far.
+///
+/// Each input is scanned as if it were appended to the transcript, so token
+/// positions, and with them the expression IDs the resolver records, never
+/// repeat within a session. Diagnostics are located in the transcript.
+pub struct Session {
+    resolver: Resolver,
+    source: String,
+    line: usize,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            resolver: Resolver::new(Interpreter::new()),
+            source: String::new(),
+            line: 1,
+        }
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.resolver.interpreter.set_max_depth(max_depth);
+    }
+
+    /// Everything entered so far, which is what diagnostics should be
+    /// rendered against.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The session's global bindings and their values, sorted by name. The
+    /// REPL only ever runs at the top level, so these are all there is.
+    pub fn globals(&self) -> Vec<(String, String)> {
+        self.resolver
+            .interpreter
+            .environment
+            .globals()
+            .into_iter()
+            .map(|(name, value)| (name, value.to_string()))
+            .collect()
+    }
+
+    /// Scans, parses, resolves, and runs one input, just as a script file
+    /// would be. An input that is a single expression statement, with or
+    /// without its ';', is evaluated and its value returned for echoing.
+    pub fn run(&mut self, input: &str) -> Result<Option<String>, Vec<Diagnostic>> {
+        // the scanner counts positions in characters, not bytes
+        let position = self.source.chars().count();
+        let mut scanner = Scanner::with_offset(input.to_string(), position, self.line);
+        scanner.scan_tokens();
+
+        self.source.push_str(input);
+        if !input.ends_with('\n') {
+            self.source.push('\n');
+        }
+        self.line += input.lines().count().max(1);
+
+        let tokens = scanner.tokens;
+        let program = match Parser::new(tokens.clone()).parse() {
+            Ok(program) => program,
+            Err(errors) => match with_semicolon(tokens) {
+                Some(program) => program,
+                None => return Err(errors.iter().map(|e| e.diagnostic()).collect()),
+            },
+        };
+        self.resolver
+            .resolve(&program)
+            .map_err(|e| vec![e.diagnostic()])?;
+
+        let interpreter = &mut self.resolver.interpreter;
+        let result = match program.statements[..] {
+            [Statement::Expression { ref expression, .. }] => interpreter
+                .evaluate(expression)
+                .map(|value| Some(value.to_string())),
+            _ => interpreter.interpret(program).map(|_| None),
+        };
+        result.map_err(|e| e.diagnostic().into_iter().collect())
+    }
+}
+
+/// Reparses input whose only problem may be a missing final ';', so that
+/// `1 + 2` works as well as `1 + 2;`.
+fn with_semicolon(mut tokens: Vec<Token>) -> Option<Program> {
+    let eof = tokens.pop()?;
+    match tokens.last() {
+        Some(last) if last.token_type != TokenType::Semicolon
+            && last.token_type != TokenType::RightBrace => {}
+        _ => return None,
+    }
+    tokens.push(Token {
+        token_type: TokenType::Semicolon,
+        lexeme: ";".to_string(),
+        ..eof.clone()
+    });
+    tokens.push(eof);
+    Parser::new(tokens).parse().ok()
+}
+
+/// Whether the REPL has read enough to run: every bracket and string is
+/// closed and the input doesn't end partway through an expression. Comments
+/// are trivia to the scanner, so they can't hide or fake either.
+pub fn is_complete(input: &str) -> bool {
+    let mut scanner = Scanner::new(input.to_string());
+    scanner.scan_tokens();
+    let mut depth = 0;
+    let mut last = None;
+    for token in &scanner.tokens {
+        match token.token_type {
+            TokenType::Eof => break,
+            TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => depth += 1,
+            TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => depth -= 1,
+            TokenType::Unexpected if token.lexeme.starts_with('"') => return false,
+            _ => (),
+        }
+        last = Some(&token.token_type);
+    }
+
+    let dangling = matches!(
+        last,
+        Some(&TokenType::And) | Some(&TokenType::Or) | Some(&TokenType::Else)
+            | Some(&TokenType::Plus) | Some(&TokenType::Minus) | Some(&TokenType::Star)
+            | Some(&TokenType::Slash) | Some(&TokenType::Bang) | Some(&TokenType::BangEqual)
+            | Some(&TokenType::Equal) | Some(&TokenType::EqualEqual) | Some(&TokenType::Less)
+            | Some(&TokenType::LessEqual) | Some(&TokenType::Greater)
+            | Some(&TokenType::GreaterEqual) | Some(&TokenType::Comma) | Some(&TokenType::Dot)
+            | Some(&TokenType::Colon)
+    );
+    depth <= 0 && !dangling
+}
+
+#[cfg(test)]
+mod tests {
+    use session::{is_complete, Session};
+
+    #[test]
+    fn locals_from_earlier_inputs_do_not_shadow_globals() {
+        let mut session = Session::new();
+        // `a = ...` is at the same offset within both blocks
+        assert!(session.run("{ var a = 1; a = a; }").is_ok());
+        assert!(session.run("var a = 2;").is_ok());
+        assert!(session.run("{ var b = 3; a = b; }").is_ok());
+        assert!(session.run("if (a != 3) missing;").is_ok());
+    }
+
+    #[test]
+    fn echoes_bare_expressions_with_or_without_semicolon() {
+        let mut session = Session::new();
+        assert_eq!(session.run("var a = 2;"), Ok(None));
+        assert_eq!(session.run("a * 3"), Ok(Some("6".to_string())));
+        assert_eq!(session.run("a;"), Ok(Some("2".to_string())));
+    }
+
+    #[test]
+    fn traces_runtime_errors_through_calls() {
+        let mut session = Session::new();
+        let input = "fun inner() {\n  return 1 + nil;\n}\nfun outer() {\n  inner();\n}\nouter();";
+        let errors = session.run(input).unwrap_err();
+        let trace: Vec<(&str, usize)> = errors[0].trace.iter().map(|(f, l)| (f.as_str(), *l)).collect();
+        assert_eq!(trace, vec![("inner", 2), ("outer", 5), ("<script>", 7)]);
+        // the stack is empty again afterwards
+        let errors = session.run("nil + 1;").unwrap_err();
+        assert!(errors[0].trace.is_empty());
+    }
+
+    #[test]
+    fn locates_errors_after_non_ascii_input() {
+        let mut session = Session::new();
+        assert!(session.run("var s = \"héllo → wörld\";").is_ok());
+        let errors = session.run("print  -s;").unwrap_err();
+        assert_eq!(errors[0].location(session.source()), (2, 8));
+        let rendered = errors[0].render("repl", session.source());
+        assert!(rendered.ends_with("2 | print  -s;\n  |        ^"), "{}", rendered);
+    }
+
+    #[test]
+    fn counts_caught_error_lines_from_the_start_of_the_session() {
+        let mut session = Session::new();
+        assert!(session.run("var e;").is_ok());
+        let input = "fun f() {\n  return -nil;\n}\ntry { f(); } catch (x) { e = x; }";
+        assert!(session.run(input).is_ok());
+        assert_eq!(session.run("e[\"line\"];"), Ok(Some("3".to_string())));
+        assert_eq!(
+            session.run("e[\"trace\"];"),
+            Ok(Some("[f (line 3), <script> (line 5)]".to_string()))
+        );
+    }
+
+    #[test]
+    fn waits_for_unfinished_input() {
+        assert!(!is_complete("fun f() {"));
+        assert!(!is_complete("print (1 +"));
+        assert!(!is_complete("var s = \"open"));
+        assert!(!is_complete("var a = 1 +"));
+        assert!(!is_complete("true and"));
+        assert!(is_complete("fun f() { return 1; }"));
+        assert!(is_complete("print \"{\"; // ("));
+        assert!(is_complete("1 + 2"));
+        assert!(is_complete("var x = 1; // and"));
+        assert!(is_complete("var x = 1; // trailing +"));
+        assert!(!is_complete("var x = 1 + // more to come"));
+    }
+}
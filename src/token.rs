@@ -7,8 +7,12 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
+    Ellipsis,
     Minus,
     Plus,
     Semicolon,
@@ -25,11 +29,17 @@ pub enum TokenType {
     String,
     Number,
     Identifier,
+    /// Only found in `Scanner::comments`, never in the token stream.
+    Comment,
     Unexpected,
     And,
+    Break,
+    Catch,
     Class,
+    Continue,
     Else,
     False,
+    Finally,
     For,
     Fun,
     If,
@@ -39,38 +49,51 @@ pub enum TokenType {
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 }
 
+pub const KEYWORDS: [(&str, TokenType); 22] = [
+    ("and", TokenType::And),
+    ("break", TokenType::Break),
+    ("catch", TokenType::Catch),
+    ("class", TokenType::Class),
+    ("continue", TokenType::Continue),
+    ("else", TokenType::Else),
+    ("false", TokenType::False),
+    ("finally", TokenType::Finally),
+    ("for", TokenType::For),
+    ("fun", TokenType::Fun),
+    ("if", TokenType::If),
+    ("nil", TokenType::Nil),
+    ("or", TokenType::Or),
+    ("print", TokenType::Print),
+    ("return", TokenType::Return),
+    ("super", TokenType::Super),
+    ("this", TokenType::This),
+    ("throw", TokenType::Throw),
+    ("true", TokenType::True),
+    ("try", TokenType::Try),
+    ("var", TokenType::Var),
+    ("while", TokenType::While),
+];
+
 pub fn get_keyword(s: &str) -> TokenType {
-    match s {
-        "and" => TokenType::And,
-        "class" => TokenType::Class,
-        "else" => TokenType::Else,
-        "false" => TokenType::False,
-        "for" => TokenType::For,
-        "fun" => TokenType::Fun,
-        "if" => TokenType::If,
-        "nil" => TokenType::Nil,
-        "or" => TokenType::Or,
-        "print" => TokenType::Print,
-        "return" => TokenType::Return,
-        "super" => TokenType::Super,
-        "this" => TokenType::This,
-        "true" => TokenType::True,
-        "var" => TokenType::Var,
-        "while" => TokenType::While,
-        _ => TokenType::Identifier,
-    }
+    KEYWORDS
+        .iter()
+        .find(|&&(k, _)| k == s)
+        .map_or(TokenType::Identifier, |(_, t)| t.clone())
 }
 
-
 #[derive(Debug, Clone)]
 pub struct Token {
     pub lexeme: String,
     pub line: usize,
+    /// One-based column of the token's first character.
+    pub column: usize,
     pub position: usize,
     pub literal: LoxValue,
     pub token_type: TokenType,
@@ -0,0 +1,670 @@
+mod natives;
+pub mod value;
+
+use chunk::{Constant, FunctionProto, OpCode, Span};
+use diagnostic::{self, Diagnostic};
+use gc;
+use interpreter::DEFAULT_MAX_DEPTH;
+use lox_map::{LoxMap, MapKey};
+use self::value::{BoundMethod, Class, Closure, Instance, Native, NativeFn, Upvalue, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// Stack index of slot zero, which holds the callee or `this`.
+    base: usize,
+    /// How many arguments the call passed, before any were filled in.
+    passed: usize,
+}
+
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub message: String,
+    pub span: Span,
+    /// Function names and lines, innermost first, if the error was raised
+    /// inside a function.
+    pub trace: Vec<(String, usize)>,
+    /// What `throw` raised, which a `catch` receives as is.
+    pub thrown: Option<Value>,
+}
+
+/// Where to go on an exception: the instruction in `frame` to resume at,
+/// and how tall the stack was when the handler was installed.
+struct Handler {
+    frame: usize,
+    height: usize,
+    ip: usize,
+    finally: bool,
+}
+
+impl RuntimeError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            kind: "runtime error",
+            message: self.message.clone(),
+            position: self.span.position,
+            length: self.span.length,
+            trace: self.trace.clone(),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[line {}] Runtime error: {}", self.span.line, self.message)
+    }
+}
+
+type VmResult<T> = Result<T, RuntimeError>;
+
+/// A stack-based virtual machine that runs code produced by the compiler.
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<Rc<str>, Value>,
+    /// Upvalues that still point into the stack, so closures created in the
+    /// same frame share them.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// How many calls can be active at once, not counting the script.
+    max_depth: usize,
+    handlers: Vec<Handler>,
+    /// The exceptions that `finally` blocks are running for, innermost
+    /// last.
+    unwinding: Vec<RuntimeError>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        let mut vm = Vm {
+            stack: Vec::with_capacity(256),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            handlers: Vec::new(),
+            unwinding: Vec::new(),
+        };
+        vm.define_native("clock", 0, natives::clock);
+        vm.define_native("len", 1, natives::len);
+        vm.define_native("push", 2, natives::push);
+        vm.define_native("pop", 1, natives::pop);
+        vm.define_native("insert", 3, natives::insert);
+        vm.define_native("remove", 2, natives::remove);
+        vm.define_native("slice", 3, natives::slice);
+        vm.define_native("keys", 1, natives::keys);
+        vm.define_native("values", 1, natives::values);
+        vm.define_native("has", 2, natives::has);
+        vm.define_native("delete", 2, natives::delete);
+        vm.define_native("gc", 0, natives::gc);
+        vm.define_native("gcStats", 0, natives::gc_stats);
+        vm
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    fn define_native(&mut self, name: &'static str, arity: usize, function: NativeFn) {
+        let native = Native {
+            name,
+            arity,
+            function,
+        };
+        self.globals
+            .insert(Rc::from(name), Value::Native(Rc::new(native)));
+    }
+
+    pub fn interpret(&mut self, script: Rc<FunctionProto>) -> VmResult<()> {
+        let closure = Rc::new(Closure {
+            proto: script,
+            upvalues: vec![],
+        });
+        self.stack.push(Value::Closure(Rc::clone(&closure)));
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: 0,
+            passed: 0,
+        });
+
+        let result = self.run();
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+            self.handlers.clear();
+            self.unwinding.clear();
+        }
+        result
+    }
+
+    /// Runs until the script returns or an exception goes unhandled.
+    fn run(&mut self) -> VmResult<()> {
+        loop {
+            let error = match self.execute() {
+                Ok(()) => return Ok(()),
+                Err(error) => error,
+            };
+            let handler = match self.handlers.pop() {
+                Some(handler) => handler,
+                None => return Err(error),
+            };
+            self.frames.truncate(handler.frame);
+            self.close_upvalues(handler.height);
+            self.stack.truncate(handler.height);
+            self.frames.last_mut().unwrap().ip = handler.ip;
+            if handler.finally {
+                self.unwinding.push(error);
+            } else {
+                let exception = self.exception(error);
+                self.stack.push(exception);
+            }
+        }
+    }
+
+    /// What a `catch` receives: the thrown value, or for a runtime error a
+    /// map of its message, line and trace.
+    fn exception(&self, error: RuntimeError) -> Value {
+        if let Some(value) = error.thrown {
+            return value;
+        }
+        let trace = error
+            .trace
+            .iter()
+            .map(|(function, line)| Value::String(Rc::from(format!("{} (line {})", function, line))))
+            .collect();
+        let mut map = LoxMap::default();
+        map.insert(MapKey::String("message".to_string()), Value::String(Rc::from(error.message)));
+        map.insert(MapKey::String("line".to_string()), Value::Number(error.span.line as f64));
+        map.insert(MapKey::String("trace".to_string()), Value::list(trace));
+        Value::map(map)
+    }
+
+    fn execute(&mut self) -> VmResult<()> {
+        loop {
+            let op = {
+                let frame = self.frames.last_mut().unwrap();
+                let op = frame.closure.proto.chunk.code[frame.ip];
+                frame.ip += 1;
+                op
+            };
+
+            match op {
+                OpCode::Constant(idx) => {
+                    let value = match self.frame().closure.proto.chunk.constants[idx as usize] {
+                        Constant::Number(n) => Value::Number(n),
+                        Constant::String(ref s) => Value::String(Rc::clone(s)),
+                        Constant::Function(_) => unreachable!("functions are loaded by Closure"),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.stack.pop();
+                }
+
+                OpCode::GetLocal(slot) => {
+                    let value = self.stack[self.frame().base + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal(slot) => {
+                    let idx = self.frame().base + slot as usize;
+                    self.stack[idx] = self.peek(0).clone();
+                }
+
+                OpCode::GetGlobal(idx) => {
+                    let name = self.string_constant(idx);
+                    match self.globals.get(&name) {
+                        Some(value) => {
+                            let value = value.clone();
+                            self.stack.push(value);
+                        }
+                        None => return Err(self.error(&format!("Undefined variable '{}'.", name))),
+                    }
+                }
+                OpCode::DefineGlobal(idx) => {
+                    let name = self.string_constant(idx);
+                    let value = self.stack.pop().unwrap();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal(idx) => {
+                    let name = self.string_constant(idx);
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(&name) {
+                        Some(slot) => *slot = value,
+                        None => return Err(self.error(&format!("Undefined variable '{}'.", name))),
+                    }
+                }
+
+                OpCode::GetUpvalue(idx) => {
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[idx as usize]);
+                    let value = match *upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[slot].clone(),
+                        Upvalue::Closed(ref value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue(idx) => {
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[idx as usize]);
+                    let value = self.peek(0).clone();
+                    let mut upvalue = upvalue.borrow_mut();
+                    match *upvalue {
+                        Upvalue::Open(slot) => self.stack[slot] = value,
+                        Upvalue::Closed(ref mut closed) => *closed = value,
+                    }
+                }
+
+                OpCode::GetProperty(idx) => {
+                    let name = self.string_constant(idx);
+                    let instance = match *self.peek(0) {
+                        Value::Instance(ref instance) => Rc::clone(instance),
+                        _ => return Err(self.error("Only instances have properties.")),
+                    };
+                    let field = instance.fields.borrow().get(&name).cloned();
+                    let value = match field {
+                        Some(value) => value,
+                        None => self.bind_method(&instance.class, &name, Value::Instance(Rc::clone(&instance)))?,
+                    };
+                    self.stack.pop();
+                    self.stack.push(value);
+                }
+                OpCode::SetProperty(idx) => {
+                    let name = self.string_constant(idx);
+                    let instance = match *self.peek(1) {
+                        Value::Instance(ref instance) => Rc::clone(instance),
+                        _ => return Err(self.error("Only instances have fields.")),
+                    };
+                    let value = self.stack.pop().unwrap();
+                    instance.fields.borrow_mut().insert(name, value.clone());
+                    self.stack.pop();
+                    self.stack.push(value);
+                }
+                OpCode::GetIndex => {
+                    let index = self.stack.pop().unwrap();
+                    let value = match self.stack.pop().unwrap() {
+                        Value::List(list) => {
+                            let list = list.borrow();
+                            let idx = natives::list_index(&index, list.len()).map_err(|m| self.error(&m))?;
+                            list[idx].clone()
+                        }
+                        Value::Map(map) => natives::map_get(&map.borrow(), &index).map_err(|m| self.error(&m))?,
+                        _ => return Err(self.error("Only lists and maps can be indexed.")),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetIndex => {
+                    let value = self.stack.pop().unwrap();
+                    let index = self.stack.pop().unwrap();
+                    match self.stack.pop().unwrap() {
+                        Value::List(list) => {
+                            let mut list = list.borrow_mut();
+                            let idx = natives::list_index(&index, list.len()).map_err(|m| self.error(&m))?;
+                            list[idx] = value.clone();
+                        }
+                        Value::Map(map) => {
+                            let key = natives::map_key(&index).map_err(|m| self.error(&m))?;
+                            map.borrow_mut().insert(key, value.clone());
+                        }
+                        _ => return Err(self.error("Only lists and maps can be indexed.")),
+                    }
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper(idx) => {
+                    let name = self.string_constant(idx);
+                    let superclass = match self.stack.pop() {
+                        Some(Value::Class(class)) => class,
+                        _ => unreachable!("'super' always holds a class"),
+                    };
+                    let receiver = self.stack.pop().unwrap();
+                    let bound = self.bind_method(&superclass, &name, receiver)?;
+                    self.stack.push(bound);
+                }
+
+                OpCode::Equal => {
+                    let b = self.stack.pop().unwrap();
+                    let a = self.stack.pop().unwrap();
+                    self.stack.push(Value::Bool(a == b));
+                }
+                OpCode::Greater => self.binary_number_op(|a, b| Value::Bool(a > b))?,
+                OpCode::GreaterEqual => self.binary_number_op(|a, b| Value::Bool(a >= b))?,
+                OpCode::Less => self.binary_number_op(|a, b| Value::Bool(a < b))?,
+                OpCode::LessEqual => self.binary_number_op(|a, b| Value::Bool(a <= b))?,
+                OpCode::Subtract => self.binary_number_op(|a, b| Value::Number(a - b))?,
+                OpCode::Multiply => self.binary_number_op(|a, b| Value::Number(a * b))?,
+                OpCode::Divide => self.binary_number_op(|a, b| Value::Number(a / b))?,
+                OpCode::Add => {
+                    let len = self.stack.len();
+                    let result = match (&self.stack[len - 2], &self.stack[len - 1]) {
+                        (&Value::Number(a), &Value::Number(b)) => Value::Number(a + b),
+                        (Value::String(a), Value::String(b)) => {
+                            let mut s = String::with_capacity(a.len() + b.len());
+                            s.push_str(a);
+                            s.push_str(b);
+                            Value::String(Rc::from(s))
+                        }
+                        _ => return Err(self.error("Operands must be two numbers or two strings.")),
+                    };
+                    self.stack.truncate(len - 2);
+                    self.stack.push(result);
+                }
+                OpCode::Not => {
+                    let value = self.stack.pop().unwrap();
+                    self.stack.push(Value::Bool(!value.is_truthy()));
+                }
+                OpCode::Negate => match self.stack.pop() {
+                    Some(Value::Number(n)) => self.stack.push(Value::Number(-n)),
+                    _ => return Err(self.error("Operand must be a number.")),
+                },
+
+                OpCode::Print => println!("{}", self.stack.pop().unwrap()),
+
+                OpCode::Jump(offset) => self.frames.last_mut().unwrap().ip += offset as usize,
+                OpCode::JumpIfFalse(offset) => {
+                    if !self.peek(0).is_truthy() {
+                        self.frames.last_mut().unwrap().ip += offset as usize;
+                    }
+                }
+                OpCode::JumpIfPassed(parameter, offset) => {
+                    let frame = self.frames.last_mut().unwrap();
+                    if frame.passed > parameter as usize {
+                        frame.ip += offset as usize;
+                    }
+                }
+                OpCode::Loop(offset) => {
+                    self.frames.last_mut().unwrap().ip -= offset as usize;
+                    gc::maybe_collect();
+                }
+
+                OpCode::Call(arg_count) => {
+                    let arg_count = arg_count as usize;
+                    let callee = self.peek(arg_count).clone();
+                    self.call_value(callee, arg_count)?;
+                }
+                OpCode::Closure(idx) => {
+                    let proto = match self.frame().closure.proto.chunk.constants[idx as usize] {
+                        Constant::Function(ref proto) => Rc::clone(proto),
+                        _ => unreachable!("Closure always refers to a function constant"),
+                    };
+                    let base = self.frame().base;
+                    let mut upvalues = Vec::with_capacity(proto.upvalues.len());
+                    for descriptor in &proto.upvalues {
+                        let upvalue = if descriptor.is_local {
+                            self.capture_upvalue(base + descriptor.index as usize)
+                        } else {
+                            Rc::clone(&self.frame().closure.upvalues[descriptor.index as usize])
+                        };
+                        upvalues.push(upvalue);
+                    }
+                    self.stack
+                        .push(Value::Closure(Rc::new(Closure { proto, upvalues })));
+                }
+                OpCode::CloseUpvalue => {
+                    let last = self.stack.len() - 1;
+                    self.close_upvalues(last);
+                    self.stack.pop();
+                }
+                OpCode::Try(offset) | OpCode::TryFinally(offset) => {
+                    let handler = Handler {
+                        frame: self.frames.len(),
+                        height: self.stack.len(),
+                        ip: self.frame().ip + offset as usize,
+                        finally: matches!(op, OpCode::TryFinally(_)),
+                    };
+                    self.handlers.push(handler);
+                }
+                OpCode::EndTry => {
+                    self.handlers.pop();
+                }
+                OpCode::Throw => {
+                    let value = self.stack.pop().unwrap();
+                    let mut error = self.error(&diagnostic::uncaught_message(&value, value.message()));
+                    error.thrown = Some(value);
+                    return Err(error);
+                }
+                OpCode::Rethrow => return Err(self.unwinding.pop().unwrap()),
+                OpCode::DropException => {
+                    self.unwinding.pop();
+                }
+
+                OpCode::Return => {
+                    let result = self.stack.pop().unwrap();
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                }
+
+                OpCode::Class(idx) => {
+                    let class = Class {
+                        name: self.string_constant(idx),
+                        methods: RefCell::new(HashMap::new()),
+                    };
+                    let class = Rc::new(class);
+                    gc::track(&class);
+                    self.stack.push(Value::Class(class));
+                }
+                OpCode::Inherit => {
+                    let superclass = match *self.peek(1) {
+                        Value::Class(ref class) => Rc::clone(class),
+                        _ => return Err(self.error("Superclass must be a class.")),
+                    };
+                    if let Value::Class(ref subclass) = *self.peek(0) {
+                        let inherited = superclass.methods.borrow();
+                        subclass
+                            .methods
+                            .borrow_mut()
+                            .extend(inherited.iter().map(|(k, v)| (Rc::clone(k), Rc::clone(v))));
+                    }
+                    self.stack.pop();
+                }
+                OpCode::Method(idx) => {
+                    let name = self.string_constant(idx);
+                    let method = match self.stack.pop() {
+                        Some(Value::Closure(closure)) => closure,
+                        _ => unreachable!("methods are always closures"),
+                    };
+                    if let Value::Class(ref class) = *self.peek(0) {
+                        class.methods.borrow_mut().insert(name, method);
+                    }
+                }
+                OpCode::BuildList(count) => {
+                    let start = self.stack.len() - count as usize;
+                    let elements = self.stack.split_off(start);
+                    self.stack.push(Value::list(elements));
+                }
+                OpCode::BuildMap(count) => {
+                    let start = self.stack.len() - 2 * count as usize;
+                    let mut map = LoxMap::default();
+                    for pair in self.stack[start..].chunks(2) {
+                        let key = natives::map_key(&pair[0]).map_err(|m| self.error(&m))?;
+                        map.insert(key, pair[1].clone());
+                    }
+                    self.stack.truncate(start);
+                    self.stack.push(Value::map(map));
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> VmResult<()> {
+        let callee_slot = self.stack.len() - arg_count - 1;
+        match callee {
+            Value::Closure(closure) => self.call(closure, arg_count),
+            Value::BoundMethod(bound) => {
+                self.stack[callee_slot] = bound.receiver.clone();
+                self.call(Rc::clone(&bound.method), arg_count)
+            }
+            Value::Class(class) => {
+                let instance = Instance {
+                    class: Rc::clone(&class),
+                    fields: RefCell::new(HashMap::new()),
+                };
+                let instance = Rc::new(instance);
+                gc::track(&instance);
+                self.stack[callee_slot] = Value::Instance(instance);
+                let initializer = class.methods.borrow().get("init").cloned();
+                match initializer {
+                    Some(initializer) => self.call(initializer, arg_count),
+                    None if arg_count != 0 => Err(self.error(&format!(
+                        "Expected 0 arguments but got {}.",
+                        arg_count
+                    ))),
+                    None => Ok(()),
+                }
+            }
+            Value::Native(native) => {
+                if arg_count != native.arity {
+                    return Err(self.error(&format!(
+                        "Expected {} arguments but got {}.",
+                        native.arity, arg_count
+                    )));
+                }
+                let result = (native.function)(&self.stack[callee_slot + 1..])
+                    .map_err(|message| self.error(&message))?;
+                self.stack.truncate(callee_slot);
+                self.stack.push(result);
+                Ok(())
+            }
+            _ => Err(self.error("Expression is not callable")),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, arg_count: usize) -> VmResult<()> {
+        let arity = closure.proto.arity;
+        if !arity.accepts(arg_count) {
+            return Err(self.error(&format!(
+                "Expected {} arguments but got {}.",
+                arity, arg_count
+            )));
+        }
+        gc::maybe_collect();
+        // the script's own frame is at the bottom
+        if self.frames.len() > self.max_depth {
+            return Err(self.error("Stack overflow."));
+        }
+        // missing arguments start as nil until their defaults are computed,
+        // and extra ones are gathered into a list for the rest parameter
+        for _ in arg_count..arity.max {
+            self.stack.push(Value::Nil);
+        }
+        if arity.variadic {
+            let extra = self.stack.len() - arg_count.saturating_sub(arity.max);
+            let rest = self.stack.split_off(extra);
+            self.stack.push(Value::list(rest));
+        }
+        let slots = arity.max + arity.variadic as usize;
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - slots - 1,
+            passed: arg_count,
+        });
+        Ok(())
+    }
+
+    fn bind_method(&self, class: &Class, name: &Rc<str>, receiver: Value) -> VmResult<Value> {
+        match class.methods.borrow().get(name) {
+            Some(method) => Ok(Value::BoundMethod(Rc::new(BoundMethod {
+                receiver,
+                method: Rc::clone(method),
+            }))),
+            None => Err(self.error(&format!("Undefined property '{}'.", name))),
+        }
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        for upvalue in &self.open_upvalues {
+            if let Upvalue::Open(open_slot) = *upvalue.borrow() {
+                if open_slot == slot {
+                    return Rc::clone(upvalue);
+                }
+            }
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        gc::track(&upvalue);
+        self.open_upvalues.push(Rc::clone(&upvalue));
+        upvalue
+    }
+
+    /// Moves every open upvalue at or above `last` off the stack and into
+    /// the upvalue itself.
+    fn close_upvalues(&mut self, last: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) => slot,
+                Upvalue::Closed(_) => return false,
+            };
+            if slot >= last {
+                *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    fn binary_number_op(&mut self, op: fn(f64, f64) -> Value) -> VmResult<()> {
+        let len = self.stack.len();
+        match (&self.stack[len - 2], &self.stack[len - 1]) {
+            (&Value::Number(a), &Value::Number(b)) => {
+                self.stack.truncate(len - 2);
+                self.stack.push(op(a, b));
+                Ok(())
+            }
+            _ => Err(self.error("Operands must be numbers.")),
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn string_constant(&self, idx: u16) -> Rc<str> {
+        match self.frame().closure.proto.chunk.constants[idx as usize] {
+            Constant::String(ref s) => Rc::clone(s),
+            _ => unreachable!("names are always string constants"),
+        }
+    }
+
+    fn error(&self, message: &str) -> RuntimeError {
+        let frame = self.frame();
+        let mut trace = vec![];
+        if self.frames.len() > 1 {
+            for (i, frame) in self.frames.iter().enumerate().rev() {
+                let name = match i {
+                    0 => "<script>",
+                    _ => &frame.closure.proto.name,
+                };
+                let line = frame.closure.proto.chunk.span_at(frame.ip - 1).line;
+                trace.push((name.to_string(), line));
+            }
+        }
+        RuntimeError {
+            message: message.to_string(),
+            span: frame.closure.proto.chunk.span_at(frame.ip - 1),
+            trace,
+            thrown: None,
+        }
+    }
+}
+
@@ -0,0 +1,126 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use gc;
+use lox_list;
+use lox_map::{self, LoxMap, MapKey};
+use vm::value::Value;
+
+pub fn clock(_arguments: &[Value]) -> Result<Value, String> {
+    let t = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    Ok(Value::Number(t as f64))
+}
+
+type List = Rc<RefCell<Vec<Value>>>;
+
+fn as_list(value: &Value, function: &str) -> Result<List, String> {
+    match *value {
+        Value::List(ref list) => Ok(Rc::clone(list)),
+        _ => Err(lox_list::expected(function)),
+    }
+}
+
+pub fn list_index(index: &Value, len: usize) -> Result<usize, String> {
+    lox_list::index(index.as_number(), len)
+}
+
+pub fn len(arguments: &[Value]) -> Result<Value, String> {
+    match arguments[0] {
+        Value::List(ref list) => Ok(Value::Number(list.borrow().len() as f64)),
+        Value::Map(ref map) => Ok(Value::Number(map.borrow().len() as f64)),
+        Value::String(ref s) => Ok(Value::Number(s.chars().count() as f64)),
+        _ => Err("len() expects a list, map, or string.".to_string()),
+    }
+}
+
+pub fn push(arguments: &[Value]) -> Result<Value, String> {
+    let list = as_list(&arguments[0], "push")?;
+    list.borrow_mut().push(arguments[1].clone());
+    Ok(Value::Nil)
+}
+
+pub fn pop(arguments: &[Value]) -> Result<Value, String> {
+    let list = as_list(&arguments[0], "pop")?;
+    let popped = lox_list::pop(&mut list.borrow_mut());
+    popped
+}
+
+pub fn insert(arguments: &[Value]) -> Result<Value, String> {
+    let list = as_list(&arguments[0], "insert")?;
+    lox_list::insert(&mut list.borrow_mut(), arguments[1].as_number(), arguments[2].clone())?;
+    Ok(Value::Nil)
+}
+
+pub fn remove(arguments: &[Value]) -> Result<Value, String> {
+    let list = as_list(&arguments[0], "remove")?;
+    let removed = lox_list::remove(&mut list.borrow_mut(), arguments[1].as_number());
+    removed
+}
+
+pub fn slice(arguments: &[Value]) -> Result<Value, String> {
+    let list = as_list(&arguments[0], "slice")?;
+    let sliced = lox_list::slice(&list.borrow(), arguments[1].as_number(), arguments[2].as_number())?;
+    Ok(Value::list(sliced))
+}
+
+type Map = Rc<RefCell<LoxMap<Value>>>;
+
+fn as_map(value: &Value, function: &str) -> Result<Map, String> {
+    match *value {
+        Value::Map(ref map) => Ok(Rc::clone(map)),
+        _ => Err(lox_map::expected(function)),
+    }
+}
+
+pub fn map_key(key: &Value) -> Result<MapKey, String> {
+    lox_map::valid(key.to_key())
+}
+
+pub fn map_get(map: &LoxMap<Value>, key: &Value) -> Result<Value, String> {
+    map.lookup(key.to_key()).cloned()
+}
+
+pub fn keys(arguments: &[Value]) -> Result<Value, String> {
+    let map = as_map(&arguments[0], "keys")?;
+    let keys = map.borrow().iter().map(|(k, _)| Value::from_key(k)).collect();
+    Ok(Value::list(keys))
+}
+
+pub fn values(arguments: &[Value]) -> Result<Value, String> {
+    let map = as_map(&arguments[0], "values")?;
+    let values = map.borrow().iter().map(|(_, v)| v.clone()).collect();
+    Ok(Value::list(values))
+}
+
+pub fn has(arguments: &[Value]) -> Result<Value, String> {
+    let map = as_map(&arguments[0], "has")?;
+    let found = map.borrow().has(arguments[1].to_key())?;
+    Ok(Value::Bool(found))
+}
+
+pub fn delete(arguments: &[Value]) -> Result<Value, String> {
+    let map = as_map(&arguments[0], "delete")?;
+    let removed = map.borrow_mut().delete(arguments[1].to_key())?;
+    Ok(Value::Bool(removed))
+}
+
+/// Runs a collection and returns how many objects it freed.
+pub fn gc(_arguments: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(gc::collect() as f64))
+}
+
+pub fn gc_stats(_arguments: &[Value]) -> Result<Value, String> {
+    let stats = gc::stats();
+    let mut map = LoxMap::default();
+    for &(key, value) in &[
+        ("collections", stats.collections),
+        ("collected", stats.collected),
+        ("tracked", stats.tracked),
+    ] {
+        map.insert(MapKey::String(key.to_string()), Value::Number(value as f64));
+    }
+    Ok(Value::map(map))
+}
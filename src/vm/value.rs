@@ -0,0 +1,292 @@
+use chunk::FunctionProto;
+use cycle;
+use lox_map::{LoxMap, MapKey};
+use gc::{self, Trace, Tracer};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// The VM's runtime value. Mirrors `LoxValue`, but callables are the VM's own
+/// closures and natives rather than `LoxCallable` trait objects.
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Rc<str>),
+    Native(Rc<Native>),
+    Closure(Rc<Closure>),
+    BoundMethod(Rc<BoundMethod>),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<LoxMap<Value>>>),
+}
+
+pub type NativeFn = fn(&[Value]) -> Result<Value, String>;
+
+pub struct Native {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+/// A captured variable. It points at a stack slot while the variable's frame
+/// is live, and owns the value once that frame returns.
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+pub struct Closure {
+    pub proto: Rc<FunctionProto>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Rc<Closure>,
+}
+
+pub struct Class {
+    pub name: Rc<str>,
+    pub methods: RefCell<HashMap<Rc<str>, Rc<Closure>>>,
+}
+
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub fields: RefCell<HashMap<Rc<str>, Value>>,
+}
+
+impl Value {
+    pub fn list(values: Vec<Value>) -> Value {
+        let list = Rc::new(RefCell::new(values));
+        gc::track(&list);
+        Value::List(list)
+    }
+
+    pub fn map(map: LoxMap<Value>) -> Value {
+        let map = Rc::new(RefCell::new(map));
+        gc::track(&map);
+        Value::Map(map)
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        match *self {
+            Value::Nil => false,
+            Value::Bool(b) => b,
+            _ => true,
+        }
+    }
+
+    /// Returns `None` for values that cannot be used as map keys.
+    pub fn to_key(&self) -> Option<MapKey> {
+        match *self {
+            Value::String(ref s) => Some(MapKey::String(s.to_string())),
+            Value::Number(n) => Some(MapKey::number(n)),
+            Value::Bool(b) => Some(MapKey::Bool(b)),
+            Value::Nil => Some(MapKey::Nil),
+            _ => None,
+        }
+    }
+
+    pub fn from_key(key: &MapKey) -> Value {
+        match *key {
+            MapKey::String(ref s) => Value::String(Rc::from(s.as_str())),
+            MapKey::Number(bits) => Value::Number(f64::from_bits(bits)),
+            MapKey::Bool(b) => Value::Bool(b),
+            MapKey::Nil => Value::Nil,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match *self {
+            Value::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// The `message` of a thrown map, if it has a string one.
+    pub fn message(&self) -> Option<String> {
+        if let Value::Map(ref map) = *self {
+            if let Some(Value::String(s)) = map.borrow().get(&MapKey::String("message".to_string())) {
+                return Some(s.to_string());
+            }
+        }
+        None
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::BoundMethod(a), Value::BoundMethod(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Value {
+    fn trace(&self, tracer: &mut Tracer) {
+        match *self {
+            Value::Native(ref native) => tracer.edge(native),
+            Value::Closure(ref closure) => tracer.edge(closure),
+            Value::BoundMethod(ref bound) => tracer.edge(bound),
+            Value::Class(ref class) => tracer.edge(class),
+            Value::Instance(ref instance) => tracer.edge(instance),
+            Value::List(ref list) => tracer.edge(list),
+            Value::Map(ref map) => tracer.edge(map),
+            Value::Nil | Value::Bool(_) | Value::Number(_) | Value::String(_) => (),
+        }
+    }
+}
+
+impl Trace for Native {
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
+
+impl Trace for Closure {
+    fn trace(&self, tracer: &mut Tracer) {
+        for upvalue in &self.upvalues {
+            tracer.edge(upvalue);
+        }
+    }
+}
+
+/// An open upvalue's variable is on the stack, which is a root anyway.
+impl Trace for RefCell<Upvalue> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Upvalue::Closed(ref value) = *self.borrow() {
+            value.trace(tracer);
+        }
+    }
+
+    fn clear(&self) {
+        *self.borrow_mut() = Upvalue::Closed(Value::Nil);
+    }
+}
+
+impl Trace for BoundMethod {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.receiver.trace(tracer);
+        tracer.edge(&self.method);
+    }
+}
+
+impl Trace for Class {
+    fn trace(&self, tracer: &mut Tracer) {
+        for method in self.methods.borrow().values() {
+            tracer.edge(method);
+        }
+    }
+
+    fn clear(&self) {
+        self.methods.borrow_mut().clear();
+    }
+}
+
+impl Trace for Instance {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.edge(&self.class);
+        for value in self.fields.borrow().values() {
+            value.trace(tracer);
+        }
+    }
+
+    fn clear(&self) {
+        self.fields.borrow_mut().clear();
+    }
+}
+
+impl Trace for RefCell<Vec<Value>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for value in self.borrow().iter() {
+            value.trace(tracer);
+        }
+    }
+
+    fn clear(&self) {
+        self.borrow_mut().clear();
+    }
+}
+
+impl Trace for RefCell<LoxMap<Value>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for (_, value) in self.borrow().iter() {
+            value.trace(tracer);
+        }
+    }
+
+    fn clear(&self) {
+        self.borrow_mut().clear();
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// Prints values exactly the way the tree-walking interpreter does.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(ref s) => write!(f, "{}", s),
+            Value::Native(ref native) => write!(f, "<fn {}>", native.name),
+            Value::Closure(ref closure) => write!(f, "<fn {}>", closure.proto.name),
+            Value::BoundMethod(ref bound) => write!(f, "<fn {}>", bound.method.proto.name),
+            Value::Class(ref class) => write!(f, "{}", class.name),
+            Value::Instance(ref instance) => write!(f, "{} instance", instance.class.name),
+            Value::List(ref list) => cycle::print_once(Rc::as_ptr(list), f, "[...]", |f| {
+                write!(f, "[")?;
+                for (i, value) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }),
+            Value::Map(ref map) => write!(f, "{}", map.borrow()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lox_map::{LoxMap, MapKey};
+    use vm::value::Value;
+
+    #[test]
+    fn prints_a_list_that_holds_itself() {
+        let list = Value::list(vec![Value::Number(1.0)]);
+        if let Value::List(ref l) = list {
+            l.borrow_mut().push(list.clone());
+        }
+        assert_eq!(list.to_string(), "[1, [...]]");
+    }
+
+    #[test]
+    fn prints_a_map_that_holds_itself() {
+        let map = Value::map(LoxMap::default());
+        if let Value::Map(ref m) = map {
+            m.borrow_mut().insert(MapKey::String("self".to_string()), map.clone());
+        }
+        assert_eq!(map.to_string(), "{self: {...}}");
+    }
+}
@@ -0,0 +1,37 @@
+extern crate lox;
+
+use lox::ast::AstPrint;
+use lox::parser::Parser;
+use lox::scanner::Scanner;
+use std::fs;
+use std::path::Path;
+
+/// Parses every `tests/ast/*.lox` file and compares the printed tree with the
+/// `.ast` file next to it. Set `UPDATE_GOLDEN=1` to rewrite the expectations.
+#[test]
+fn parser_output_matches_golden_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/ast");
+    let mut checked = 0;
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "lox") {
+            continue;
+        }
+        let source = fs::read_to_string(&path).unwrap();
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens();
+        let program = Parser::new(scanner.tokens)
+            .parse()
+            .unwrap_or_else(|errors| panic!("{}: {}", path.display(), errors[0]));
+        let actual = program.pretty_print() + "\n";
+
+        let golden = path.with_extension("ast");
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&golden, &actual).unwrap();
+        }
+        let expected = fs::read_to_string(&golden).unwrap_or_default();
+        assert_eq!(actual, expected, "{}", golden.display());
+        checked += 1;
+    }
+    assert!(checked > 0);
+}
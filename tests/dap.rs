@@ -0,0 +1,170 @@
+extern crate serde_json;
+
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+/// Drives `lox dap` the way an editor would.
+struct Client {
+    adapter: Child,
+    output: BufReader<ChildStdout>,
+    seq: u64,
+    /// Messages read while waiting for something else.
+    pending: Vec<Value>,
+}
+
+impl Client {
+    fn start() -> Client {
+        let mut adapter = Command::new(env!("CARGO_BIN_EXE_lox"))
+            .arg("dap")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let output = BufReader::new(adapter.stdout.take().unwrap());
+        Client {
+            adapter,
+            output,
+            seq: 0,
+            pending: vec![],
+        }
+    }
+
+    fn read(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            self.output.read_line(&mut header).unwrap();
+            match header.trim_end() {
+                "" => break,
+                h => length = h["Content-Length:".len()..].trim().parse().unwrap(),
+            }
+        }
+        let mut body = vec![0; length];
+        self.output.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// The first message, pending or new, that `matches` accepts.
+    fn wait_for<F: Fn(&Value) -> bool>(&mut self, matches: F) -> Value {
+        if let Some(i) = self.pending.iter().position(&matches) {
+            return self.pending.remove(i);
+        }
+        loop {
+            let message = self.read();
+            if matches(&message) {
+                return message;
+            }
+            self.pending.push(message);
+        }
+    }
+
+    fn event(&mut self, event: &str) -> Value {
+        self.wait_for(|m| m["type"] == "event" && m["event"] == event)
+    }
+
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        self.seq += 1;
+        let seq = self.seq;
+        let body = json!({"seq": seq, "type": "request", "command": command, "arguments": arguments})
+            .to_string();
+        let stdin = self.adapter.stdin.as_mut().unwrap();
+        write!(stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        stdin.flush().unwrap();
+        let response = self.wait_for(|m| m["type"] == "response" && m["request_seq"] == seq);
+        assert_eq!(response["success"], true, "{}", response);
+        response["body"].clone()
+    }
+}
+
+fn names_and_values(variables: &Value) -> Vec<(String, String)> {
+    variables["variables"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| (v["name"].as_str().unwrap().to_string(), v["value"].as_str().unwrap().to_string()))
+        .collect()
+}
+
+#[test]
+fn debugs_a_scripted_session() {
+    let path = env::temp_dir().join(format!("lox-dap-{}.lox", std::process::id()));
+    let source = "fun makePoint(x, y) {\n  var p = [x, y];\n  return p;\n}\nvar a = makePoint(1, 2);\nprint a;\n";
+    fs::write(&path, source).unwrap();
+
+    let mut client = Client::start();
+    client.request("initialize", json!({"adapterID": "lox"}));
+    client.event("initialized");
+    client.request("launch", json!({"program": path}));
+    let set = client.request(
+        "setBreakpoints",
+        json!({"source": {"path": path}, "breakpoints": [{"line": 3}, {"line": 4}]}),
+    );
+    assert_eq!(set["breakpoints"][0]["verified"], true);
+    assert_eq!(set["breakpoints"][1]["verified"], false);
+    client.request("configurationDone", json!({}));
+
+    let stopped = client.event("stopped");
+    assert_eq!(stopped["body"]["reason"], "breakpoint");
+    let stack = client.request("stackTrace", json!({"threadId": 1}));
+    let frames: Vec<(&str, u64)> = stack["stackFrames"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| (f["name"].as_str().unwrap(), f["line"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(frames, vec![("makePoint", 3), ("<script>", 5)]);
+
+    let frame = stack["stackFrames"][0]["id"].clone();
+    let scopes = client.request("scopes", json!({ "frameId": frame }));
+    assert_eq!(scopes["scopes"][0]["name"], "Locals");
+    let reference = scopes["scopes"][0]["variablesReference"].clone();
+    let locals = client.request("variables", json!({ "variablesReference": reference }));
+    let expected = vec![("x", "1"), ("y", "2"), ("p", "[1, 2]")];
+    let expected: Vec<(String, String)> = expected
+        .into_iter()
+        .map(|(n, v)| (n.to_string(), v.to_string()))
+        .collect();
+    assert_eq!(names_and_values(&locals), expected);
+
+    let sum = client.request("evaluate", json!({"expression": "x + y", "frameId": frame}));
+    assert_eq!(sum["result"], "3");
+
+    client.request("next", json!({"threadId": 1}));
+    let stopped = client.event("stopped");
+    assert_eq!(stopped["body"]["reason"], "step");
+    let stack = client.request("stackTrace", json!({"threadId": 1}));
+    assert_eq!(stack["stackFrames"][0]["line"], 6);
+
+    client.request("continue", json!({"threadId": 1}));
+    let output = client.event("output");
+    assert_eq!(output["body"]["output"], "[1, 2]\n");
+    assert_eq!(client.event("exited")["body"]["exitCode"], 0);
+    client.event("terminated");
+    client.request("disconnect", json!({}));
+    assert!(client.adapter.wait().unwrap().success());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn reports_runaway_recursion_as_a_stack_overflow() {
+    let path = env::temp_dir().join(format!("lox-dap-recursion-{}.lox", std::process::id()));
+    fs::write(&path, "fun f(n) {\n  return f(n + 1);\n}\nf(0);\n").unwrap();
+
+    let mut client = Client::start();
+    client.request("initialize", json!({"adapterID": "lox"}));
+    client.request("launch", json!({"program": path}));
+    client.request("configurationDone", json!({}));
+
+    let output = client.event("output");
+    assert_eq!(output["body"]["category"], "stderr");
+    let text = output["body"]["output"].as_str().unwrap();
+    assert!(text.contains("Stack overflow."), "{}", text);
+    assert_eq!(client.event("exited")["body"]["exitCode"], 70);
+    client.event("terminated");
+    client.request("disconnect", json!({}));
+    assert!(client.adapter.wait().unwrap().success());
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,132 @@
+extern crate serde_json;
+
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+/// Drives `lox lsp` the way an editor would.
+struct Client {
+    server: Child,
+    output: BufReader<ChildStdout>,
+    next_id: u64,
+}
+
+impl Client {
+    fn start() -> Client {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_lox"))
+            .arg("lsp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let output = BufReader::new(server.stdout.take().unwrap());
+        Client {
+            server,
+            output,
+            next_id: 0,
+        }
+    }
+
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        let stdin = self.server.stdin.as_mut().unwrap();
+        write!(stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        stdin.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            self.output.read_line(&mut header).unwrap();
+            match header.trim_end() {
+                "" => break,
+                h => length = h["Content-Length:".len()..].trim().parse().unwrap(),
+            }
+        }
+        let mut body = vec![0; length];
+        self.output.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({"jsonrpc": "2.0", "method": method, "params": params}));
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}));
+        let response = self.receive();
+        assert_eq!(response["id"], id);
+        response["result"].clone()
+    }
+}
+
+const URI: &str = "file:///point.lox";
+
+fn at(line: u64, character: u64) -> Value {
+    json!({"textDocument": {"uri": URI}, "position": {"line": line, "character": character}})
+}
+
+#[test]
+fn answers_a_scripted_session() {
+    let mut client = Client::start();
+    let capabilities = client.request("initialize", json!({"capabilities": {}}));
+    assert_eq!(capabilities["capabilities"]["hoverProvider"], true);
+    client.notify("initialized", json!({}));
+
+    let text = "fun makePoint(x, y) {\n  return [x, y];\n}\nvar p = makePoint(1, 2);\nprint p\n";
+    client.notify(
+        "textDocument/didOpen",
+        json!({"textDocument": {"uri": URI, "languageId": "lox", "version": 1, "text": text}}),
+    );
+    let published = client.receive();
+    assert_eq!(published["method"], "textDocument/publishDiagnostics");
+    let diagnostics = &published["params"]["diagnostics"];
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 4);
+
+    let fixed = text.replace("print p\n", "print p;\n");
+    client.notify(
+        "textDocument/didChange",
+        json!({"textDocument": {"uri": URI, "version": 2}, "contentChanges": [{"text": fixed}]}),
+    );
+    let published = client.receive();
+    assert_eq!(published["params"]["diagnostics"], json!([]));
+
+    let definition = client.request("textDocument/definition", at(1, 11));
+    assert_eq!(definition["range"]["start"], json!({"line": 0, "character": 14}));
+
+    let hover = client.request("textDocument/hover", at(3, 10));
+    let markdown = hover["contents"]["value"].as_str().unwrap();
+    assert!(markdown.contains("fun makePoint(x, y)"), "{}", markdown);
+    assert!(markdown.contains("Takes 2 arguments."), "{}", markdown);
+
+    let mut references = at(4, 6);
+    references["context"] = json!({"includeDeclaration": true});
+    let references = client.request("textDocument/references", references);
+    let lines: Vec<&Value> = references
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|l| &l["range"]["start"]["line"])
+        .collect();
+    assert_eq!(lines, vec![3, 4]);
+
+    let symbols = client.request("textDocument/documentSymbol", json!({"textDocument": {"uri": URI}}));
+    assert_eq!(symbols[0]["name"], "makePoint");
+
+    let completions = client.request("textDocument/completion", at(4, 0));
+    let labels: Vec<&str> = completions
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["label"].as_str().unwrap())
+        .collect();
+    assert!(labels.contains(&"while") && labels.contains(&"makePoint") && labels.contains(&"len"));
+
+    assert_eq!(client.request("shutdown", Value::Null), Value::Null);
+    client.notify("exit", Value::Null);
+    assert!(client.server.wait().unwrap().success());
+}
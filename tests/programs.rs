@@ -0,0 +1,43 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Runs every `tests/programs/*.lox` file on both backends and compares
+/// what it prints, followed by its exit code if that isn't 0, with the
+/// `.out` file next to it. Both backends must match the same expectation.
+/// Set `UPDATE_GOLDEN=1` to rewrite the expectations from the tree walker.
+#[test]
+fn programs_behave_the_same_on_both_backends() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut checked = 0;
+    for entry in fs::read_dir(root.join("tests/programs")).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "lox") {
+            continue;
+        }
+        let relative = path.strip_prefix(root).unwrap();
+        let golden = path.with_extension("out");
+        for backend in ["tree", "vm"] {
+            let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+                .arg(format!("--backend={}", backend))
+                .arg(relative)
+                .current_dir(root)
+                .output()
+                .unwrap();
+            let mut actual = String::from_utf8(output.stdout).unwrap();
+            actual += &String::from_utf8(output.stderr).unwrap();
+            match output.status.code() {
+                Some(0) => (),
+                code => actual += &format!("[exit {:?}]\n", code),
+            }
+
+            if backend == "tree" && std::env::var_os("UPDATE_GOLDEN").is_some() {
+                fs::write(&golden, &actual).unwrap();
+            }
+            let expected = fs::read_to_string(&golden).unwrap_or_default();
+            assert_eq!(actual, expected, "{} on {}", golden.display(), backend);
+        }
+        checked += 1;
+    }
+    assert!(checked > 0);
+}